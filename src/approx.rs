@@ -0,0 +1,168 @@
+use float_cmp::{approx_eq, F64Margin};
+use num_complex::Complex64;
+
+/// How far apart two kets, state vectors, gates or density matrices can be, and still compare
+/// as equal with `float_cmp::ApproxEq`. Works with the `approx_eq!` macro, for example
+/// `approx_eq!(Ket, a, b, epsilon = 1e-12, up_to_global_phase = true)`.
+///
+/// Two complex numbers are close enough when any one of these holds, just like `F64Margin`
+/// does for real numbers:
+///
+/// * `epsilon`: the absolute difference |a - b| is at most epsilon.
+/// * `relative`: the absolute difference is at most `relative` times the larger of |a| and |b|.
+/// * `ulps`: the real parts and the imaginary parts are at most `ulps` floats apart.
+///
+/// With `up_to_global_phase`, the second state is first rotated by the phase e^(iα) that
+/// brings it closest to the first one, so states that differ only in their global phase
+/// compare as equal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ComplexMargin {
+  pub epsilon: f64,
+  pub relative: f64,
+  pub ulps: i64,
+  pub up_to_global_phase: bool,
+}
+
+// The same defaults as F64Margin
+impl Default for ComplexMargin {
+  fn default() -> ComplexMargin {
+    ComplexMargin {
+      epsilon: f64::EPSILON,
+      relative: 0.0,
+      ulps: 4,
+      up_to_global_phase: false,
+    }
+  }
+}
+
+impl ComplexMargin {
+  /// A margin that only lets exactly equal numbers through. `approx_eq!` starts from this,
+  /// before setting the fields that it is given.
+  pub fn zero() -> ComplexMargin {
+    ComplexMargin {
+      epsilon: 0.0,
+      relative: 0.0,
+      ulps: 0,
+      up_to_global_phase: false,
+    }
+  }
+
+  pub fn epsilon(self, epsilon: f64) -> ComplexMargin {
+    ComplexMargin { epsilon, ..self }
+  }
+
+  pub fn relative(self, relative: f64) -> ComplexMargin {
+    ComplexMargin { relative, ..self }
+  }
+
+  pub fn ulps(self, ulps: i64) -> ComplexMargin {
+    ComplexMargin { ulps, ..self }
+  }
+
+  pub fn up_to_global_phase(self, up_to_global_phase: bool) -> ComplexMargin {
+    ComplexMargin {
+      up_to_global_phase,
+      ..self
+    }
+  }
+
+  fn numbers_approx_eq(&self, a: Complex64, b: Complex64) -> bool {
+    let difference = (a - b).norm();
+    difference <= self.epsilon
+      || difference <= self.relative * a.norm().max(b.norm())
+      || (approx_eq!(f64, a.re, b.re, ulps = self.ulps)
+        && approx_eq!(f64, a.im, b.im, ulps = self.ulps))
+  }
+
+  /// Compares two lists of amplitudes or matrix elements, one by one.
+  pub(crate) fn all_approx_eq(&self, a: &[Complex64], b: &[Complex64]) -> bool {
+    if a.len() != b.len() {
+      return false;
+    }
+    // The phase of <a|b> is the global phase that b has on top of a, if any
+    let overlap: Complex64 = a.iter().zip(b).map(|(x, y)| x.conj() * y).sum();
+    let phase = if self.up_to_global_phase && overlap.norm() > 0.0 {
+      overlap.conj() / overlap.norm()
+    } else {
+      Complex64::from(1.0)
+    };
+    a.iter()
+      .zip(b)
+      .all(|(x, y)| self.numbers_approx_eq(*x, y * phase))
+  }
+}
+
+/// Whether a square matrix, given by its rows, is the identity matrix up to the margin.
+/// Checking unitarity (U†U = I) or trace preservation (Σ K_i† K_i = I) comes down to this.
+pub(crate) fn is_identity<R: AsRef<[Complex64]>>(rows: &[R], margin: ComplexMargin) -> bool {
+  let size = rows.len();
+  let elements: Vec<Complex64> = rows
+    .iter()
+    .flat_map(|row| row.as_ref().iter().copied())
+    .collect();
+  let identity: Vec<Complex64> = (0..size * size)
+    .map(|i| Complex64::from(if i % (size + 1) == 0 { 1.0 } else { 0.0 }))
+    .collect();
+  margin.all_approx_eq(&elements, &identity)
+}
+
+impl From<F64Margin> for ComplexMargin {
+  fn from(margin: F64Margin) -> ComplexMargin {
+    ComplexMargin::zero()
+      .epsilon(margin.epsilon)
+      .ulps(margin.ulps)
+  }
+}
+
+impl From<(f64, i64)> for ComplexMargin {
+  fn from((epsilon, ulps): (f64, i64)) -> ComplexMargin {
+    ComplexMargin::zero().epsilon(epsilon).ulps(ulps)
+  }
+}
+
+#[test]
+fn margins_for_complex_numbers() {
+  let a = Complex64::new(1.0, -2.0);
+  let b = Complex64::new(1.0 + 1e-9, -2.0);
+  assert!(ComplexMargin::zero().numbers_approx_eq(a, a));
+  assert!(!ComplexMargin::default().numbers_approx_eq(a, b));
+  assert!(ComplexMargin::zero().epsilon(1e-8).numbers_approx_eq(a, b));
+  assert!(ComplexMargin::zero().relative(1e-9).numbers_approx_eq(a, b));
+  assert!(!ComplexMargin::zero()
+    .relative(1e-10)
+    .numbers_approx_eq(a, b));
+  let next = Complex64::new(f64::from_bits(1.0f64.to_bits() + 3), -2.0);
+  assert!(ComplexMargin::default().numbers_approx_eq(a, next));
+  assert!(!ComplexMargin::zero().ulps(2).numbers_approx_eq(a, next));
+}
+
+#[test]
+fn identity_matrices() {
+  let one = Complex64::from(1.0);
+  let zero = Complex64::from(0.0);
+  let margin = ComplexMargin::zero().epsilon(1e-12);
+  assert!(is_identity(&[[one, zero], [zero, one]], margin));
+  assert!(is_identity(&[vec![one]], margin));
+  assert!(!is_identity(&[[zero, one], [one, zero]], margin));
+  assert!(!is_identity(&[[one, zero], [zero, one + 1e-9]], margin));
+  assert!(is_identity(
+    &[[one, zero], [zero, one + 1e-9]],
+    margin.epsilon(1e-8)
+  ));
+  // Rows of the wrong length are not a square matrix
+  assert!(!is_identity(&[vec![one, zero], vec![zero]], margin));
+}
+
+#[test]
+fn global_phase_is_ignored_on_request() {
+  let a = [Complex64::new(0.6, 0.0), Complex64::new(0.0, 0.8)];
+  let phase = Complex64::from_polar(1.0, 1.3);
+  let b = [a[0] * phase, a[1] * phase];
+  let margin = ComplexMargin::zero().epsilon(1e-12);
+  assert!(!margin.all_approx_eq(&a, &b));
+  assert!(margin.up_to_global_phase(true).all_approx_eq(&a, &b));
+  assert!(!margin.up_to_global_phase(true).all_approx_eq(&a, &a[..1]));
+  // A relative phase between the amplitudes is a different state
+  let c = [a[0], a[1] * phase];
+  assert!(!margin.up_to_global_phase(true).all_approx_eq(&a, &c));
+}
@@ -0,0 +1,91 @@
+use crate::channel::Channel;
+use crate::circuit::GateKind;
+use num_complex::Complex64;
+use rand::RngCore;
+use std::fmt;
+
+/// An operation that a backend can not simulate, like a non-Clifford gate on a stabilizer
+/// state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationError {
+  pub message: String,
+}
+
+impl SimulationError {
+  pub fn new<S: Into<String>>(message: S) -> SimulationError {
+    SimulationError {
+      message: message.into(),
+    }
+  }
+}
+
+impl fmt::Display for SimulationError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+impl std::error::Error for SimulationError {}
+
+/// A simulator that circuits can run on, with `Circuit::run_on`.
+///
+/// The circuit takes care of the classical register and the control flow, and hands the
+/// backend one quantum operation at a time. `StateVector` and `DensityMatrix` are both
+/// backends, so the same circuit can be simulated either way. Backends that can only
+/// simulate some operations return a `SimulationError` for the rest.
+pub trait Backend {
+  fn num_qubits(&self) -> usize;
+
+  /// Applies a gate to the target qubit, when all of the control qubits are |1>.
+  fn apply_gate(
+    &mut self,
+    kind: &GateKind,
+    controls: &[usize],
+    target: usize,
+  ) -> Result<(), SimulationError>;
+
+  /// Swaps qubits `a` and `b`, when all of the control qubits are |1>.
+  fn apply_controlled_swap(
+    &mut self,
+    controls: &[usize],
+    a: usize,
+    b: usize,
+  ) -> Result<(), SimulationError>;
+
+  /// The iSWAP gate on qubits `a` and `b`.
+  fn iswap(&mut self, a: usize, b: usize) -> Result<(), SimulationError>;
+
+  /// Applies a unitary matrix on several qubits, with `qubits[0]` as the most significant bit.
+  fn apply_unitary(
+    &mut self,
+    matrix: &[Vec<Complex64>],
+    qubits: &[usize],
+  ) -> Result<(), SimulationError>;
+
+  /// Measures a qubit in the computational basis, collapsing the state to match.
+  fn measure<R: RngCore + ?Sized>(
+    &mut self,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<bool, SimulationError>;
+
+  /// Returns a qubit to |0>. By default, by measuring it and flipping it if it was |1>.
+  fn reset<R: RngCore + ?Sized>(
+    &mut self,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<(), SimulationError> {
+    if self.measure(qubit, rng)? {
+      self.apply_gate(&GateKind::X, &[], qubit)?;
+    }
+    Ok(())
+  }
+
+  /// Applies a noise channel to a qubit.
+  fn apply_channel<R: RngCore + ?Sized>(
+    &mut self,
+    channel: &Channel,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<(), SimulationError>;
+}
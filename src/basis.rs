@@ -0,0 +1,90 @@
+use crate::gate::Gate;
+use crate::ket::*;
+
+/// An orthonormal basis for measuring a single qubit: two kets with <zero|one> = 0.
+/// Measuring in the basis gives `false` for the `zero` ket and `true` for the `one` ket.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Basis {
+  zero: Ket,
+  one: Ket,
+}
+
+impl Basis {
+  /// The eigenbasis of Pauli X, |+> and |->.
+  pub const X: Basis = Basis {
+    zero: KET_PLUS,
+    one: KET_MINUS,
+  };
+
+  /// The eigenbasis of Pauli Y, |i> and |-i>.
+  pub const Y: Basis = Basis {
+    zero: KET_PLUS_I,
+    one: KET_MINUS_I,
+  };
+
+  /// The computational basis, |0> and |1>.
+  pub const Z: Basis = Basis {
+    zero: KET_ZERO,
+    one: KET_ONE,
+  };
+
+  /// Creates a basis from two kets, checking that both are valid states and that they
+  /// are orthogonal.
+  pub fn new(zero: Ket, one: Ket) -> Result<Basis, InvalidStateError> {
+    for ket in &[zero, one] {
+      if !ket.is_valid() {
+        return Err(InvalidStateError::new(format!(
+          "the kets of a basis must be valid states, but |{}|² + |{}|² = {}",
+          ket.first,
+          ket.second,
+          ket.first.norm_sqr() + ket.second.norm_sqr()
+        )));
+      }
+    }
+    let overlap = zero.inner(&one);
+    if overlap.norm() > 1e-12 {
+      return Err(InvalidStateError::new(format!(
+        "the kets of a basis must be orthogonal, but their inner product is {}",
+        overlap
+      )));
+    }
+    Ok(Basis { zero, one })
+  }
+
+  pub fn zero(&self) -> Ket {
+    self.zero
+  }
+
+  pub fn one(&self) -> Ket {
+    self.one
+  }
+
+  /// The unitary that maps |0> and |1> to the kets of the basis. Its conjugate transpose
+  /// rotates the basis onto the computational basis, so that a measurement in the Z basis
+  /// measures in this one.
+  pub fn from_computational(&self) -> Gate {
+    Gate::new([
+      [self.zero.first, self.one.first],
+      [self.zero.second, self.one.second],
+    ])
+  }
+}
+
+#[test]
+fn basis_must_be_orthonormal() {
+  use num_complex::Complex64;
+  let tilted = Ket::from_bloch(0.4, 1.0);
+  let opposite = Ket::from_bloch(std::f64::consts::PI - 0.4, 1.0 + std::f64::consts::PI);
+  let basis = Basis::new(tilted, opposite).unwrap();
+  assert!(basis.zero() == tilted && basis.one() == opposite);
+  let error = Basis::new(KET_ZERO, KET_PLUS).unwrap_err();
+  assert!(error.to_string().contains("orthogonal"), "{}", error);
+  let error = Basis::new(KET_ZERO, KET_ONE * Complex64::from(2.0)).unwrap_err();
+  assert!(error.to_string().contains("basis"), "{}", error);
+  for basis in &[Basis::X, Basis::Y, Basis::Z, basis] {
+    assert!(Basis::new(basis.zero(), basis.one()).is_ok());
+    assert!(crate::gate::Unitary::is_unitary(
+      &basis.from_computational()
+    ));
+  }
+}
@@ -0,0 +1,248 @@
+use crate::approx::{is_identity, ComplexMargin};
+use crate::gate::*;
+use crate::ket::{COMPLEX_ONE, COMPLEX_ZERO};
+use num_complex::Complex64;
+use rand::{Rng, RngCore};
+
+/// A quantum channel on a single qubit, the general form of noise.
+/// It is given by Kraus operators K_i, and takes a density matrix ρ to Σ K_i ρ K_i†.
+///
+/// A gate is the special case of a channel with a single, unitary, Kraus operator.
+/// On a pure state, a channel applies one of its Kraus operators K_i at random, with
+/// probability ‖K_i ψ‖², which averages out to the same mixed state (a "trajectory").
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+  kraus: Vec<Gate>,
+}
+
+impl Channel {
+  /// A channel with the given Kraus operators. They are not checked; use `is_cptp` for that.
+  pub fn new(kraus: Vec<Gate>) -> Channel {
+    Channel { kraus }
+  }
+
+  /// The channel that does nothing.
+  pub fn identity() -> Channel {
+    Channel::new(vec![IDENTITY])
+  }
+
+  /// Flips the qubit with probability `p`: applies X with probability p.
+  pub fn bit_flip(p: f64) -> Channel {
+    Channel::pauli(p, 0.0, 0.0)
+  }
+
+  /// Flips the phase of the qubit with probability `p`: applies Z with probability p.
+  pub fn phase_flip(p: f64) -> Channel {
+    Channel::pauli(0.0, 0.0, p)
+  }
+
+  /// With probability `p`, replaces the qubit by a random Pauli error, X, Y or Z, each
+  /// equally likely. This is ρ → (1 - p) ρ + p/3 (XρX + YρY + ZρZ).
+  pub fn depolarizing(p: f64) -> Channel {
+    Channel::pauli(p / 3.0, p / 3.0, p / 3.0)
+  }
+
+  /// Applies X, Y or Z with probabilities `px`, `py` and `pz`, and leaves the qubit alone
+  /// otherwise.
+  ///
+  /// Panics if a probability is negative, or they add up to more than 1.
+  pub fn pauli(px: f64, py: f64, pz: f64) -> Channel {
+    assert!(
+      px >= 0.0 && py >= 0.0 && pz >= 0.0 && px + py + pz <= 1.0 + 1e-12,
+      "the probabilities of a Pauli channel must be nonnegative and add up to at most 1, \
+       but they are {}, {} and {}",
+      px,
+      py,
+      pz
+    );
+    let operators = [
+      // Rounding errors could make this a tiny bit negative, like for depolarizing(1.0)
+      ((1.0 - px - py - pz).max(0.0), IDENTITY),
+      (px, PAULI_X),
+      (py, PAULI_Y),
+      (pz, PAULI_Z),
+    ];
+    Channel::new(
+      operators
+        .iter()
+        .filter(|(p, _)| *p != 0.0)
+        .map(|(p, gate)| scaled(gate, p.sqrt()))
+        .collect(),
+    )
+  }
+
+  /// Energy loss: |1> decays to |0> with probability `gamma`.
+  ///
+  /// Panics if `gamma` is not between 0 and 1.
+  pub fn amplitude_damping(gamma: f64) -> Channel {
+    assert!(
+      (0.0..=1.0).contains(&gamma),
+      "the probability of amplitude damping must be between 0 and 1, but it is {}",
+      gamma
+    );
+    let real = |x: f64| Complex64::new(x, 0.0);
+    Channel::new(vec![
+      Gate::new([
+        [COMPLEX_ONE, COMPLEX_ZERO],
+        [COMPLEX_ZERO, real((1.0 - gamma).sqrt())],
+      ]),
+      Gate::new([
+        [COMPLEX_ZERO, real(gamma.sqrt())],
+        [COMPLEX_ZERO, COMPLEX_ZERO],
+      ]),
+    ])
+  }
+
+  /// Loss of coherence without loss of energy: the off-diagonal elements of the density
+  /// matrix shrink by a factor of sqrt(1 - `lambda`), while the populations stay the same.
+  ///
+  /// Panics if `lambda` is not between 0 and 1.
+  pub fn phase_damping(lambda: f64) -> Channel {
+    assert!(
+      (0.0..=1.0).contains(&lambda),
+      "the probability of phase damping must be between 0 and 1, but it is {}",
+      lambda
+    );
+    let real = |x: f64| Complex64::new(x, 0.0);
+    Channel::new(vec![
+      Gate::new([
+        [COMPLEX_ONE, COMPLEX_ZERO],
+        [COMPLEX_ZERO, real((1.0 - lambda).sqrt())],
+      ]),
+      Gate::new([
+        [COMPLEX_ZERO, COMPLEX_ZERO],
+        [COMPLEX_ZERO, real(lambda.sqrt())],
+      ]),
+    ])
+  }
+
+  pub fn kraus_operators(&self) -> &[Gate] {
+    &self.kraus
+  }
+
+  /// Picks one of the Kraus operators at random, for a trajectory. `norms[i]` must be
+  /// ‖K_i ψ‖², which add up to 1 for a trace preserving channel.
+  pub(crate) fn choose<R: RngCore + ?Sized>(norms: &[f64], rng: &mut R) -> usize {
+    let total: f64 = norms.iter().sum();
+    let mut threshold = rng.gen::<f64>() * total;
+    for (index, norm) in norms.iter().enumerate() {
+      if threshold < *norm {
+        return index;
+      }
+      threshold -= norm;
+    }
+    // Only reachable through rounding errors, so pick the last likely operator
+    norms.iter().rposition(|norm| *norm > 0.0).unwrap_or(0)
+  }
+}
+
+fn scaled(gate: &Gate, factor: f64) -> Gate {
+  let m = gate.matrix();
+  let factor = Complex64::new(factor, 0.0);
+  Gate::new([
+    [m[0][0] * factor, m[0][1] * factor],
+    [m[1][0] * factor, m[1][1] * factor],
+  ])
+}
+
+// Like the states they act on, channels have a validity constraint: they must be
+// completely positive and trace preserving (CPTP), so that they take density matrices
+// to density matrices. Any set of Kraus operators is completely positive, and it is
+// trace preserving when Σ K_i† K_i = I.
+pub trait ValidChannel {
+  fn is_cptp(&self) -> bool;
+}
+
+impl ValidChannel for Channel {
+  fn is_cptp(&self) -> bool {
+    let mut sum = [[COMPLEX_ZERO; 2]; 2];
+    for operator in &self.kraus {
+      let product = (operator.dagger() * *operator).matrix();
+      for (row, out) in sum.iter_mut().enumerate() {
+        for (column, value) in out.iter_mut().enumerate() {
+          *value += product[row][column];
+        }
+      }
+    }
+    is_identity(&sum, ComplexMargin::zero().epsilon(1e-12))
+  }
+}
+
+#[test]
+fn built_in_channels_are_cptp() {
+  for p in &[0.0, 0.1, 0.5, 1.0] {
+    assert!(Channel::bit_flip(*p).is_cptp());
+    assert!(Channel::phase_flip(*p).is_cptp());
+    assert!(Channel::depolarizing(*p).is_cptp());
+    assert!(Channel::amplitude_damping(*p).is_cptp());
+    assert!(Channel::phase_damping(*p).is_cptp());
+  }
+  assert!(Channel::pauli(0.1, 0.2, 0.3).is_cptp());
+  assert!(Channel::identity().is_cptp());
+  // No identity part at all, only errors
+  assert_eq!(Channel::bit_flip(1.0).kraus_operators(), &[PAULI_X]);
+}
+
+#[test]
+fn channel_not_cptp() {
+  assert!(!Channel::new(vec![PAULI_X, PAULI_Z]).is_cptp());
+  assert!(!Channel::new(vec![]).is_cptp());
+  // Kraus operators that add up to more than one
+  assert!(!Channel::new(vec![IDENTITY, PAULI_X]).is_cptp());
+}
+
+#[test]
+#[should_panic(expected = "add up to at most 1")]
+fn pauli_probabilities_over_one() {
+  Channel::pauli(0.5, 0.5, 0.5);
+}
+
+#[test]
+#[should_panic(expected = "nonnegative")]
+fn negative_pauli_probability() {
+  Channel::pauli(-0.1, 0.0, 0.0);
+}
+
+#[test]
+fn choose_follows_norms() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(14);
+  let mut counts = [0; 3];
+  for _ in 0..1000 {
+    counts[Channel::choose(&[0.2, 0.0, 0.8], &mut rng)] += 1;
+  }
+  assert_eq!(counts[1], 0);
+  assert!((150..250).contains(&counts[0]), "{:?}", counts);
+}
+
+#[test]
+fn trajectories_average_to_density_matrix() {
+  use crate::density_matrix::DensityMatrix;
+  use crate::ket::*;
+  use crate::state_vector::StateVector;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(15);
+  let channel = Channel::amplitude_damping(0.4);
+  let plus = (KET_ZERO + KET_ONE) * Complex64::new(0.5f64.sqrt(), 0.0);
+  let mut expected = DensityMatrix::from_state(plus.tensor(KET_ONE));
+  expected.apply_channel(&channel, 1);
+  let runs = 2000;
+  let mut average = DensityMatrix::new(2) * 0.0;
+  let mut ket_ones = 0;
+  for _ in 0..runs {
+    let mut state = StateVector::from(plus).tensor(KET_ONE);
+    state.apply_channel(&channel, 1, &mut rng);
+    assert!(state.is_valid());
+    average = average + DensityMatrix::from_state(state) * (1.0 / runs as f64);
+    let mut ket = KET_ONE;
+    ket.apply_channel(&channel, &mut rng);
+    ket_ones += (ket == KET_ONE) as usize;
+  }
+  for row in 0..4 {
+    for column in 0..4 {
+      let difference = average.element(row, column) - expected.element(row, column);
+      assert!(difference.norm() < 0.05, "{} {}", row, column);
+    }
+  }
+  assert!((1100..1300).contains(&ket_ones), "{}", ket_ones);
+}
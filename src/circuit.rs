@@ -0,0 +1,906 @@
+use crate::backend::{Backend, SimulationError};
+use crate::gate::*;
+use crate::noise::NoiseModel;
+use crate::state_vector::StateVector;
+use num_complex::Complex64;
+use rand::RngCore;
+use std::collections::BTreeMap;
+
+/// The kinds of single-qubit gates a circuit can contain.
+/// Keeping the name of the gate around, instead of just its matrix,
+/// lets us print the circuit back out in a readable form.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GateKind {
+  I,
+  X,
+  Y,
+  Z,
+  H,
+  S,
+  Sdg,
+  T,
+  Tdg,
+  Rx(f64),
+  Ry(f64),
+  Rz(f64),
+  Phase(f64),
+  U3(f64, f64, f64),
+  /// Any other single-qubit unitary
+  Custom(Gate),
+}
+
+impl GateKind {
+  /// The matrix of the gate.
+  pub fn gate(&self) -> Gate {
+    match *self {
+      GateKind::I => IDENTITY,
+      GateKind::X => PAULI_X,
+      GateKind::Y => PAULI_Y,
+      GateKind::Z => PAULI_Z,
+      GateKind::H => HADAMARD,
+      GateKind::S => S_GATE,
+      GateKind::Sdg => S_DAGGER,
+      GateKind::T => T_GATE,
+      GateKind::Tdg => T_DAGGER,
+      GateKind::Rx(theta) => Gate::rx(theta),
+      GateKind::Ry(theta) => Gate::ry(theta),
+      GateKind::Rz(theta) => Gate::rz(theta),
+      GateKind::Phase(lambda) => Gate::phase(lambda),
+      GateKind::U3(theta, phi, lambda) => Gate::u3(theta, phi, lambda),
+      GateKind::Custom(gate) => gate,
+    }
+  }
+
+  /// A short lowercase name for the gate, such as "h" or "rx".
+  pub fn name(&self) -> &'static str {
+    match self {
+      GateKind::I => "id",
+      GateKind::X => "x",
+      GateKind::Y => "y",
+      GateKind::Z => "z",
+      GateKind::H => "h",
+      GateKind::S => "s",
+      GateKind::Sdg => "sdg",
+      GateKind::T => "t",
+      GateKind::Tdg => "tdg",
+      GateKind::Rx(_) => "rx",
+      GateKind::Ry(_) => "ry",
+      GateKind::Rz(_) => "rz",
+      GateKind::Phase(_) => "p",
+      GateKind::U3(..) => "u3",
+      GateKind::Custom(_) => "unitary",
+    }
+  }
+
+  /// The inverse of the gate.
+  pub fn dagger(&self) -> GateKind {
+    match *self {
+      GateKind::S => GateKind::Sdg,
+      GateKind::Sdg => GateKind::S,
+      GateKind::T => GateKind::Tdg,
+      GateKind::Tdg => GateKind::T,
+      GateKind::Rx(theta) => GateKind::Rx(-theta),
+      GateKind::Ry(theta) => GateKind::Ry(-theta),
+      GateKind::Rz(theta) => GateKind::Rz(-theta),
+      GateKind::Phase(lambda) => GateKind::Phase(-lambda),
+      GateKind::U3(theta, phi, lambda) => GateKind::U3(-theta, -lambda, -phi),
+      GateKind::Custom(gate) => GateKind::Custom(gate.dagger()),
+      // The rest are their own inverses
+      kind => kind,
+    }
+  }
+}
+
+#[test]
+fn gate_kind_dagger_inverts() {
+  let kinds = [
+    GateKind::I,
+    GateKind::X,
+    GateKind::Y,
+    GateKind::Z,
+    GateKind::H,
+    GateKind::S,
+    GateKind::Sdg,
+    GateKind::T,
+    GateKind::Tdg,
+    GateKind::Rx(0.3),
+    GateKind::Ry(-1.2),
+    GateKind::Rz(2.0),
+    GateKind::Phase(0.7),
+    GateKind::U3(0.1, 0.2, 0.3),
+    GateKind::Custom(Gate::u3(1.0, 2.0, 3.0)),
+  ];
+  for kind in &kinds {
+    let product = kind.dagger().gate() * kind.gate();
+    for (a, b) in product
+      .matrix
+      .iter()
+      .flatten()
+      .zip(IDENTITY.matrix.iter().flatten())
+    {
+      assert!((a - b).norm() < 1e-12, "{:?}", kind);
+    }
+  }
+}
+
+/// A single step of a circuit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+  /// Apply a single-qubit gate to the target qubit, when all of the control qubits are |1>
+  Gate {
+    kind: GateKind,
+    target: usize,
+    controls: Vec<usize>,
+  },
+  /// Swap two qubits, when all of the control qubits are |1>
+  Swap {
+    a: usize,
+    b: usize,
+    controls: Vec<usize>,
+  },
+  /// Swap two qubits, multiplying |01> and |10> by i
+  ISwap { a: usize, b: usize },
+  /// Apply a unitary matrix to several qubits, with `qubits[0]` as the most significant bit
+  /// of the row and column indices
+  Unitary {
+    matrix: Vec<Vec<Complex64>>,
+    qubits: Vec<usize>,
+  },
+  /// Measure a qubit in the computational basis, storing the result in a classical bit
+  Measure { qubit: usize, clbit: usize },
+  /// Return a qubit to the state |0>
+  Reset { qubit: usize },
+  /// Does nothing to the state; marks that instructions should not be reordered across it
+  Barrier { qubits: Vec<usize> },
+  /// Run one list of instructions or another, depending on the classical register
+  If {
+    condition: Condition,
+    then_branch: Vec<Instruction>,
+    else_branch: Vec<Instruction>,
+  },
+  /// Keep running a list of instructions for as long as the condition holds
+  While {
+    condition: Condition,
+    body: Vec<Instruction>,
+  },
+  /// Marks a place that a jump can continue from
+  Label(String),
+  /// Continue from the label with the given name, if there is no condition or it holds.
+  /// The label must be in the same list of instructions as the jump.
+  Jump {
+    label: String,
+    condition: Option<Condition>,
+  },
+}
+
+/// A test on the classical register. The classical bits are read as an unsigned integer,
+/// with the first bit in `clbits` as the least significant, and compared against `value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+  pub clbits: Vec<usize>,
+  pub value: u64,
+  pub comparison: Comparison,
+}
+
+/// How a condition compares the classical bits against its value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Comparison {
+  Equal,
+  NotEqual,
+}
+
+impl Condition {
+  /// Creates a condition that holds when the classical bits read as `value`.
+  pub fn new(clbits: Vec<usize>, value: u64) -> Condition {
+    Condition {
+      clbits,
+      value,
+      comparison: Comparison::Equal,
+    }
+  }
+
+  /// Creates a condition that holds when the classical bits read as anything but `value`.
+  pub fn not_equal(clbits: Vec<usize>, value: u64) -> Condition {
+    Condition {
+      clbits,
+      value,
+      comparison: Comparison::NotEqual,
+    }
+  }
+
+  /// Whether the condition holds for the given classical register.
+  pub fn is_met(&self, classical: &[bool]) -> bool {
+    let value = self
+      .clbits
+      .iter()
+      .rev()
+      .fold(0, |value, clbit| (value << 1) | classical[*clbit] as u64);
+    match self.comparison {
+      Comparison::Equal => value == self.value,
+      Comparison::NotEqual => value != self.value,
+    }
+  }
+}
+
+#[test]
+fn condition_reads_little_endian() {
+  let classical = [true, false, true];
+  assert!(Condition::new(vec![0, 1, 2], 0b101).is_met(&classical));
+  assert!(Condition::new(vec![1, 2], 0b10).is_met(&classical));
+  assert!(!Condition::new(vec![0, 1], 0b10).is_met(&classical));
+  assert!(Condition::not_equal(vec![0, 1], 0b10).is_met(&classical));
+}
+
+/// A quantum circuit: a register of qubits, a register of classical bits,
+/// and a list of instructions to run on them in order.
+///
+/// Circuits are put together with the builder methods, which can be chained:
+/// `circuit.h(0).x(1).measure_all();`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Circuit {
+  num_qubits: usize,
+  num_clbits: usize,
+  instructions: Vec<Instruction>,
+}
+
+/// The outcome of running a circuit: the final quantum state, and the classical register
+/// holding the measurement results. The state is a `StateVector`, unless the circuit ran
+/// on some other backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult<S = StateVector> {
+  pub state: S,
+  pub classical: Vec<bool>,
+}
+
+impl<S> RunResult<S> {
+  /// The classical register as a string of bits, classical bit 0 first.
+  pub fn classical_bitstring(&self) -> String {
+    self
+      .classical
+      .iter()
+      .map(|bit| if *bit { '1' } else { '0' })
+      .collect()
+  }
+}
+
+impl Circuit {
+  /// Creates an empty circuit with `num_qubits` qubits and `num_clbits` classical bits.
+  pub fn new(num_qubits: usize, num_clbits: usize) -> Circuit {
+    Circuit {
+      num_qubits,
+      num_clbits,
+      instructions: Vec::new(),
+    }
+  }
+
+  /// The number of qubits in the circuit.
+  pub fn num_qubits(&self) -> usize {
+    self.num_qubits
+  }
+
+  /// The number of classical bits in the circuit.
+  pub fn num_clbits(&self) -> usize {
+    self.num_clbits
+  }
+
+  /// The instructions of the circuit, in order.
+  pub fn instructions(&self) -> &[Instruction] {
+    &self.instructions
+  }
+
+  /// Appends an instruction to the circuit.
+  ///
+  /// Panics if the instruction refers to qubits or classical bits the circuit does not have.
+  pub fn push(&mut self, instruction: Instruction) -> &mut Self {
+    self.check_instruction(&instruction);
+    self.instructions.push(instruction);
+    self
+  }
+
+  fn check_instruction(&self, instruction: &Instruction) {
+    match instruction {
+      Instruction::Gate {
+        target, controls, ..
+      } => self.check_qubits(controls.iter().chain(&[*target])),
+      Instruction::Swap { a, b, controls } => self.check_qubits(controls.iter().chain(&[*a, *b])),
+      Instruction::ISwap { a, b } => self.check_qubits(&[*a, *b]),
+      Instruction::Unitary { matrix, qubits } => {
+        self.check_qubits(qubits);
+        assert!(
+          matrix.len() == 1 << qubits.len() && matrix.iter().all(|row| row.len() == matrix.len()),
+          "a unitary on {} qubits must be a {1}x{1} matrix",
+          qubits.len(),
+          1 << qubits.len()
+        );
+      }
+      Instruction::Measure { qubit, clbit } => {
+        self.check_qubit(*qubit);
+        self.check_clbit(*clbit);
+      }
+      Instruction::Reset { qubit } => self.check_qubit(*qubit),
+      Instruction::Barrier { qubits } => {
+        for qubit in qubits {
+          self.check_qubit(*qubit);
+        }
+      }
+      Instruction::If {
+        condition,
+        then_branch,
+        else_branch,
+      } => {
+        for clbit in &condition.clbits {
+          self.check_clbit(*clbit);
+        }
+        for instruction in then_branch.iter().chain(else_branch) {
+          self.check_instruction(instruction);
+        }
+      }
+      Instruction::While { condition, body } => {
+        for clbit in &condition.clbits {
+          self.check_clbit(*clbit);
+        }
+        for instruction in body {
+          self.check_instruction(instruction);
+        }
+      }
+      Instruction::Label(_) => {}
+      Instruction::Jump { condition, .. } => {
+        for clbit in condition.iter().flat_map(|condition| &condition.clbits) {
+          self.check_clbit(*clbit);
+        }
+      }
+    }
+  }
+
+  fn check_qubit(&self, qubit: usize) {
+    assert!(
+      qubit < self.num_qubits,
+      "qubit {} out of range for a circuit of {} qubits",
+      qubit,
+      self.num_qubits
+    );
+  }
+
+  // Checks that the qubits of a single gate are in range, and that none of them repeat
+  fn check_qubits<'a, I: IntoIterator<Item = &'a usize>>(&self, qubits: I) {
+    let mut seen = Vec::new();
+    for qubit in qubits {
+      self.check_qubit(*qubit);
+      assert!(
+        !seen.contains(qubit),
+        "qubit {} used twice in the same gate",
+        qubit
+      );
+      seen.push(*qubit);
+    }
+  }
+
+  fn check_clbit(&self, clbit: usize) {
+    assert!(
+      clbit < self.num_clbits,
+      "classical bit {} out of range for a circuit of {} classical bits",
+      clbit,
+      self.num_clbits
+    );
+  }
+
+  /// Applies a single-qubit gate to the target qubit.
+  pub fn gate(&mut self, kind: GateKind, target: usize) -> &mut Self {
+    self.controlled(kind, &[], target)
+  }
+
+  /// Applies a single-qubit gate to the target qubit, controlled by any number of qubits.
+  pub fn controlled(&mut self, kind: GateKind, controls: &[usize], target: usize) -> &mut Self {
+    self.push(Instruction::Gate {
+      kind,
+      target,
+      controls: controls.to_vec(),
+    })
+  }
+
+  pub fn i(&mut self, target: usize) -> &mut Self {
+    self.gate(GateKind::I, target)
+  }
+
+  pub fn x(&mut self, target: usize) -> &mut Self {
+    self.gate(GateKind::X, target)
+  }
+
+  pub fn y(&mut self, target: usize) -> &mut Self {
+    self.gate(GateKind::Y, target)
+  }
+
+  pub fn z(&mut self, target: usize) -> &mut Self {
+    self.gate(GateKind::Z, target)
+  }
+
+  pub fn h(&mut self, target: usize) -> &mut Self {
+    self.gate(GateKind::H, target)
+  }
+
+  pub fn s(&mut self, target: usize) -> &mut Self {
+    self.gate(GateKind::S, target)
+  }
+
+  pub fn sdg(&mut self, target: usize) -> &mut Self {
+    self.gate(GateKind::Sdg, target)
+  }
+
+  pub fn t(&mut self, target: usize) -> &mut Self {
+    self.gate(GateKind::T, target)
+  }
+
+  pub fn tdg(&mut self, target: usize) -> &mut Self {
+    self.gate(GateKind::Tdg, target)
+  }
+
+  pub fn rx(&mut self, theta: f64, target: usize) -> &mut Self {
+    self.gate(GateKind::Rx(theta), target)
+  }
+
+  pub fn ry(&mut self, theta: f64, target: usize) -> &mut Self {
+    self.gate(GateKind::Ry(theta), target)
+  }
+
+  pub fn rz(&mut self, theta: f64, target: usize) -> &mut Self {
+    self.gate(GateKind::Rz(theta), target)
+  }
+
+  pub fn p(&mut self, lambda: f64, target: usize) -> &mut Self {
+    self.gate(GateKind::Phase(lambda), target)
+  }
+
+  pub fn u3(&mut self, theta: f64, phi: f64, lambda: f64, target: usize) -> &mut Self {
+    self.gate(GateKind::U3(theta, phi, lambda), target)
+  }
+
+  /// The controlled NOT gate, flipping the target when the control is |1>.
+  pub fn cx(&mut self, control: usize, target: usize) -> &mut Self {
+    self.controlled(GateKind::X, &[control], target)
+  }
+
+  pub fn cy(&mut self, control: usize, target: usize) -> &mut Self {
+    self.controlled(GateKind::Y, &[control], target)
+  }
+
+  pub fn cz(&mut self, control: usize, target: usize) -> &mut Self {
+    self.controlled(GateKind::Z, &[control], target)
+  }
+
+  /// The Toffoli gate, flipping the target when both controls are |1>.
+  pub fn ccx(&mut self, control_a: usize, control_b: usize, target: usize) -> &mut Self {
+    self.controlled(GateKind::X, &[control_a, control_b], target)
+  }
+
+  pub fn swap(&mut self, a: usize, b: usize) -> &mut Self {
+    self.push(Instruction::Swap {
+      a,
+      b,
+      controls: Vec::new(),
+    })
+  }
+
+  /// The Fredkin gate, swapping `a` and `b` when the control is |1>.
+  pub fn cswap(&mut self, control: usize, a: usize, b: usize) -> &mut Self {
+    self.push(Instruction::Swap {
+      a,
+      b,
+      controls: vec![control],
+    })
+  }
+
+  pub fn iswap(&mut self, a: usize, b: usize) -> &mut Self {
+    self.push(Instruction::ISwap { a, b })
+  }
+
+  /// Applies an arbitrary single-qubit unitary to the target qubit.
+  pub fn unitary(&mut self, gate: Gate, target: usize) -> &mut Self {
+    self.gate(GateKind::Custom(gate), target)
+  }
+
+  /// Measures a qubit into a classical bit.
+  pub fn measure(&mut self, qubit: usize, clbit: usize) -> &mut Self {
+    self.push(Instruction::Measure { qubit, clbit })
+  }
+
+  /// Returns a qubit to the state |0>, by measuring it and flipping it if it was |1>.
+  pub fn reset(&mut self, qubit: usize) -> &mut Self {
+    self.push(Instruction::Reset { qubit })
+  }
+
+  /// A barrier across the given qubits. Has no effect on the simulation.
+  pub fn barrier(&mut self, qubits: &[usize]) -> &mut Self {
+    self.push(Instruction::Barrier {
+      qubits: qubits.to_vec(),
+    })
+  }
+
+  /// Measures every qubit into the classical bit with the same index.
+  /// The classical register grows to fit, if needed.
+  pub fn measure_all(&mut self) -> &mut Self {
+    self.num_clbits = self.num_clbits.max(self.num_qubits);
+    for qubit in 0..self.num_qubits {
+      self.measure(qubit, qubit);
+    }
+    self
+  }
+
+  /// Runs the circuit on a state vector, starting from every qubit in the state |0>
+  /// and every classical bit cleared. Measurements draw their randomness from `rng`.
+  pub fn run<R: RngCore + ?Sized>(&self, rng: &mut R) -> RunResult {
+    self.run_noisy(&NoiseModel::new(), rng)
+  }
+
+  /// Runs the circuit on a state vector with noise. Every noise channel picks one of its
+  /// Kraus operators at random, so each run follows a single trajectory of the noisy circuit.
+  ///
+  /// Panics if the circuit jumps to a label that it does not have; `run_on` returns an
+  /// error for that instead.
+  pub fn run_noisy<R: RngCore + ?Sized>(&self, noise: &NoiseModel, rng: &mut R) -> RunResult {
+    self
+      .run_on(StateVector::new(self.num_qubits), noise, rng)
+      .unwrap_or_else(|error| panic!("{}", error))
+  }
+
+  /// Runs the circuit on any backend, starting from the state it is in, with the errors of
+  /// the noise model after every gate and on every measurement. Returns an error if the
+  /// circuit uses an operation that the backend can not simulate, or jumps to a label that
+  /// does not exist.
+  ///
+  /// Panics if the backend has a different number of qubits than the circuit.
+  pub fn run_on<B: Backend, R: RngCore + ?Sized>(
+    &self,
+    mut state: B,
+    noise: &NoiseModel,
+    rng: &mut R,
+  ) -> Result<RunResult<B>, SimulationError> {
+    assert_eq!(
+      state.num_qubits(),
+      self.num_qubits,
+      "the backend must have as many qubits as the circuit"
+    );
+    let mut classical = vec![false; self.num_clbits];
+    execute(&self.instructions, &mut state, &mut classical, noise, rng)?;
+    Ok(RunResult { state, classical })
+  }
+
+  /// Runs the circuit `shots` times, and counts how many times each final classical register
+  /// was seen. The keys of the histogram are bitstrings, classical bit 0 first.
+  ///
+  /// Every shot is simulated from the start, so circuits with measurements in the middle
+  /// and classical control flow behave just like they would on hardware.
+  pub fn sample<R: RngCore + ?Sized>(&self, shots: usize, rng: &mut R) -> BTreeMap<String, usize> {
+    self.sample_noisy(shots, &NoiseModel::new(), rng)
+  }
+
+  /// Like `sample`, but every shot runs with the errors of the noise model.
+  pub fn sample_noisy<R: RngCore + ?Sized>(
+    &self,
+    shots: usize,
+    noise: &NoiseModel,
+    rng: &mut R,
+  ) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for _ in 0..shots {
+      *counts
+        .entry(self.run_noisy(noise, rng).classical_bitstring())
+        .or_insert(0) += 1;
+    }
+    counts
+  }
+
+  /// Like `sample_noisy`, but on any backend. Every shot starts from a copy of `state`.
+  pub fn sample_on<B: Backend + Clone, R: RngCore + ?Sized>(
+    &self,
+    shots: usize,
+    state: &B,
+    noise: &NoiseModel,
+    rng: &mut R,
+  ) -> Result<BTreeMap<String, usize>, SimulationError> {
+    let mut counts = BTreeMap::new();
+    for _ in 0..shots {
+      let result = self.run_on(state.clone(), noise, rng)?;
+      *counts.entry(result.classical_bitstring()).or_insert(0) += 1;
+    }
+    Ok(counts)
+  }
+}
+
+fn execute<B: Backend, R: RngCore + ?Sized>(
+  instructions: &[Instruction],
+  state: &mut B,
+  classical: &mut [bool],
+  noise: &NoiseModel,
+  rng: &mut R,
+) -> Result<(), SimulationError> {
+  // Jumps move the position around, so walk the instructions by index
+  let mut position = 0;
+  while let Some(instruction) = instructions.get(position) {
+    position += 1;
+    match instruction {
+      Instruction::Gate {
+        kind,
+        target,
+        controls,
+      } => state.apply_gate(kind, controls, *target)?,
+      Instruction::Swap { a, b, controls } => state.apply_controlled_swap(controls, *a, *b)?,
+      Instruction::ISwap { a, b } => state.iswap(*a, *b)?,
+      Instruction::Unitary { matrix, qubits } => state.apply_unitary(matrix, qubits)?,
+      Instruction::Measure { qubit, clbit } => {
+        let outcome = state.measure(*qubit, rng)?;
+        classical[*clbit] = noise.read(*qubit, outcome, rng);
+      }
+      Instruction::Reset { qubit } => state.reset(*qubit, rng)?,
+      Instruction::Barrier { .. } => {}
+      Instruction::If {
+        condition,
+        then_branch,
+        else_branch,
+      } => {
+        let branch = if condition.is_met(classical) {
+          then_branch
+        } else {
+          else_branch
+        };
+        execute(branch, state, classical, noise, rng)?;
+      }
+      Instruction::While { condition, body } => {
+        while condition.is_met(classical) {
+          execute(body, state, classical, noise, rng)?;
+        }
+      }
+      Instruction::Label(_) => {}
+      Instruction::Jump { label, condition } => {
+        let jumps = match condition {
+          Some(condition) => condition.is_met(classical),
+          None => true,
+        };
+        if jumps {
+          position = instructions
+            .iter()
+            .position(|other| matches!(other, Instruction::Label(name) if name == label))
+            .ok_or_else(|| SimulationError::new(format!("jump to unknown label '{}'", label)))?;
+        }
+      }
+    }
+    noise.after_instruction(instruction, state, rng)?;
+  }
+  Ok(())
+}
+
+#[test]
+fn empty_circuit_leaves_zeros() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+  let result = Circuit::new(3, 2).run(&mut rng);
+  assert!(result.state == StateVector::new(3));
+  assert_eq!(result.classical, vec![false, false]);
+}
+
+#[test]
+fn builder_chains() {
+  let mut circuit = Circuit::new(2, 0);
+  circuit.h(0).x(1).rz(0.5, 0).measure_all();
+  assert_eq!(circuit.num_clbits(), 2);
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      Instruction::Gate {
+        kind: GateKind::H,
+        target: 0,
+        controls: vec![]
+      },
+      Instruction::Gate {
+        kind: GateKind::X,
+        target: 1,
+        controls: vec![]
+      },
+      Instruction::Gate {
+        kind: GateKind::Rz(0.5),
+        target: 0,
+        controls: vec![]
+      },
+      Instruction::Measure { qubit: 0, clbit: 0 },
+      Instruction::Measure { qubit: 1, clbit: 1 },
+    ]
+  );
+}
+
+#[test]
+fn run_flips_and_measures() {
+  use crate::ket::*;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+  let mut circuit = Circuit::new(3, 3);
+  circuit.x(0).x(2).measure_all();
+  let result = circuit.run(&mut rng);
+  assert!(result.state == StateVector::from_kets(&[KET_ONE, KET_ZERO, KET_ONE]));
+  assert_eq!(result.classical_bitstring(), "101");
+}
+
+#[test]
+fn run_without_measurement_keeps_superposition() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+  let mut circuit = Circuit::new(2, 0);
+  circuit.h(0).h(1);
+  let result = circuit.run(&mut rng);
+  for probability in result.state.probabilities() {
+    assert!((probability - 0.25).abs() < 1e-12);
+  }
+}
+
+#[test]
+fn measurements_are_random_but_seeded() {
+  use rand::SeedableRng;
+  let mut circuit = Circuit::new(1, 1);
+  circuit.h(0).measure(0, 0);
+  let mut rng = rand::rngs::StdRng::seed_from_u64(4);
+  let first: Vec<bool> = (0..32)
+    .map(|_| circuit.run(&mut rng).classical[0])
+    .collect();
+  let mut rng = rand::rngs::StdRng::seed_from_u64(4);
+  let second: Vec<bool> = (0..32)
+    .map(|_| circuit.run(&mut rng).classical[0])
+    .collect();
+  assert_eq!(first, second);
+  assert!(first.contains(&true) && first.contains(&false));
+}
+
+#[test]
+fn bell_pair_measurements_agree() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+  let mut circuit = Circuit::new(2, 2);
+  circuit.h(0).cx(0, 1).measure_all();
+  let mut seen = Vec::new();
+  for _ in 0..32 {
+    let bits = circuit.run(&mut rng).classical_bitstring();
+    assert!(bits == "00" || bits == "11");
+    seen.push(bits);
+  }
+  assert!(seen.contains(&"00".to_string()) && seen.contains(&"11".to_string()));
+}
+
+#[test]
+fn ghz_state() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(6);
+  let mut circuit = Circuit::new(4, 0);
+  circuit.h(0).cx(0, 1).cx(1, 2).cx(2, 3);
+  let state = circuit.run(&mut rng).state;
+  let probabilities = state.probabilities();
+  assert!((probabilities[0b0000] - 0.5).abs() < 1e-12);
+  assert!((probabilities[0b1111] - 0.5).abs() < 1e-12);
+}
+
+#[test]
+fn swap_gates_in_circuits() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+  let mut circuit = Circuit::new(3, 0);
+  circuit.x(0).swap(0, 2).x(1).cswap(1, 0, 2).ccx(0, 1, 2);
+  assert!(circuit.run(&mut rng).state == StateVector::from_basis_index(3, 0b111));
+}
+
+#[test]
+fn reset_returns_to_zero() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(8);
+  let mut circuit = Circuit::new(2, 0);
+  circuit.h(0).x(1).barrier(&[0, 1]).reset(0).reset(1);
+  for _ in 0..8 {
+    assert!(circuit.run(&mut rng).state == StateVector::new(2));
+  }
+}
+
+#[test]
+fn classically_controlled_correction() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(9);
+  // Measure a random bit, then flip the other qubit to match it
+  let mut circuit = Circuit::new(2, 2);
+  circuit.h(0).measure(0, 0).push(Instruction::If {
+    condition: Condition::new(vec![0], 1),
+    then_branch: vec![Instruction::Gate {
+      kind: GateKind::X,
+      target: 1,
+      controls: vec![],
+    }],
+    else_branch: vec![],
+  });
+  circuit.measure(1, 1);
+  for _ in 0..16 {
+    let bits = circuit.run(&mut rng).classical_bitstring();
+    assert!(bits == "00" || bits == "11");
+  }
+}
+
+#[test]
+fn repeat_until_success() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(10);
+  // Keep flipping coins until one comes up |1>
+  let mut circuit = Circuit::new(1, 1);
+  circuit.push(Instruction::While {
+    condition: Condition::new(vec![0], 0),
+    body: vec![
+      Instruction::Reset { qubit: 0 },
+      Instruction::Gate {
+        kind: GateKind::H,
+        target: 0,
+        controls: vec![],
+      },
+      Instruction::Measure { qubit: 0, clbit: 0 },
+    ],
+  });
+  let counts = circuit.sample(32, &mut rng);
+  assert_eq!(counts.len(), 1);
+  assert_eq!(counts["1"], 32);
+}
+
+#[test]
+fn sample_counts_classical_registers() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+  let mut circuit = Circuit::new(2, 2);
+  circuit.h(0).cx(0, 1).measure_all();
+  let counts = circuit.sample(1000, &mut rng);
+  assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["00", "11"]);
+  assert!((400..600).contains(&counts["00"]), "{:?}", counts);
+}
+
+#[test]
+fn jumps_loop_until_condition() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(12);
+  // The same coin flipping loop as above, written with labels and jumps
+  let mut circuit = Circuit::new(1, 1);
+  circuit
+    .push(Instruction::Label("flip".to_string()))
+    .reset(0)
+    .h(0)
+    .measure(0, 0)
+    .push(Instruction::Jump {
+      label: "flip".to_string(),
+      condition: Some(Condition::new(vec![0], 0)),
+    })
+    .push(Instruction::Jump {
+      label: "end".to_string(),
+      condition: None,
+    })
+    .x(0)
+    .push(Instruction::Label("end".to_string()));
+  for _ in 0..16 {
+    let result = circuit.run(&mut rng);
+    assert_eq!(result.classical, vec![true]);
+    assert!(result.state == StateVector::from_basis_index(1, 1));
+  }
+}
+
+#[test]
+fn jump_to_unknown_label_is_an_error() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(12);
+  let mut circuit = Circuit::new(1, 0);
+  circuit.x(0).push(Instruction::Jump {
+    label: "nowhere".to_string(),
+    condition: None,
+  });
+  let error = circuit
+    .run_on(StateVector::new(1), &NoiseModel::new(), &mut rng)
+    .unwrap_err();
+  assert_eq!(error.message, "jump to unknown label 'nowhere'");
+}
+
+#[test]
+#[should_panic]
+fn repeated_qubit_in_gate() {
+  Circuit::new(2, 0).cx(1, 1);
+}
+
+#[test]
+#[should_panic]
+fn gate_out_of_range() {
+  Circuit::new(2, 0).h(2);
+}
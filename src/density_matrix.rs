@@ -0,0 +1,635 @@
+use crate::approx::ComplexMargin;
+use crate::backend::{Backend, SimulationError};
+use crate::channel::Channel;
+use crate::circuit::GateKind;
+use crate::gate::Gate;
+use crate::ket::*;
+use crate::linalg::{hermitian_eigen, multiply, positive_sqrt};
+use crate::state_vector::StateVector;
+use float_cmp::ApproxEq;
+use num_complex::Complex64;
+use rand::{Rng, RngCore};
+
+/// A density matrix describes a register of `n` qubits that may be in a mixed state:
+/// a probabilistic mixture of pure states, ρ = Σ p_i |ψ_i><ψ_i|.
+/// It is a 2^n by 2^n Hermitian matrix, with trace 1 and no negative eigenvalues.
+/// Qubits are ordered big-endian, just like in `StateVector`.
+///
+/// Where a state vector holds one amplitude for each basis state, a density matrix holds one
+/// element for each pair of them. Pure states have purity Tr(ρ²) = 1, and mixed ones less.
+#[derive(Debug, Clone)]
+pub struct DensityMatrix {
+  num_qubits: usize,
+  // The matrix is stored as a state vector of 2n qubits: element (row, column) is the amplitude
+  // at index row * 2^n + column. Then a gate U on the rows is U on qubit q, and U† on the
+  // columns is the conjugate of U on qubit n + q, and the state vector can do all the work.
+  elements: StateVector,
+}
+
+impl DensityMatrix {
+  /// Creates a register of `num_qubits` qubits, all of them in the state |0>.
+  pub fn new(num_qubits: usize) -> DensityMatrix {
+    DensityMatrix::from_state(StateVector::new(num_qubits))
+  }
+
+  /// The density matrix |ψ><ψ| of a pure state.
+  pub fn from_state<T: Into<StateVector>>(state: T) -> DensityMatrix {
+    let state = state.into();
+    let amplitudes = state.amplitudes();
+    let elements = amplitudes
+      .iter()
+      .flat_map(|row| amplitudes.iter().map(move |column| row * column.conj()))
+      .collect();
+    DensityMatrix {
+      num_qubits: state.num_qubits(),
+      elements: StateVector::from_amplitudes(elements),
+    }
+  }
+
+  /// A probabilistic mixture Σ p_i ρ_i of states, given as (p_i, ρ_i) pairs.
+  /// The states can be density matrices, or anything that converts into one, like a `Ket`.
+  ///
+  /// Panics if there are no states, or they have different numbers of qubits.
+  pub fn from_mixture<T: Into<DensityMatrix>>(states: Vec<(f64, T)>) -> DensityMatrix {
+    states
+      .into_iter()
+      .map(|(probability, state)| state.into() * probability)
+      .reduce(|sum, state| sum + state)
+      .expect("a mixture needs at least one state")
+  }
+
+  /// Creates a density matrix from its elements, given as a list of rows.
+  ///
+  /// Panics if the matrix is not square, with a power of two rows.
+  pub fn from_elements(rows: Vec<Vec<Complex64>>) -> DensityMatrix {
+    let dimension = rows.len();
+    assert!(
+      dimension.is_power_of_two() && rows.iter().all(|row| row.len() == dimension),
+      "a density matrix must be square, with a power of two rows"
+    );
+    DensityMatrix {
+      num_qubits: dimension.trailing_zeros() as usize,
+      elements: StateVector::from_amplitudes(rows.into_iter().flatten().collect()),
+    }
+  }
+
+  pub fn num_qubits(&self) -> usize {
+    self.num_qubits
+  }
+
+  /// The number of rows and columns, 2^n.
+  pub fn dimension(&self) -> usize {
+    1 << self.num_qubits
+  }
+
+  /// The element at (row, column), <row|ρ|column>.
+  pub fn element(&self, row: usize, column: usize) -> Complex64 {
+    assert!(
+      row < self.dimension() && column < self.dimension(),
+      "element ({}, {}) out of range for a {}x{1} density matrix",
+      row,
+      column,
+      self.dimension()
+    );
+    self.elements.amplitude(row * self.dimension() + column)
+  }
+
+  /// The elements as a list of rows.
+  pub fn rows(&self) -> Vec<Vec<Complex64>> {
+    self
+      .elements
+      .amplitudes()
+      .chunks(self.dimension())
+      .map(|row| row.to_vec())
+      .collect()
+  }
+
+  /// The sum of the diagonal elements. For a valid density matrix it is 1.
+  pub fn trace(&self) -> Complex64 {
+    (0..self.dimension()).map(|i| self.element(i, i)).sum()
+  }
+
+  /// Tr(ρ²), which is 1 for pure states, and goes down to 1/2^n for the maximally mixed state.
+  pub fn purity(&self) -> f64 {
+    // ρ is Hermitian, so Tr(ρ²) = Σ ρ_ij ρ_ji = Σ |ρ_ij|²
+    self
+      .elements
+      .amplitudes()
+      .iter()
+      .map(|x| x.norm_sqr())
+      .sum()
+  }
+
+  /// The eigenvalues of the matrix, in ascending order. For a valid density matrix,
+  /// these are the probabilities of the pure states it is a mixture of.
+  pub fn eigenvalues(&self) -> Vec<f64> {
+    hermitian_eigen(&self.rows())
+      .into_iter()
+      .map(|(value, _)| value)
+      .collect()
+  }
+
+  /// Whether the matrix equals its own conjugate transpose.
+  pub fn is_hermitian(&self) -> bool {
+    (0..self.dimension()).all(|row| {
+      (0..self.dimension())
+        .all(|column| (self.element(row, column) - self.element(column, row).conj()).norm() < 1e-10)
+    })
+  }
+
+  /// Whether the matrix has no negative eigenvalues.
+  pub fn is_positive_semidefinite(&self) -> bool {
+    self.is_hermitian() && self.eigenvalues().iter().all(|value| *value > -1e-10)
+  }
+
+  /// The fidelity (Tr sqrt(sqrt(ρ) σ sqrt(ρ)))² between two density matrices. It is 1 when they
+  /// are the same state and 0 when they are orthogonal, and for pure states it is |<ψ|φ>|².
+  /// When one of them is a pure state |ψ>, it is simply <ψ|σ|ψ>.
+  ///
+  /// Panics if the registers have different numbers of qubits.
+  pub fn fidelity(&self, other: &DensityMatrix) -> f64 {
+    assert_eq!(
+      self.num_qubits, other.num_qubits,
+      "cannot compare density matrices of different sizes"
+    );
+    let root = positive_sqrt(&self.rows());
+    let product = multiply(&multiply(&root, &other.rows()), &root);
+    let trace: f64 = hermitian_eigen(&product)
+      .iter()
+      .map(|(value, _)| value.max(0.0).sqrt())
+      .sum();
+    trace * trace
+  }
+
+  /// The trace distance ½ Tr|ρ - σ|, which is half the sum of the absolute values of the
+  /// eigenvalues of ρ - σ. It is the largest difference there can be between the
+  /// probabilities of any measurement outcome on the two states.
+  ///
+  /// Panics if the registers have different numbers of qubits.
+  pub fn trace_distance(&self, other: &DensityMatrix) -> f64 {
+    assert_eq!(
+      self.num_qubits, other.num_qubits,
+      "cannot compare density matrices of different sizes"
+    );
+    let difference: Vec<Vec<Complex64>> = self
+      .rows()
+      .iter()
+      .zip(other.rows())
+      .map(|(a, b)| a.iter().zip(b).map(|(x, y)| x - y).collect())
+      .collect();
+    hermitian_eigen(&difference)
+      .iter()
+      .map(|(value, _)| value.abs())
+      .sum::<f64>()
+      / 2.0
+  }
+
+  /// The probability of measuring each basis state, which are the diagonal elements.
+  pub fn probabilities(&self) -> Vec<f64> {
+    (0..self.dimension())
+      .map(|i| self.element(i, i).re)
+      .collect()
+  }
+
+  /// Applies a single-qubit gate to the target qubit: ρ → UρU†.
+  pub fn apply(&mut self, gate: &Gate, target: usize) {
+    self.apply_controlled(gate, &[], target);
+  }
+
+  /// Applies a gate to the target qubit, when all of the control qubits are |1>.
+  pub fn apply_controlled(&mut self, gate: &Gate, controls: &[usize], target: usize) {
+    self.check_qubits(controls.iter().chain(&[target]));
+    self.elements.apply_controlled(gate, controls, target);
+    let (controls, target) = self.column_qubits(controls, target);
+    self
+      .elements
+      .apply_controlled(&conjugate(gate), &controls, target);
+  }
+
+  /// Swaps qubits `a` and `b`, when all of the control qubits are |1>.
+  pub fn apply_controlled_swap(&mut self, controls: &[usize], a: usize, b: usize) {
+    self.check_qubits(controls.iter().chain(&[a, b]));
+    self.elements.apply_controlled_swap(controls, a, b);
+    let n = self.num_qubits;
+    let controls: Vec<usize> = controls.iter().map(|control| control + n).collect();
+    self.elements.apply_controlled_swap(&controls, a + n, b + n);
+  }
+
+  /// Swaps the states of qubits `a` and `b`.
+  pub fn swap(&mut self, a: usize, b: usize) {
+    self.apply_controlled_swap(&[], a, b);
+  }
+
+  /// The iSWAP gate: swaps qubits `a` and `b`, multiplying |01> and |10> by i.
+  pub fn iswap(&mut self, a: usize, b: usize) {
+    let (o, l, i) = (COMPLEX_ZERO, COMPLEX_ONE, Complex64::new(0.0, 1.0));
+    let matrix = vec![
+      vec![l, o, o, o],
+      vec![o, o, i, o],
+      vec![o, i, o, o],
+      vec![o, o, o, l],
+    ];
+    self.apply_unitary(&matrix, &[a, b]);
+  }
+
+  /// Applies a unitary matrix on several qubits, with `qubits[0]` as the most significant bit.
+  pub fn apply_unitary(&mut self, matrix: &[Vec<Complex64>], qubits: &[usize]) {
+    self.check_qubits(qubits);
+    self.elements.apply_unitary(matrix, qubits);
+    let conjugate: Vec<Vec<Complex64>> = matrix
+      .iter()
+      .map(|row| row.iter().map(|x| x.conj()).collect())
+      .collect();
+    let qubits: Vec<usize> = qubits.iter().map(|qubit| qubit + self.num_qubits).collect();
+    self.elements.apply_unitary(&conjugate, &qubits);
+  }
+
+  /// Applies a noise channel to the target qubit: ρ → Σ K_i ρ K_i†.
+  pub fn apply_channel(&mut self, channel: &Channel, target: usize) {
+    self.check_qubits(&[target]);
+    let mut sum = self.clone() * 0.0;
+    for operator in channel.kraus_operators() {
+      let mut term = self.clone();
+      term.apply(operator, target);
+      sum = sum + term;
+    }
+    *self = sum;
+  }
+
+  /// The probability that measuring the qubit gives |1>.
+  pub fn probability_of_one(&self, qubit: usize) -> f64 {
+    let mask = self.qubit_mask(qubit);
+    (0..self.dimension())
+      .filter(|i| i & mask != 0)
+      .map(|i| self.element(i, i).re)
+      .sum()
+  }
+
+  /// Measures a single qubit in the computational basis.
+  /// Returns `false` for |0> and `true` for |1>, with Born rule probabilities,
+  /// and collapses the state to match.
+  pub fn measure<R: RngCore + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> bool {
+    let one = rng.gen::<f64>() < self.probability_of_one(qubit);
+    self.project(qubit, one);
+    one
+  }
+
+  /// Collapses the qubit to |0> or |1>, without picking the outcome at random:
+  /// ρ → PρP / Tr(PρP) for the projector P onto the outcome.
+  ///
+  /// Panics if the outcome is impossible.
+  pub fn project(&mut self, qubit: usize, one: bool) {
+    let projector = if one {
+      Gate::new([[COMPLEX_ZERO, COMPLEX_ZERO], [COMPLEX_ZERO, COMPLEX_ONE]])
+    } else {
+      Gate::new([[COMPLEX_ONE, COMPLEX_ZERO], [COMPLEX_ZERO, COMPLEX_ZERO]])
+    };
+    self.apply(&projector, qubit);
+    let trace = self.trace().re;
+    assert!(
+      trace > 0.0,
+      "outcome {} of qubit {} is impossible",
+      one as u8,
+      qubit
+    );
+    self.elements = self.elements.clone() * Complex64::new(1.0 / trace, 0.0);
+  }
+
+  pub(crate) fn qubit_mask(&self, qubit: usize) -> usize {
+    assert!(
+      qubit < self.num_qubits,
+      "qubit {} out of range for a register of {} qubits",
+      qubit,
+      self.num_qubits
+    );
+    1 << (self.num_qubits - 1 - qubit)
+  }
+
+  fn check_qubits<'a, I: IntoIterator<Item = &'a usize>>(&self, qubits: I) {
+    for qubit in qubits {
+      self.qubit_mask(*qubit);
+    }
+  }
+
+  // The qubits of the stored state vector that index the columns of the matrix
+  fn column_qubits(&self, controls: &[usize], target: usize) -> (Vec<usize>, usize) {
+    let n = self.num_qubits;
+    (
+      controls.iter().map(|control| control + n).collect(),
+      target + n,
+    )
+  }
+}
+
+// The complex conjugate of a gate, element by element (not the conjugate transpose)
+fn conjugate(gate: &Gate) -> Gate {
+  let m = gate.matrix();
+  Gate::new([
+    [m[0][0].conj(), m[0][1].conj()],
+    [m[1][0].conj(), m[1][1].conj()],
+  ])
+}
+
+// A density matrix follows every branch of a noise channel at once, so only measurements
+// are random
+impl Backend for DensityMatrix {
+  fn num_qubits(&self) -> usize {
+    self.num_qubits
+  }
+
+  fn apply_gate(
+    &mut self,
+    kind: &GateKind,
+    controls: &[usize],
+    target: usize,
+  ) -> Result<(), SimulationError> {
+    self.apply_controlled(&kind.gate(), controls, target);
+    Ok(())
+  }
+
+  fn apply_controlled_swap(
+    &mut self,
+    controls: &[usize],
+    a: usize,
+    b: usize,
+  ) -> Result<(), SimulationError> {
+    DensityMatrix::apply_controlled_swap(self, controls, a, b);
+    Ok(())
+  }
+
+  fn iswap(&mut self, a: usize, b: usize) -> Result<(), SimulationError> {
+    DensityMatrix::iswap(self, a, b);
+    Ok(())
+  }
+
+  fn apply_unitary(
+    &mut self,
+    matrix: &[Vec<Complex64>],
+    qubits: &[usize],
+  ) -> Result<(), SimulationError> {
+    DensityMatrix::apply_unitary(self, matrix, qubits);
+    Ok(())
+  }
+
+  fn measure<R: RngCore + ?Sized>(
+    &mut self,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<bool, SimulationError> {
+    Ok(DensityMatrix::measure(self, qubit, rng))
+  }
+
+  // Resetting is the same as letting the qubit decay all the way to |0>, no randomness needed
+  fn reset<R: RngCore + ?Sized>(
+    &mut self,
+    qubit: usize,
+    _rng: &mut R,
+  ) -> Result<(), SimulationError> {
+    DensityMatrix::apply_channel(self, &Channel::amplitude_damping(1.0), qubit);
+    Ok(())
+  }
+
+  fn apply_channel<R: RngCore + ?Sized>(
+    &mut self,
+    channel: &Channel,
+    qubit: usize,
+    _rng: &mut R,
+  ) -> Result<(), SimulationError> {
+    DensityMatrix::apply_channel(self, channel, qubit);
+    Ok(())
+  }
+}
+
+impl From<Ket> for DensityMatrix {
+  fn from(ket: Ket) -> DensityMatrix {
+    DensityMatrix::from_state(ket)
+  }
+}
+
+impl From<StateVector> for DensityMatrix {
+  fn from(state: StateVector) -> DensityMatrix {
+    DensityMatrix::from_state(state)
+  }
+}
+
+// Adding density matrices elementwise, which is how mixtures are built
+impl std::ops::Add<DensityMatrix> for DensityMatrix {
+  type Output = DensityMatrix;
+
+  fn add(self, other: DensityMatrix) -> DensityMatrix {
+    assert_eq!(
+      self.num_qubits, other.num_qubits,
+      "cannot add density matrices of different sizes"
+    );
+    DensityMatrix {
+      num_qubits: self.num_qubits,
+      elements: self.elements + other.elements,
+    }
+  }
+}
+
+// Scaling a density matrix by a probability
+impl std::ops::Mul<f64> for DensityMatrix {
+  type Output = DensityMatrix;
+
+  fn mul(self, scalar: f64) -> DensityMatrix {
+    DensityMatrix {
+      num_qubits: self.num_qubits,
+      elements: self.elements * Complex64::new(scalar, 0.0),
+    }
+  }
+}
+
+// A density matrix is valid when it is Hermitian, has trace 1, and is positive semidefinite.
+// Rounding errors pile up quickly on matrices, so these checks use a tolerance of 1e-10.
+impl ValidQuantumState for DensityMatrix {
+  fn is_valid(&self) -> bool {
+    (self.trace() - COMPLEX_ONE).norm() < 1e-10 && self.is_positive_semidefinite()
+  }
+}
+
+// Approximate equality, element by element: `approx_eq!(&DensityMatrix, &a, &b, epsilon = 1e-12)`.
+// A density matrix has no global phase, since e^(iα)|ψ><ψ|e^(-iα) = |ψ><ψ|, and for two of
+// them tr(ρσ) is never negative, so `up_to_global_phase` makes no difference here.
+impl<'a> ApproxEq for &'a DensityMatrix {
+  type Margin = ComplexMargin;
+
+  fn approx_eq<M: Into<ComplexMargin>>(self, other: &'a DensityMatrix, margin: M) -> bool {
+    margin
+      .into()
+      .all_approx_eq(self.elements.amplitudes(), other.elements.amplitudes())
+  }
+}
+
+#[cfg(test)]
+fn matrices_approx_equal(a: &DensityMatrix, b: &DensityMatrix) -> bool {
+  float_cmp::approx_eq!(&DensityMatrix, a, b, epsilon = 1e-12)
+}
+
+#[test]
+fn pure_state_is_outer_product() {
+  let plus = (KET_ZERO + KET_ONE) * Complex64::new(0.5f64.sqrt(), 0.0);
+  let rho = DensityMatrix::from(plus);
+  for row in 0..2 {
+    for column in 0..2 {
+      assert!((rho.element(row, column).re - 0.5).abs() < 1e-12);
+    }
+  }
+  assert!(rho.is_valid());
+  assert!((rho.purity() - 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn mixture_of_basis_states() {
+  let rho = DensityMatrix::from_mixture(vec![(0.5, KET_ZERO), (0.5, KET_ONE)]);
+  assert!(rho.is_valid());
+  assert!((rho.purity() - 0.5).abs() < 1e-12);
+  assert_eq!(rho.element(0, 1), COMPLEX_ZERO);
+  assert_eq!(rho.probabilities(), vec![0.5, 0.5]);
+  // Two qubits, maximally mixed
+  let states = (0..4)
+    .map(|index| (0.25, StateVector::from_basis_index(2, index)))
+    .collect();
+  let rho = DensityMatrix::from_mixture(states);
+  assert!((rho.purity() - 0.25).abs() < 1e-12);
+}
+
+#[test]
+fn invalid_matrices() {
+  let c = |re: f64, im: f64| Complex64::new(re, im);
+  // Not Hermitian
+  let rho = DensityMatrix::from_elements(vec![
+    vec![c(0.5, 0.0), c(0.1, 0.0)],
+    vec![c(0.2, 0.0), c(0.5, 0.0)],
+  ]);
+  assert!(!rho.is_valid());
+  // Trace 2
+  assert!(!(DensityMatrix::new(1) * 2.0).is_valid());
+  // A negative eigenvalue, even though the diagonal looks fine
+  let rho = DensityMatrix::from_elements(vec![
+    vec![c(0.5, 0.0), c(0.0, 0.8)],
+    vec![c(0.0, -0.8), c(0.5, 0.0)],
+  ]);
+  assert!(rho.is_hermitian());
+  assert!(!rho.is_positive_semidefinite());
+  assert!(!rho.is_valid());
+}
+
+#[test]
+fn gates_match_state_vector() {
+  use crate::gate::*;
+  let mut state = StateVector::new(3);
+  let mut rho = DensityMatrix::new(3);
+  state.apply(&HADAMARD, 0);
+  rho.apply(&HADAMARD, 0);
+  state.apply_controlled(&Gate::ry(0.7), &[0], 2);
+  rho.apply_controlled(&Gate::ry(0.7), &[0], 2);
+  state.apply(&Gate::u3(0.3, 1.2, -0.4), 1);
+  rho.apply(&Gate::u3(0.3, 1.2, -0.4), 1);
+  state.iswap(1, 2);
+  rho.iswap(1, 2);
+  state.apply_controlled_swap(&[2], 0, 1);
+  rho.apply_controlled_swap(&[2], 0, 1);
+  state.apply_controlled(&S_GATE, &[1, 2], 0);
+  rho.apply_controlled(&S_GATE, &[1, 2], 0);
+  assert!(matrices_approx_equal(
+    &rho,
+    &DensityMatrix::from_state(state)
+  ));
+  assert!(rho.is_valid());
+}
+
+#[test]
+fn measure_mixed_state() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(13);
+  let mixture = DensityMatrix::from_mixture(vec![(0.25, KET_ZERO), (0.75, KET_ONE)]);
+  assert!((mixture.probability_of_one(0) - 0.75).abs() < 1e-12);
+  let mut ones = 0;
+  for _ in 0..1000 {
+    let mut rho = mixture.clone();
+    let one = rho.measure(0, &mut rng);
+    ones += one as usize;
+    // After the measurement the state is pure again
+    assert!(matrices_approx_equal(
+      &rho,
+      &DensityMatrix::from(if one { KET_ONE } else { KET_ZERO })
+    ));
+  }
+  assert!((700..800).contains(&ones), "{}", ones);
+}
+
+#[test]
+fn decoherence_lowers_purity() {
+  use crate::gate::*;
+  // Entangle a qubit with a second one and forget about the second: the first is left mixed
+  let mut state = StateVector::new(2);
+  state.apply(&HADAMARD, 0);
+  state.apply_controlled(&PAULI_X, &[0], 1);
+  let rho = DensityMatrix::from_state(state);
+  assert!((rho.purity() - 1.0).abs() < 1e-12);
+  let mut dephased = rho.clone();
+  dephased.project(1, false);
+  let mixed = DensityMatrix::from_mixture(vec![
+    (0.5, dephased),
+    (0.5, {
+      let mut other = rho;
+      other.project(1, true);
+      other
+    }),
+  ]);
+  assert!((mixed.purity() - 0.5).abs() < 1e-12);
+  assert!(mixed.is_valid());
+}
+
+#[test]
+fn channels_on_density_matrices() {
+  use crate::gate::*;
+  let c = |re: f64| Complex64::new(re, 0.0);
+  // Amplitude damping lets |1> decay towards |0>
+  let mut rho = DensityMatrix::from(KET_ONE);
+  rho.apply_channel(&Channel::amplitude_damping(0.3), 0);
+  assert!((rho.element(0, 0) - c(0.3)).norm() < 1e-12);
+  assert!((rho.element(1, 1) - c(0.7)).norm() < 1e-12);
+  // Phase damping and depolarizing both shrink the coherences of |+>
+  let mut plus = DensityMatrix::new(2);
+  plus.apply(&HADAMARD, 1);
+  let mut damped = plus.clone();
+  damped.apply_channel(&Channel::phase_damping(0.36), 1);
+  assert!((damped.element(0, 1) - c(0.4)).norm() < 1e-12);
+  assert!((damped.element(0, 0) - c(0.5)).norm() < 1e-12);
+  plus.apply_channel(&Channel::depolarizing(0.75), 1);
+  assert!((plus.purity() - 0.5).abs() < 1e-12);
+  assert!(plus.is_valid() && damped.is_valid());
+  // The bit flip only touches its own qubit
+  let mut rho = DensityMatrix::new(2);
+  rho.apply_channel(&Channel::bit_flip(0.25), 0);
+  for (p, expected) in rho.probabilities().iter().zip(&[0.75, 0.0, 0.25, 0.0]) {
+    assert!((p - expected).abs() < 1e-12);
+  }
+}
+
+#[test]
+fn fidelity_and_trace_distance() {
+  let plus = (KET_ZERO + KET_ONE).normalized();
+  let tilted = Ket::from_bloch(0.7, 0.3);
+  let pure = DensityMatrix::from(plus);
+  // For pure states, these match the formulas on kets
+  let other = DensityMatrix::from(tilted);
+  assert!((pure.fidelity(&other) - plus.fidelity(&tilted)).abs() < 1e-10);
+  assert!((pure.trace_distance(&other) - plus.trace_distance(&tilted)).abs() < 1e-10);
+  assert!((pure.fidelity(&pure) - 1.0).abs() < 1e-10);
+  assert!(pure.trace_distance(&pure) < 1e-10);
+  // For diagonal matrices, they are the classical fidelity and total variation distance
+  let p = DensityMatrix::from_mixture(vec![(0.9, KET_ZERO), (0.1, KET_ONE)]);
+  let q = DensityMatrix::from_mixture(vec![(0.4, KET_ZERO), (0.6, KET_ONE)]);
+  let classical = (0.9f64 * 0.4).sqrt() + (0.1f64 * 0.6).sqrt();
+  assert!((p.fidelity(&q) - classical * classical).abs() < 1e-10);
+  assert!((q.fidelity(&p) - classical * classical).abs() < 1e-10);
+  assert!((p.trace_distance(&q) - 0.5).abs() < 1e-10);
+  // With a pure state, the fidelity is <ψ|σ|ψ>
+  let mixed = DensityMatrix::from_mixture(vec![(0.3, plus), (0.7, tilted)]);
+  let expected = 0.3 + 0.7 * plus.fidelity(&tilted);
+  assert!((pure.fidelity(&mixed) - expected).abs() < 1e-10);
+  assert!((mixed.fidelity(&pure) - expected).abs() < 1e-10);
+}
@@ -0,0 +1,437 @@
+use crate::approx::{is_identity, ComplexMargin};
+use crate::ket::*;
+#[cfg(test)]
+use float_cmp::approx_eq;
+use float_cmp::ApproxEq;
+use num_complex::Complex64;
+use std::f64::consts::FRAC_1_SQRT_2;
+
+/// A single-qubit gate is a 2x2 matrix of complex numbers.
+/// Applying a gate to a ket is a matrix-vector multiplication:
+/// the first row produces the new "first" amplitude, and the second row the new "second".
+#[derive(Debug, Copy, Clone)]
+pub struct Gate {
+  pub(crate) matrix: [[Complex64; 2]; 2],
+}
+
+impl Gate {
+  /// Creates a gate from its matrix, given as rows.
+  /// The matrix should be unitary; see `Unitary::is_unitary`.
+  pub const fn new(matrix: [[Complex64; 2]; 2]) -> Gate {
+    Gate { matrix }
+  }
+
+  /// The matrix of the gate, given as rows.
+  pub fn matrix(&self) -> [[Complex64; 2]; 2] {
+    self.matrix
+  }
+
+  /// Applies the gate to a ket, producing a new ket.
+  pub fn apply(&self, ket: Ket) -> Ket {
+    let m = &self.matrix;
+    Ket {
+      first: m[0][0] * ket.first + m[0][1] * ket.second,
+      second: m[1][0] * ket.first + m[1][1] * ket.second,
+    }
+  }
+
+  /// Rotation by `theta` radians around the X axis of the Bloch sphere.
+  pub fn rx(theta: f64) -> Gate {
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    Gate::new([
+      [Complex64::from(cos), Complex64::new(0.0, -sin)],
+      [Complex64::new(0.0, -sin), Complex64::from(cos)],
+    ])
+  }
+
+  /// Rotation by `theta` radians around the Y axis of the Bloch sphere.
+  pub fn ry(theta: f64) -> Gate {
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    Gate::new([
+      [Complex64::from(cos), Complex64::from(-sin)],
+      [Complex64::from(sin), Complex64::from(cos)],
+    ])
+  }
+
+  /// Rotation by `theta` radians around the Z axis of the Bloch sphere.
+  /// Differs from `Gate::phase(theta)` only by the global phase e^(-iθ/2).
+  pub fn rz(theta: f64) -> Gate {
+    Gate::new([
+      [Complex64::from_polar(1.0, -theta / 2.0), COMPLEX_ZERO],
+      [COMPLEX_ZERO, Complex64::from_polar(1.0, theta / 2.0)],
+    ])
+  }
+
+  /// The phase gate, multiplying the |1> amplitude by e^(iλ).
+  /// `S_GATE` and `T_GATE` are phase gates with λ = π/2 and λ = π/4.
+  pub fn phase(lambda: f64) -> Gate {
+    Gate::new([
+      [COMPLEX_ONE, COMPLEX_ZERO],
+      [COMPLEX_ZERO, Complex64::from_polar(1.0, lambda)],
+    ])
+  }
+
+  /// The general single-qubit gate U3(θ, φ, λ) = Rz(φ) Ry(θ) Rz(λ), up to a global phase.
+  /// Every single-qubit gate can be written in this form, times a global phase.
+  pub fn u3(theta: f64, phi: f64, lambda: f64) -> Gate {
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    Gate::new([
+      [Complex64::from(cos), -Complex64::from_polar(sin, lambda)],
+      [
+        Complex64::from_polar(sin, phi),
+        Complex64::from_polar(cos, phi + lambda),
+      ],
+    ])
+  }
+
+  /// Multiplies the whole state by e^(iα). This can not be observed on its own,
+  /// but it matters when the gate is controlled by another qubit.
+  pub fn global_phase(alpha: f64) -> Gate {
+    let phase = Complex64::from_polar(1.0, alpha);
+    Gate::new([[phase, COMPLEX_ZERO], [COMPLEX_ZERO, phase]])
+  }
+
+  /// Finds the angles that write this gate as e^(iα) U3(θ, φ, λ).
+  /// Returns `(theta, phi, lambda, alpha)`. This is the inverse of `Gate::u3`, with the global
+  /// phase α split off; when θ is 0 or π only the sum or difference of φ and λ matters,
+  /// and φ is chosen to be 0.
+  pub fn u3_angles(&self) -> (f64, f64, f64, f64) {
+    let [[a, b], [c, d]] = self.matrix;
+    let theta = 2.0 * c.norm().atan2(a.norm());
+    if a.norm() < 1e-12 {
+      // A half turn: U3(π, φ, λ) = [[0, -e^(iλ)], [e^(iφ), 0]]
+      let alpha = c.arg();
+      (theta, 0.0, (-b).arg() - alpha, alpha)
+    } else if c.norm() < 1e-12 {
+      // No turn at all: U3(0, φ, λ) = [[1, 0], [0, e^(i(φ + λ))]]
+      let alpha = a.arg();
+      (theta, 0.0, d.arg() - alpha, alpha)
+    } else {
+      let alpha = a.arg();
+      (theta, c.arg() - alpha, (-b).arg() - alpha, alpha)
+    }
+  }
+
+  /// The conjugate transpose U† of the gate. For a unitary gate, this is also its inverse.
+  pub fn dagger(&self) -> Gate {
+    let m = &self.matrix;
+    Gate {
+      matrix: [
+        [m[0][0].conj(), m[1][0].conj()],
+        [m[0][1].conj(), m[1][1].conj()],
+      ],
+    }
+  }
+}
+
+// Some helper constants for writing out the matrices
+const COMPLEX_MINUS_ONE: Complex64 = Complex64 { re: -1.0, im: 0.0 };
+const COMPLEX_I: Complex64 = Complex64 { re: 0.0, im: 1.0 };
+const COMPLEX_MINUS_I: Complex64 = Complex64 { re: 0.0, im: -1.0 };
+pub(crate) const COMPLEX_SQRT_HALF: Complex64 = Complex64 {
+  re: FRAC_1_SQRT_2,
+  im: 0.0,
+};
+pub(crate) const COMPLEX_MINUS_SQRT_HALF: Complex64 = Complex64 {
+  re: -FRAC_1_SQRT_2,
+  im: 0.0,
+};
+
+/// The identity gate. Does nothing.
+pub const IDENTITY: Gate = Gate::new([[COMPLEX_ONE, COMPLEX_ZERO], [COMPLEX_ZERO, COMPLEX_ONE]]);
+
+/// The Pauli X gate, also known as the NOT gate. Swaps |0> and |1>.
+pub const PAULI_X: Gate = Gate::new([[COMPLEX_ZERO, COMPLEX_ONE], [COMPLEX_ONE, COMPLEX_ZERO]]);
+
+/// The Pauli Y gate. Maps |0> to i|1> and |1> to -i|0>.
+pub const PAULI_Y: Gate = Gate::new([[COMPLEX_ZERO, COMPLEX_MINUS_I], [COMPLEX_I, COMPLEX_ZERO]]);
+
+/// The Pauli Z gate. Flips the sign of the |1> amplitude.
+pub const PAULI_Z: Gate = Gate::new([
+  [COMPLEX_ONE, COMPLEX_ZERO],
+  [COMPLEX_ZERO, COMPLEX_MINUS_ONE],
+]);
+
+/// The Hadamard gate. Maps |0> to (|0> + |1>)/√2 and |1> to (|0> - |1>)/√2.
+pub const HADAMARD: Gate = Gate::new([
+  [COMPLEX_SQRT_HALF, COMPLEX_SQRT_HALF],
+  [COMPLEX_SQRT_HALF, COMPLEX_MINUS_SQRT_HALF],
+]);
+
+/// The S gate, a quarter turn around the Z axis. Multiplies the |1> amplitude by i.
+pub const S_GATE: Gate = Gate::new([[COMPLEX_ONE, COMPLEX_ZERO], [COMPLEX_ZERO, COMPLEX_I]]);
+
+/// The inverse of the S gate. Multiplies the |1> amplitude by -i.
+pub const S_DAGGER: Gate =
+  Gate::new([[COMPLEX_ONE, COMPLEX_ZERO], [COMPLEX_ZERO, COMPLEX_MINUS_I]]);
+
+/// The T gate, an eighth turn around the Z axis. Multiplies the |1> amplitude by e^(iπ/4).
+pub const T_GATE: Gate = Gate::new([
+  [COMPLEX_ONE, COMPLEX_ZERO],
+  [
+    COMPLEX_ZERO,
+    Complex64 {
+      re: FRAC_1_SQRT_2,
+      im: FRAC_1_SQRT_2,
+    },
+  ],
+]);
+
+/// The inverse of the T gate. Multiplies the |1> amplitude by e^(-iπ/4).
+pub const T_DAGGER: Gate = Gate::new([
+  [COMPLEX_ONE, COMPLEX_ZERO],
+  [
+    COMPLEX_ZERO,
+    Complex64 {
+      re: FRAC_1_SQRT_2,
+      im: -FRAC_1_SQRT_2,
+    },
+  ],
+]);
+
+// Exact equality, just like for Ket
+impl PartialEq for Gate {
+  fn eq(&self, other: &Self) -> bool {
+    self.matrix == other.matrix
+  }
+}
+impl Eq for Gate {}
+
+// Approximate equality of the matrices. Two gates that are equal up to a global phase
+// act the same on every state, which `up_to_global_phase = true` allows for.
+impl ApproxEq for Gate {
+  type Margin = ComplexMargin;
+
+  fn approx_eq<M: Into<ComplexMargin>>(self, other: Gate, margin: M) -> bool {
+    let flatten = |gate: Gate| {
+      [
+        gate.matrix[0][0],
+        gate.matrix[0][1],
+        gate.matrix[1][0],
+        gate.matrix[1][1],
+      ]
+    };
+    margin.into().all_approx_eq(&flatten(self), &flatten(other))
+  }
+}
+
+// Applying a gate can also be written as a multiplication, gate * ket
+use std::ops::Mul;
+impl Mul<Ket> for Gate {
+  type Output = Ket;
+
+  fn mul(self, rhs: Ket) -> Ket {
+    self.apply(rhs)
+  }
+}
+
+#[test]
+fn pauli_x_flips() {
+  assert!(PAULI_X * KET_ZERO == KET_ONE);
+  assert!(PAULI_X * KET_ONE == KET_ZERO);
+}
+
+#[test]
+fn identity_does_nothing() {
+  assert!(IDENTITY * KET_ZERO == KET_ZERO);
+  assert!(IDENTITY * KET_ONE == KET_ONE);
+}
+
+#[test]
+fn pauli_y_and_z() {
+  assert!(PAULI_Y * KET_ZERO == KET_ONE * COMPLEX_I);
+  assert!(PAULI_Y * KET_ONE == KET_ZERO * COMPLEX_MINUS_I);
+  assert!(PAULI_Z * KET_ZERO == KET_ZERO);
+  assert!(PAULI_Z * KET_ONE == KET_ONE * COMPLEX_MINUS_ONE);
+}
+
+#[test]
+fn hadamard_creates_superposition() {
+  let plus = HADAMARD * KET_ZERO;
+  assert!(plus == (KET_ZERO + KET_ONE) * COMPLEX_SQRT_HALF);
+  assert!(plus.is_valid());
+}
+
+// Gates compose by matrix multiplication: (a * b) applies b first, then a
+impl Mul<Gate> for Gate {
+  type Output = Gate;
+
+  fn mul(self, rhs: Gate) -> Gate {
+    let a = &self.matrix;
+    let b = &rhs.matrix;
+    let mut matrix = [[COMPLEX_ZERO; 2]; 2];
+    for (row, out) in matrix.iter_mut().enumerate() {
+      for (column, value) in out.iter_mut().enumerate() {
+        *value = a[row][0] * b[0][column] + a[row][1] * b[1][column];
+      }
+    }
+    Gate { matrix }
+  }
+}
+
+#[test]
+fn s_squared_is_z() {
+  assert!(S_GATE * S_GATE == PAULI_Z);
+}
+
+#[test]
+fn dagger_inverts() {
+  assert!(S_GATE.dagger() == S_DAGGER);
+  assert!(T_GATE.dagger() == T_DAGGER);
+  assert!(S_GATE * S_DAGGER == IDENTITY);
+}
+
+// Rotations are only equal up to rounding errors, and sometimes only up to a global phase
+#[cfg(test)]
+fn gates_approx_equal(a: Gate, b: Gate) -> bool {
+  approx_eq!(Gate, a, b, epsilon = 1e-12)
+}
+
+#[test]
+fn half_turns_flip_zero_to_one() {
+  use std::f64::consts::PI;
+  assert!((Gate::rx(PI) * KET_ZERO).equal_up_to_global_phase(&KET_ONE));
+  assert!((Gate::ry(PI) * KET_ZERO).equal_up_to_global_phase(&KET_ONE));
+  assert!((Gate::u3(PI, 0.0, PI) * KET_ZERO).equal_up_to_global_phase(&KET_ONE));
+  assert!((Gate::rz(PI) * KET_ZERO).equal_up_to_global_phase(&KET_ZERO));
+}
+
+#[test]
+fn rotations_match_fixed_gates() {
+  use std::f64::consts::PI;
+  assert!(gates_approx_equal(Gate::phase(PI / 2.0), S_GATE));
+  assert!(gates_approx_equal(Gate::phase(PI / 4.0), T_GATE));
+  assert!(gates_approx_equal(Gate::phase(-PI / 4.0), T_DAGGER));
+  assert!(gates_approx_equal(Gate::u3(PI / 2.0, 0.0, PI), HADAMARD));
+  assert!(gates_approx_equal(
+    Gate::global_phase(PI / 2.0) * Gate::rx(PI),
+    PAULI_X
+  ));
+  assert!(gates_approx_equal(
+    Gate::global_phase(PI / 2.0) * Gate::rz(PI),
+    PAULI_Z
+  ));
+}
+
+#[test]
+fn u3_decomposes_into_rotations() {
+  let (theta, phi, lambda) = (0.3, 1.1, -2.4);
+  let rotations =
+    Gate::global_phase((phi + lambda) / 2.0) * Gate::rz(phi) * Gate::ry(theta) * Gate::rz(lambda);
+  assert!(gates_approx_equal(Gate::u3(theta, phi, lambda), rotations));
+  assert!(gates_approx_equal(
+    Gate::u3(
+      theta,
+      -std::f64::consts::FRAC_PI_2,
+      std::f64::consts::FRAC_PI_2
+    ),
+    Gate::rx(theta)
+  ));
+}
+
+#[test]
+fn u3_angles_rebuild_gate() {
+  let gates = [
+    IDENTITY,
+    PAULI_X,
+    PAULI_Y,
+    PAULI_Z,
+    HADAMARD,
+    S_GATE,
+    T_DAGGER,
+    Gate::rx(0.3),
+    Gate::ry(-2.2),
+    Gate::rz(1.7),
+    Gate::u3(0.4, -1.1, 2.9),
+    Gate::global_phase(0.6) * Gate::u3(2.0, 0.5, 0.25),
+  ];
+  for gate in &gates {
+    let (theta, phi, lambda, alpha) = gate.u3_angles();
+    let rebuilt = Gate::global_phase(alpha) * Gate::u3(theta, phi, lambda);
+    assert!(gates_approx_equal(*gate, rebuilt), "{:?}", gate);
+  }
+}
+
+#[test]
+fn rotations_match_paulis_up_to_global_phase() {
+  use std::f64::consts::PI;
+  for (rotation, pauli) in &[
+    (Gate::rx(PI), PAULI_X),
+    (Gate::ry(PI), PAULI_Y),
+    (Gate::rz(PI), PAULI_Z),
+  ] {
+    assert!(!approx_eq!(Gate, *rotation, *pauli, epsilon = 1e-12));
+    assert!(approx_eq!(
+      Gate,
+      *rotation,
+      *pauli,
+      epsilon = 1e-12,
+      up_to_global_phase = true
+    ));
+  }
+  assert!(!approx_eq!(
+    Gate,
+    PAULI_X,
+    PAULI_Z,
+    epsilon = 1e-12,
+    up_to_global_phase = true
+  ));
+}
+
+#[test]
+fn t_squared_is_s() {
+  assert!(gates_approx_equal(T_GATE * T_GATE, S_GATE));
+  assert!(gates_approx_equal(T_DAGGER * T_DAGGER, S_DAGGER));
+}
+
+// Just like quantum states, gates have a validity constraint:
+// they must be unitary, meaning U†U = I. This is what keeps
+// valid quantum states valid after applying the gate.
+pub trait Unitary {
+  fn is_unitary(&self) -> bool;
+}
+
+impl Unitary for Gate {
+  fn is_unitary(&self) -> bool {
+    let product = self.dagger() * *self;
+    is_identity(&product.matrix, ComplexMargin::zero().epsilon(1e-12))
+  }
+}
+
+#[test]
+fn standard_gates_unitary() {
+  for gate in &[
+    IDENTITY, PAULI_X, PAULI_Y, PAULI_Z, HADAMARD, S_GATE, S_DAGGER, T_GATE, T_DAGGER,
+  ] {
+    assert!(gate.is_unitary());
+  }
+}
+
+#[test]
+fn rotations_unitary() {
+  for angle in &[0.0, 0.5, 1.0, -2.0, 3.5] {
+    assert!(Gate::rx(*angle).is_unitary());
+    assert!(Gate::ry(*angle).is_unitary());
+    assert!(Gate::rz(*angle).is_unitary());
+    assert!(Gate::phase(*angle).is_unitary());
+    assert!(Gate::global_phase(*angle).is_unitary());
+    assert!(Gate::u3(*angle, 2.0 * angle, -angle).is_unitary());
+  }
+}
+
+#[test]
+fn gate_not_unitary() {
+  let gate = Gate::new([[COMPLEX_ONE, COMPLEX_ONE], [COMPLEX_ZERO, COMPLEX_ONE]]);
+  assert!(!gate.is_unitary());
+}
+
+#[test]
+fn unitary_gates_keep_states_valid() {
+  let ket = Ket {
+    first: Complex64::from(0.6),
+    second: Complex64::from(0.8),
+  };
+  for gate in &[PAULI_Y, HADAMARD, T_GATE] {
+    assert!(gate.apply(ket).is_valid());
+  }
+}
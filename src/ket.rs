@@ -1,13 +1,20 @@
-use float_cmp::approx_eq;
+use crate::approx::ComplexMargin;
+use crate::basis::Basis;
+use crate::channel::Channel;
+use crate::gate::{Gate, COMPLEX_MINUS_SQRT_HALF, COMPLEX_SQRT_HALF};
+use crate::state_vector::StateVector;
+use float_cmp::{approx_eq, ApproxEq};
 use num_complex::Complex64;
+use rand::{Rng, RngCore};
+use std::fmt;
 
 /// A ket is a two-dimensional vector.
 /// It has two components, "first" and "second".
 /// These components, individually, are complex numbers.
 #[derive(Debug, Copy, Clone)]
 pub struct Ket {
-  first: Complex64,
-  second: Complex64,
+  pub(crate) first: Complex64,
+  pub(crate) second: Complex64,
 }
 
 // Let's define a couple of helper constants in the right type.
@@ -28,6 +35,250 @@ pub const KET_ONE: Ket = Ket {
   second: COMPLEX_ONE,
 };
 
+const I_SQRT_HALF: Complex64 = Complex64 {
+  re: 0.0,
+  im: std::f64::consts::FRAC_1_SQRT_2,
+};
+const MINUS_I_SQRT_HALF: Complex64 = Complex64 {
+  re: 0.0,
+  im: -std::f64::consts::FRAC_1_SQRT_2,
+};
+
+/// The ket (|0> + |1>)/√2, which the Hadamard gate makes out of |0>. Has the symbol |+>.
+pub const KET_PLUS: Ket = Ket {
+  first: COMPLEX_SQRT_HALF,
+  second: COMPLEX_SQRT_HALF,
+};
+
+/// The ket (|0> - |1>)/√2, which the Hadamard gate makes out of |1>. Has the symbol |->.
+pub const KET_MINUS: Ket = Ket {
+  first: COMPLEX_SQRT_HALF,
+  second: COMPLEX_MINUS_SQRT_HALF,
+};
+
+/// The ket (|0> + i|1>)/√2, on the Y axis of the Bloch sphere. Has the symbol |i>.
+pub const KET_PLUS_I: Ket = Ket {
+  first: COMPLEX_SQRT_HALF,
+  second: I_SQRT_HALF,
+};
+
+/// The ket (|0> - i|1>)/√2, on the negative Y axis of the Bloch sphere. Has the symbol |-i>.
+pub const KET_MINUS_I: Ket = Ket {
+  first: COMPLEX_SQRT_HALF,
+  second: MINUS_I_SQRT_HALF,
+};
+
+/// A ket that does not satisfy the norm constraint |first|² + |second|² = 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidStateError {
+  pub message: String,
+}
+
+impl InvalidStateError {
+  pub fn new<S: Into<String>>(message: S) -> InvalidStateError {
+    InvalidStateError {
+      message: message.into(),
+    }
+  }
+}
+
+impl fmt::Display for InvalidStateError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+impl std::error::Error for InvalidStateError {}
+
+impl Ket {
+  /// Creates the ket first|0> + second|1>, checking that it is a valid quantum state:
+  /// the squares of the amplitudes must sum up to 1, give or take rounding errors.
+  pub fn new(first: Complex64, second: Complex64) -> Result<Ket, InvalidStateError> {
+    let ket = Ket { first, second };
+    if ket.is_valid() {
+      Ok(ket)
+    } else {
+      Err(InvalidStateError::new(format!(
+        "the squares of the amplitudes of a ket must sum up to 1, but |{}|² + |{}|² = {}",
+        first,
+        second,
+        ket.norm_sqr()
+      )))
+    }
+  }
+
+  /// Creates the ket first|0> + second|1> without checking that it is valid. For code that
+  /// builds many kets that are known to be normalized, and for constants.
+  pub const fn new_unchecked(first: Complex64, second: Complex64) -> Ket {
+    Ket { first, second }
+  }
+
+  /// The ket scaled to length 1, which is the same physical state.
+  ///
+  /// Panics if both amplitudes are zero, since the zero vector is not a state at all.
+  pub fn normalized(&self) -> Ket {
+    let norm = self.norm_sqr().sqrt();
+    assert!(norm > 0.0, "cannot normalize a ket with zero amplitudes");
+    Ket {
+      first: self.first / norm,
+      second: self.second / norm,
+    }
+  }
+
+  fn norm_sqr(&self) -> f64 {
+    self.first.norm_sqr() + self.second.norm_sqr()
+  }
+
+  /// The length of the ket, sqrt(<ψ|ψ>). It is 1 for every valid state.
+  pub fn norm(&self) -> f64 {
+    self.norm_sqr().sqrt()
+  }
+
+  /// The conjugate transpose of the ket, the bra <ψ|.
+  pub fn dagger(&self) -> Bra {
+    Bra {
+      first: self.first.conj(),
+      second: self.second.conj(),
+    }
+  }
+
+  /// The inner product <self|other>, which is the same as `self.dagger() * other`.
+  pub fn inner(&self, other: &Ket) -> Complex64 {
+    self.dagger() * *other
+  }
+
+  /// The outer product |self><other|, as a 2x2 matrix. It is not unitary in general,
+  /// but it can be a Kraus operator, like |0><1| in amplitude damping.
+  pub fn outer(&self, other: &Ket) -> Gate {
+    *self * other.dagger()
+  }
+
+  /// The fidelity |<self|other>|² between two pure states: 1 when they are the same state,
+  /// even with a different global phase, and 0 when they are orthogonal.
+  pub fn fidelity(&self, other: &Ket) -> f64 {
+    self.inner(other).norm_sqr() / (self.norm_sqr() * other.norm_sqr())
+  }
+
+  /// The trace distance between two pure states, sqrt(1 - F). It is the largest difference
+  /// there can be between the probabilities of any measurement outcome on the two states.
+  pub fn trace_distance(&self, other: &Ket) -> f64 {
+    (1.0 - self.fidelity(other)).max(0.0).sqrt()
+  }
+
+  /// The tensor product |self> ⊗ |other>, a register where this ket is qubit 0.
+  /// For example, `KET_ZERO.tensor(KET_ONE)` is the two-qubit state |01>.
+  pub fn tensor<T: Into<StateVector>>(self, other: T) -> StateVector {
+    StateVector::from(self).tensor(other)
+  }
+
+  /// Measures the ket in the computational basis. Returns `false` for |0> and `true` for |1>,
+  /// with probabilities given by the squares of the amplitudes (the Born rule).
+  /// The ket collapses to the measured basis state, keeping the phase of its amplitude.
+  ///
+  /// All randomness comes from `rng`, so measurements are reproducible from a seeded generator.
+  pub fn measure<R: RngCore + ?Sized>(&mut self, rng: &mut R) -> bool {
+    let zero = self.first.norm_sqr();
+    let one = self.second.norm_sqr();
+    let outcome = rng.gen::<f64>() * (zero + one) < one;
+    if outcome {
+      self.first = COMPLEX_ZERO;
+      self.second /= one.sqrt();
+    } else {
+      self.first /= zero.sqrt();
+      self.second = COMPLEX_ZERO;
+    }
+    outcome
+  }
+
+  /// The probability of each outcome of measuring the ket in the basis, and the state it
+  /// collapses to. The state is the basis vector, with the phase of <b|ψ>. When an outcome
+  /// is impossible, its state is all zeros, like in `StateVector::measurement_outcomes`.
+  pub fn measurement_outcomes(&self, basis: &Basis) -> [(f64, Ket); 2] {
+    let norm = self.norm_sqr();
+    let outcome = |vector: Ket| {
+      let overlap = vector.inner(self);
+      if overlap == COMPLEX_ZERO {
+        return (0.0, Ket::new_unchecked(COMPLEX_ZERO, COMPLEX_ZERO));
+      }
+      (
+        overlap.norm_sqr() / norm,
+        vector * (overlap / overlap.norm()),
+      )
+    };
+    [outcome(basis.zero()), outcome(basis.one())]
+  }
+
+  /// Measures the ket in any orthonormal basis, like `Basis::X`. Returns `false` for the
+  /// first basis vector and `true` for the second, and collapses the ket to that vector.
+  /// In `Basis::Z` this is the same as `measure`.
+  pub fn measure_in<R: RngCore + ?Sized>(&mut self, basis: &Basis, rng: &mut R) -> bool {
+    let [(zero, zero_state), (one, one_state)] = self.measurement_outcomes(basis);
+    let outcome = rng.gen::<f64>() * (zero + one) < one;
+    *self = if outcome { one_state } else { zero_state };
+    outcome
+  }
+
+  /// Applies a noise channel along a single trajectory: one of the Kraus operators K_i is
+  /// picked with probability ‖K_i ψ‖², applied, and the ket renormalized.
+  /// Averaged over many runs, this gives the same mixed state as the channel itself.
+  /// Returns the index of the Kraus operator that was applied.
+  pub fn apply_channel<R: RngCore + ?Sized>(&mut self, channel: &Channel, rng: &mut R) -> usize {
+    let branches: Vec<Ket> = channel
+      .kraus_operators()
+      .iter()
+      .map(|operator| *operator * *self)
+      .collect();
+    let norms: Vec<f64> = branches
+      .iter()
+      .map(|ket| ket.first.norm_sqr() + ket.second.norm_sqr())
+      .collect();
+    let chosen = Channel::choose(&norms, rng);
+    *self = branches[chosen] * Complex64::new(1.0 / norms[chosen].sqrt(), 0.0);
+    chosen
+  }
+
+  /// The ket cos(θ/2)|0> + e^(iφ) sin(θ/2)|1>, at polar angle θ and azimuth φ on the
+  /// Bloch sphere. θ = 0 is |0> at the north pole, and θ = π is |1> at the south pole.
+  pub fn from_bloch(theta: f64, phi: f64) -> Ket {
+    Ket {
+      first: Complex64::from((theta / 2.0).cos()),
+      second: Complex64::from_polar((theta / 2.0).sin(), phi),
+    }
+  }
+
+  /// The point (x, y, z) on the Bloch sphere, which is the expectation values of the Pauli
+  /// X, Y and Z operators. The global phase of the ket does not change it.
+  pub fn bloch_vector(&self) -> (f64, f64, f64) {
+    let norm = self.first.norm_sqr() + self.second.norm_sqr();
+    let coherence = 2.0 * self.first.conj() * self.second / norm;
+    let z = (self.first.norm_sqr() - self.second.norm_sqr()) / norm;
+    (coherence.re, coherence.im, z)
+  }
+
+  /// The polar angle θ in [0, π] and the azimuth φ in (-π, π] on the Bloch sphere, so that
+  /// `Ket::from_bloch(θ, φ)` is the same state up to a global phase.
+  /// At the poles the azimuth does not matter, and is 0.
+  pub fn bloch_angles(&self) -> (f64, f64) {
+    let theta = 2.0 * self.second.norm().atan2(self.first.norm());
+    if self.first == COMPLEX_ZERO || self.second == COMPLEX_ZERO {
+      return (theta, 0.0);
+    }
+    // The relative phase of the two amplitudes, which is (a* b) / |a* b|
+    let phi = (self.first.conj() * self.second).arg();
+    (theta, phi)
+  }
+
+  /// Checks if two kets are the same physical state, that is if they are equal up to a
+  /// global phase e^(iα), which no measurement can tell apart.
+  pub fn equal_up_to_global_phase(&self, other: &Ket) -> bool {
+    let overlap = self.first.conj() * other.first + self.second.conj() * other.second;
+    let norms = (self.first.norm_sqr() + self.second.norm_sqr())
+      * (other.first.norm_sqr() + other.second.norm_sqr());
+    // By Cauchy-Schwarz |<a|b>|² <= <a|a><b|b>, with equality only if b is a multiple of a
+    approx_eq!(f64, overlap.norm_sqr(), norms, epsilon = 1e-12)
+  }
+}
+
 // Now we need to implement equality checking for our Ket
 impl PartialEq for Ket {
   fn eq(&self, other: &Self) -> bool {
@@ -36,6 +287,18 @@ impl PartialEq for Ket {
 }
 impl Eq for Ket {}
 
+// Exact equality breaks as soon as rounding errors creep in, so there is also approximate
+// equality, with a configurable margin: `approx_eq!(Ket, a, b, epsilon = 1e-12)`
+impl ApproxEq for Ket {
+  type Margin = ComplexMargin;
+
+  fn approx_eq<M: Into<ComplexMargin>>(self, other: Ket, margin: M) -> bool {
+    margin
+      .into()
+      .all_approx_eq(&[self.first, self.second], &[other.first, other.second])
+  }
+}
+
 // Let's test that our equality checking works
 #[test]
 fn ket_zero_equal_to_itself() {
@@ -52,6 +315,42 @@ fn ket_zero_not_equal_to_ket_one() {
   assert!(KET_ZERO != KET_ONE)
 }
 
+#[test]
+fn approximately_equal_kets() {
+  let half = Complex64::from(0.5f64.sqrt());
+  let plus = half * KET_ZERO + half * KET_ONE;
+  let rounded = crate::gate::HADAMARD * KET_ZERO;
+  assert!(approx_eq!(Ket, plus, rounded, ulps = 2));
+  assert!(approx_eq!(
+    Ket,
+    plus,
+    plus * Complex64::from(1.0 + 1e-10),
+    epsilon = 1e-9
+  ));
+  assert!(!approx_eq!(
+    Ket,
+    plus,
+    plus * Complex64::from(1.0 + 1e-10),
+    relative = 1e-11
+  ));
+  let minus_i = plus * Complex64::new(0.0, -1.0);
+  assert!(!approx_eq!(Ket, plus, minus_i, epsilon = 1e-12));
+  assert!(approx_eq!(
+    Ket,
+    plus,
+    minus_i,
+    epsilon = 1e-12,
+    up_to_global_phase = true
+  ));
+  assert!(!approx_eq!(
+    Ket,
+    KET_ZERO,
+    KET_ONE,
+    epsilon = 1e-12,
+    up_to_global_phase = true
+  ));
+}
+
 //  Let's implement adding two Kets together
 use std::ops::Add;
 impl Add for Ket {
@@ -147,12 +446,24 @@ pub trait ValidQuantumState {
 // The sums of the squares of the amplitudes must be equal to 1
 // Amplitude of a complex number x is |x|, available as .norm()
 // in the Complex64 type
+// Rounding errors from a few gates easily add up to more than a couple of ulps,
+// so anything within 1e-12 of 1 counts as well
 impl ValidQuantumState for Ket {
   fn is_valid(&self) -> bool {
     let a = self.first.norm();
     let b = self.second.norm();
     let result = (a * a) + (b * b);
-    approx_eq!(f64, result, 1.0, ulps = 2)
+    approx_eq!(f64, result, 1.0, epsilon = 1e-12, ulps = 2)
+  }
+}
+
+#[test]
+fn gates_keep_kets_valid() {
+  let mut ket = KET_ZERO;
+  for step in 0..50 {
+    let angle = 0.37 * step as f64;
+    ket = Gate::u3(angle, 1.3 * angle, -0.7) * ket;
+    assert!(ket.is_valid(), "invalid after step {}", step);
   }
 }
 
@@ -183,3 +494,238 @@ fn ket_arithmetic_valid() {
   let c = a + b;
   assert!(c.is_valid());
 }
+
+// Measurement is random, but with a seeded random number generator it is reproducible
+#[test]
+fn measure_basis_states() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+  for _ in 0..10 {
+    let mut zero = KET_ZERO;
+    let mut one = KET_ONE;
+    assert!(!zero.measure(&mut rng));
+    assert!(one.measure(&mut rng));
+    assert!(zero == KET_ZERO && one == KET_ONE);
+  }
+}
+
+#[test]
+fn measure_collapses() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+  let mut ket = Complex64::from(0.6) * KET_ZERO + Complex64::from(0.8) * KET_ONE;
+  let first = ket.measure(&mut rng);
+  assert!(ket == KET_ZERO || ket == KET_ONE);
+  for _ in 0..10 {
+    assert_eq!(ket.measure(&mut rng), first);
+  }
+}
+
+#[test]
+fn measure_follows_born_rule() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+  let ket = Complex64::from(0.6) * KET_ZERO + Complex64::from(0.8) * KET_ONE;
+  let ones = (0..10_000)
+    .filter(|_| ket.clone().measure(&mut rng))
+    .count();
+  // P(|1>) = 0.8 * 0.8 = 0.64
+  assert!((6200..6600).contains(&ones), "{} ones", ones);
+}
+
+#[test]
+fn bloch_coordinates_of_basis_states() {
+  use std::f64::consts::PI;
+  let (x, y, z) = KET_ZERO.bloch_vector();
+  assert!(x.abs() < 1e-12 && y.abs() < 1e-12 && (z - 1.0).abs() < 1e-12);
+  assert_eq!(KET_ONE.bloch_angles(), (PI, 0.0));
+  let half = Complex64::from(0.5f64.sqrt());
+  // |+> is on the x axis, and (|0> + i|1>)/√2 on the y axis
+  let (x, y, z) = (half * KET_ZERO + half * KET_ONE).bloch_vector();
+  assert!((x - 1.0).abs() < 1e-12 && y.abs() < 1e-12 && z.abs() < 1e-12);
+  let plus_i = half * KET_ZERO + half * Complex64::i() * KET_ONE;
+  let (theta, phi) = plus_i.bloch_angles();
+  assert!((theta - PI / 2.0).abs() < 1e-12 && (phi - PI / 2.0).abs() < 1e-12);
+}
+
+#[test]
+fn bloch_angles_round_trip() {
+  for (theta, phi) in &[(0.3, 1.2), (2.0, -2.5), (1.0, 3.0)] {
+    let ket = Ket::from_bloch(*theta, *phi);
+    assert!(ket.is_valid());
+    let (t, p) = ket.bloch_angles();
+    assert!((t - theta).abs() < 1e-12 && (p - phi).abs() < 1e-12);
+    let (x, y, z) = ket.bloch_vector();
+    assert!((x - theta.sin() * phi.cos()).abs() < 1e-12);
+    assert!((y - theta.sin() * phi.sin()).abs() < 1e-12);
+    assert!((z - theta.cos()).abs() < 1e-12);
+    // The global phase changes the amplitudes, but not the point on the sphere
+    let rotated = ket * Complex64::from_polar(1.0, 0.7);
+    let (t, p) = rotated.bloch_angles();
+    assert!((t - theta).abs() < 1e-12 && (p - phi).abs() < 1e-12);
+  }
+}
+
+#[test]
+fn comparison_up_to_global_phase() {
+  let ket = Ket::from_bloch(0.8, 0.4);
+  let rotated = ket * Complex64::from_polar(1.0, 2.1);
+  assert!(ket != rotated);
+  assert!(ket.equal_up_to_global_phase(&rotated));
+  assert!(KET_ONE.equal_up_to_global_phase(&(KET_ONE * -COMPLEX_ONE)));
+  assert!(!ket.equal_up_to_global_phase(&Ket::from_bloch(0.8, 0.5)));
+  assert!(!KET_ZERO.equal_up_to_global_phase(&KET_ONE));
+}
+
+#[test]
+fn construction_checks_the_norm() {
+  let ket = Ket::new(Complex64::from(0.6), Complex64::new(0.0, 0.8)).unwrap();
+  assert!(ket.is_valid());
+  let error = Ket::new(COMPLEX_ONE, COMPLEX_ONE).unwrap_err();
+  assert!(error.to_string().contains("= 2"), "{}", error);
+  assert!(Ket::new(COMPLEX_ZERO, COMPLEX_ZERO).is_err());
+  assert!(Ket::new(Complex64::from(f64::NAN), COMPLEX_ZERO).is_err());
+  // The unchecked constructor takes anything, even for constants
+  const TWICE_ONE: Ket = Ket::new_unchecked(COMPLEX_ZERO, Complex64 { re: 2.0, im: 0.0 });
+  assert!(!TWICE_ONE.is_valid());
+}
+
+#[test]
+fn normalizing_kets() {
+  let ket = (KET_ZERO + KET_ONE).normalized();
+  assert!(ket.is_valid());
+  assert!(approx_eq!(
+    Ket,
+    ket,
+    crate::gate::HADAMARD * KET_ZERO,
+    epsilon = 1e-15
+  ));
+  let ket = Ket::new_unchecked(Complex64::new(0.0, 3.0), Complex64::from(-4.0)).normalized();
+  assert!(ket == Ket::new(Complex64::new(0.0, 0.6), Complex64::from(-0.8)).unwrap());
+  assert!(KET_ONE.normalized() == KET_ONE);
+}
+
+#[test]
+#[should_panic]
+fn zero_ket_can_not_be_normalized() {
+  Ket::new_unchecked(COMPLEX_ZERO, COMPLEX_ZERO).normalized();
+}
+
+#[test]
+fn named_basis_states() {
+  use crate::gate::*;
+  let margin = ComplexMargin::zero().epsilon(1e-15);
+  assert!(approx_eq!(Ket, HADAMARD * KET_ZERO, KET_PLUS, margin));
+  assert!(approx_eq!(Ket, HADAMARD * KET_ONE, KET_MINUS, margin));
+  assert!(approx_eq!(Ket, S_GATE * KET_PLUS, KET_PLUS_I, margin));
+  assert!(approx_eq!(Ket, S_DAGGER * KET_PLUS, KET_MINUS_I, margin));
+  for ket in &[KET_PLUS, KET_MINUS, KET_PLUS_I, KET_MINUS_I] {
+    assert!(ket.is_valid());
+  }
+  assert!(KET_PLUS_I.inner(&KET_MINUS_I).norm() < 1e-15);
+  let (x, y, z) = KET_MINUS_I.bloch_vector();
+  assert!(x.abs() < 1e-15 && (y + 1.0).abs() < 1e-15 && z.abs() < 1e-15);
+}
+
+#[test]
+fn measurement_in_other_bases() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(34);
+  let [(zero, plus), (one, _)] = KET_ZERO.measurement_outcomes(&Basis::X);
+  assert!((zero - 0.5).abs() < 1e-12 && (one - 0.5).abs() < 1e-12);
+  assert!(approx_eq!(Ket, plus, KET_PLUS, epsilon = 1e-12));
+  let [(zero, _), (one, _)] = KET_PLUS_I.measurement_outcomes(&Basis::Y);
+  assert!((zero - 1.0).abs() < 1e-12 && one.abs() < 1e-12);
+  // An impossible outcome collapses to the zero vector, not to a basis vector
+  let [(_, impossible), (certain, _)] = KET_ONE.measurement_outcomes(&Basis::Z);
+  assert!((certain - 1.0).abs() < 1e-12);
+  assert!(impossible.first == COMPLEX_ZERO && impossible.second == COMPLEX_ZERO);
+  // The collapsed state keeps the phase of the amplitude, like in the Z basis
+  let mut ket = KET_MINUS * Complex64::new(0.0, 1.0);
+  assert!(ket.measure_in(&Basis::X, &mut rng));
+  assert!(ket == KET_MINUS * Complex64::new(0.0, 1.0));
+  let mut ones = 0;
+  for _ in 0..1000 {
+    let mut ket = KET_ZERO;
+    if ket.measure_in(&Basis::Y, &mut rng) {
+      ones += 1;
+      assert!(approx_eq!(Ket, ket, KET_MINUS_I, epsilon = 1e-12));
+    } else {
+      assert!(approx_eq!(Ket, ket, KET_PLUS_I, epsilon = 1e-12));
+    }
+  }
+  assert!((450..550).contains(&ones), "{} ones", ones);
+}
+
+/// A bra <ψ| is the conjugate transpose of the ket |ψ>: a row vector instead of a column.
+/// A bra times a ket is their inner product, and a ket times a bra their outer product.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bra {
+  pub(crate) first: Complex64,
+  pub(crate) second: Complex64,
+}
+
+impl Bra {
+  /// The ket that this is the conjugate transpose of.
+  pub fn dagger(&self) -> Ket {
+    Ket {
+      first: self.first.conj(),
+      second: self.second.conj(),
+    }
+  }
+}
+
+// <a| * |b> is the inner product <a|b>, a single complex number
+impl std::ops::Mul<Ket> for Bra {
+  type Output = Complex64;
+
+  fn mul(self, rhs: Ket) -> Complex64 {
+    self.first * rhs.first + self.second * rhs.second
+  }
+}
+
+// |a> * <b| is the outer product |a><b|, a 2x2 matrix
+impl std::ops::Mul<Bra> for Ket {
+  type Output = Gate;
+
+  fn mul(self, rhs: Bra) -> Gate {
+    Gate::new([
+      [self.first * rhs.first, self.first * rhs.second],
+      [self.second * rhs.first, self.second * rhs.second],
+    ])
+  }
+}
+
+#[test]
+fn inner_and_outer_products() {
+  let i = Complex64::new(0.0, 1.0);
+  let ket = Ket::new(Complex64::from(0.6), i * 0.8).unwrap();
+  assert!(ket.dagger().dagger() == ket);
+  assert!((ket.inner(&ket) - COMPLEX_ONE).norm() < 1e-12);
+  assert!((ket.norm() - 1.0).abs() < 1e-12);
+  assert!(KET_ZERO.inner(&KET_ONE) == COMPLEX_ZERO);
+  // <ψ|1> = conj(ψ_1)
+  assert!(ket.dagger() * KET_ONE == -i * 0.8);
+  assert!(KET_ZERO.outer(&KET_ONE) == Gate::new([[COMPLEX_ZERO, COMPLEX_ONE], [COMPLEX_ZERO; 2]]));
+  // Projecting onto |ψ> gives back |ψ>, and the projector is Hermitian
+  let projector = ket.outer(&ket);
+  assert!(approx_eq!(Ket, projector * ket, ket, epsilon = 1e-12));
+  assert!(approx_eq!(
+    Gate,
+    projector,
+    projector.dagger(),
+    epsilon = 1e-12
+  ));
+}
+
+#[test]
+fn fidelity_and_trace_distance_of_kets() {
+  let plus = (KET_ZERO + KET_ONE).normalized();
+  assert!((KET_ZERO.fidelity(&KET_ZERO) - 1.0).abs() < 1e-12);
+  assert!(KET_ZERO.fidelity(&KET_ONE).abs() < 1e-12);
+  assert!((KET_ZERO.fidelity(&plus) - 0.5).abs() < 1e-12);
+  assert!((plus.fidelity(&(plus * Complex64::new(0.0, -1.0))) - 1.0).abs() < 1e-12);
+  assert!((KET_ZERO.trace_distance(&KET_ONE) - 1.0).abs() < 1e-12);
+  assert!((KET_ZERO.trace_distance(&plus) - 0.5f64.sqrt()).abs() < 1e-12);
+  assert!(plus.trace_distance(&plus) < 1e-6);
+}
@@ -1,2 +1,18 @@
+pub mod approx;
+pub mod backend;
+pub mod basis;
+pub mod channel;
+pub mod circuit;
+pub mod density_matrix;
+pub mod gate;
 pub mod ket;
-use crate::ket::*;
+mod linalg;
+pub mod mps;
+pub mod noise;
+pub mod pauli;
+pub mod qasm;
+pub mod quil;
+pub mod sparse;
+pub mod stabilizer;
+pub mod state_vector;
+pub mod trajectories;
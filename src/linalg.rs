@@ -0,0 +1,249 @@
+use crate::ket::{COMPLEX_ONE, COMPLEX_ZERO};
+use num_complex::Complex64;
+
+// The bits of linear algebra the simulators need, on small dense complex matrices
+// stored as a list of rows.
+
+pub(crate) type Matrix = Vec<Vec<Complex64>>;
+
+pub(crate) fn identity(size: usize) -> Matrix {
+  (0..size)
+    .map(|i| {
+      (0..size)
+        .map(|j| if i == j { COMPLEX_ONE } else { COMPLEX_ZERO })
+        .collect()
+    })
+    .collect()
+}
+
+pub(crate) fn multiply(a: &[Vec<Complex64>], b: &[Vec<Complex64>]) -> Matrix {
+  a.iter()
+    .map(|row| {
+      (0..b[0].len())
+        .map(|j| row.iter().zip(b).map(|(x, b_row)| x * b_row[j]).sum())
+        .collect()
+    })
+    .collect()
+}
+
+/// The square root of a positive semidefinite Hermitian matrix: the one with the same
+/// eigenvectors, and the square roots of the eigenvalues. Eigenvalues that came out slightly
+/// negative from rounding errors count as zero.
+pub(crate) fn positive_sqrt(matrix: &[Vec<Complex64>]) -> Matrix {
+  let pairs = hermitian_eigen(matrix);
+  (0..matrix.len())
+    .map(|i| {
+      (0..matrix.len())
+        .map(|j| {
+          pairs
+            .iter()
+            .map(|(value, vector)| vector[i] * vector[j].conj() * value.max(0.0).sqrt())
+            .sum()
+        })
+        .collect()
+    })
+    .collect()
+}
+
+/// The eigenvalues and eigenvectors of a Hermitian matrix, found with the Jacobi method:
+/// rotate away the off-diagonal elements one pair at a time, until the matrix is diagonal.
+/// Returns (eigenvalue, eigenvector) pairs, with the eigenvalues in ascending order.
+pub(crate) fn hermitian_eigen(matrix: &[Vec<Complex64>]) -> Vec<(f64, Vec<Complex64>)> {
+  let size = matrix.len();
+  let mut a: Matrix = matrix.to_vec();
+  // The columns of `v` are the eigenvectors
+  let mut v = identity(size);
+  let scale: f64 = a.iter().flatten().map(|x| x.norm_sqr()).sum::<f64>().sqrt();
+  for _sweep in 0..100 {
+    let off_diagonal: f64 = (0..size)
+      .flat_map(|i| (0..size).filter(move |j| *j != i).map(move |j| (i, j)))
+      .map(|(i, j)| a[i][j].norm_sqr())
+      .sum::<f64>()
+      .sqrt();
+    if off_diagonal <= 1e-15 * scale {
+      break;
+    }
+    for p in 0..size {
+      for q in p + 1..size {
+        let magnitude = a[p][q].norm();
+        if magnitude <= 1e-300 {
+          continue;
+        }
+        // First a phase on q to make a[p][q] real, then an ordinary real rotation
+        let phase = a[p][q] / magnitude;
+        let tau = (a[q][q].re - a[p][p].re) / (2.0 * magnitude);
+        let t = tau.signum() / (tau.abs() + (1.0 + tau * tau).sqrt());
+        let c = 1.0 / (1.0 + t * t).sqrt();
+        let s = t * c;
+        // The rotation U, restricted to p and q, is [[c, s], [-s e^(-iφ), c e^(-iφ)]]
+        let u_qp = -s * phase.conj();
+        let u_qq = c * phase.conj();
+        for row in a.iter_mut().chain(v.iter_mut()) {
+          let (x, y) = (row[p], row[q]);
+          row[p] = x * c + y * u_qp;
+          row[q] = x * s + y * u_qq;
+        }
+        let (upper, lower) = a.split_at_mut(q);
+        for (x, y) in upper[p].iter_mut().zip(lower[0].iter_mut()) {
+          let (old_x, old_y) = (*x, *y);
+          *x = old_x * c + old_y * u_qp.conj();
+          *y = old_x * s + old_y * u_qq.conj();
+        }
+      }
+    }
+  }
+  let mut pairs: Vec<(f64, Vec<Complex64>)> = (0..size)
+    .map(|k| (a[k][k].re, v.iter().map(|row| row[k]).collect()))
+    .collect();
+  pairs.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+  pairs
+}
+
+/// The singular value decomposition A = U Σ V† of an m by n matrix, found with the one-sided
+/// Jacobi method: rotate pairs of columns of A until they are all orthogonal.
+/// Returns (U, σ, V), with the singular values in descending order. U is m by k and V is
+/// n by k, where k = n, so there may be some zero singular values at the end.
+pub(crate) fn svd(matrix: &[Vec<Complex64>]) -> (Matrix, Vec<f64>, Matrix) {
+  let rows = matrix.len();
+  let columns = matrix.first().map_or(0, |row| row.len());
+  // Work on the columns, which are the rows of the transpose
+  let mut a: Matrix = (0..columns)
+    .map(|j| matrix.iter().map(|row| row[j]).collect())
+    .collect();
+  let mut v = identity(columns);
+  let dot = |x: &[Complex64], y: &[Complex64]| -> Complex64 {
+    x.iter().zip(y).map(|(x, y)| x.conj() * y).sum()
+  };
+  for _sweep in 0..100 {
+    let mut rotated = false;
+    for p in 0..columns {
+      for q in p + 1..columns {
+        let alpha = dot(&a[p], &a[p]).re;
+        let beta = dot(&a[q], &a[q]).re;
+        let gamma = dot(&a[p], &a[q]);
+        let magnitude = gamma.norm();
+        if magnitude <= 1e-15 * (alpha * beta).sqrt() || magnitude <= 1e-300 {
+          continue;
+        }
+        rotated = true;
+        // A phase on column q makes the overlap real, and then a real rotation removes it
+        let phase = (gamma / magnitude).conj();
+        let zeta = (beta - alpha) / (2.0 * magnitude);
+        let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+        let c = 1.0 / (1.0 + t * t).sqrt();
+        let s = t * c;
+        for columns in [&mut a, &mut v] {
+          let (left, right) = columns.split_at_mut(q);
+          for (x, y) in left[p].iter_mut().zip(right[0].iter_mut()) {
+            let (old_x, old_y) = (*x, *y * phase);
+            *x = old_x * c - old_y * s;
+            *y = old_x * s + old_y * c;
+          }
+        }
+      }
+    }
+    if !rotated {
+      break;
+    }
+  }
+  // The columns of A V are now orthogonal, and their lengths are the singular values
+  let mut order: Vec<usize> = (0..columns).collect();
+  let norms: Vec<f64> = a
+    .iter()
+    .map(|column| dot(column, column).re.sqrt())
+    .collect();
+  order.sort_by(|i, j| norms[*j].partial_cmp(&norms[*i]).unwrap());
+  let u = (0..rows)
+    .map(|i| {
+      order
+        .iter()
+        .map(|k| {
+          if norms[*k] > 0.0 {
+            a[*k][i] / norms[*k]
+          } else {
+            COMPLEX_ZERO
+          }
+        })
+        .collect()
+    })
+    .collect();
+  let v = (0..columns)
+    .map(|i| order.iter().map(|k| v[*k][i]).collect())
+    .collect();
+  (u, order.iter().map(|k| norms[*k]).collect(), v)
+}
+
+#[test]
+fn eigen_of_pauli_y() {
+  let i = Complex64::new(0.0, 1.0);
+  let y = vec![vec![COMPLEX_ZERO, -i], vec![i, COMPLEX_ZERO]];
+  let pairs = hermitian_eigen(&y);
+  assert!((pairs[0].0 + 1.0).abs() < 1e-12);
+  assert!((pairs[1].0 - 1.0).abs() < 1e-12);
+  // Y v = λ v
+  for (value, vector) in &pairs {
+    for (row, component) in y.iter().zip(vector) {
+      let product: Complex64 = row.iter().zip(vector).map(|(m, x)| m * x).sum();
+      assert!((product - component * value).norm() < 1e-12);
+    }
+  }
+}
+
+#[test]
+fn eigen_decomposition_rebuilds_matrix() {
+  let c = |re: f64, im: f64| Complex64::new(re, im);
+  let matrix = vec![
+    vec![c(2.0, 0.0), c(0.5, 1.0), c(0.0, -0.3)],
+    vec![c(0.5, -1.0), c(1.0, 0.0), c(0.2, 0.2)],
+    vec![c(0.0, 0.3), c(0.2, -0.2), c(-1.0, 0.0)],
+  ];
+  let pairs = hermitian_eigen(&matrix);
+  for i in 0..3 {
+    for j in 0..3 {
+      let rebuilt: Complex64 = pairs
+        .iter()
+        .map(|(value, vector)| vector[i] * vector[j].conj() * value)
+        .sum();
+      assert!((rebuilt - matrix[i][j]).norm() < 1e-12, "{} {}", i, j);
+    }
+  }
+  assert!(pairs[0].0 <= pairs[1].0 && pairs[1].0 <= pairs[2].0);
+}
+
+#[test]
+fn svd_rebuilds_matrix() {
+  let c = |re: f64, im: f64| Complex64::new(re, im);
+  let matrix = vec![
+    vec![c(1.0, 0.5), c(0.0, -1.0), c(2.0, 0.0), c(0.3, 0.3)],
+    vec![c(-0.5, 0.0), c(1.0, 1.0), c(0.0, 0.2), c(1.0, -0.7)],
+  ];
+  let (u, singular_values, v) = svd(&matrix);
+  assert_eq!(singular_values.len(), 4);
+  assert!(singular_values.windows(2).all(|pair| pair[0] >= pair[1]));
+  // Only two of them can be nonzero
+  assert!(singular_values[2] < 1e-12 && singular_values[3] < 1e-12);
+  for i in 0..2 {
+    for j in 0..4 {
+      let rebuilt: Complex64 = (0..4)
+        .map(|k| u[i][k] * singular_values[k] * v[j][k].conj())
+        .sum();
+      assert!((rebuilt - matrix[i][j]).norm() < 1e-12, "{} {}", i, j);
+    }
+  }
+}
+
+#[test]
+fn square_root_squares_back() {
+  let c = |re: f64, im: f64| Complex64::new(re, im);
+  let matrix = vec![
+    vec![c(0.5, 0.0), c(0.1, 0.2), c(0.0, 0.0)],
+    vec![c(0.1, -0.2), c(0.3, 0.0), c(0.05, 0.0)],
+    vec![c(0.0, 0.0), c(0.05, 0.0), c(0.2, 0.0)],
+  ];
+  let root = positive_sqrt(&matrix);
+  for (row, expected) in multiply(&root, &root).iter().zip(&matrix) {
+    for (x, y) in row.iter().zip(expected) {
+      assert!((x - y).norm() < 1e-12);
+    }
+  }
+}
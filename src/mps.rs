@@ -0,0 +1,848 @@
+use crate::backend::{Backend, SimulationError};
+use crate::channel::Channel;
+use crate::circuit::GateKind;
+use crate::gate::Gate;
+use crate::ket::{COMPLEX_ONE, COMPLEX_ZERO};
+use crate::linalg::{identity, svd, Matrix};
+use crate::state_vector::StateVector;
+use num_complex::Complex64;
+use rand::{Rng, RngCore};
+use std::collections::BTreeMap;
+
+/// The state of a register of qubits as a matrix product state (MPS): a chain of tensors,
+/// one for each qubit, connected by bonds. The amplitude of a basis state is the product of
+/// one matrix from each tensor, picked by the bit of its qubit.
+///
+/// The size of the bonds grows with the entanglement between the two halves of the chain
+/// they connect. Circuits on a line of qubits that stay only a little entangled need small
+/// bonds, and can run on far more qubits than a state vector could hold.
+///
+/// After every gate on more than one qubit, the bonds are cut down with a singular value
+/// decomposition. Singular values σ with σ² below the truncation threshold are dropped, and
+/// so are any beyond the maximum bond dimension. The weight Σσ² of everything dropped adds up
+/// into `truncation_error`, which is zero as long as the simulation is exact.
+///
+/// Gates on qubits that are not next to each other are done by swapping the qubits next to
+/// each other first, and back again afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixProductState {
+  sites: Vec<Site>,
+  max_bond_dimension: usize,
+  truncation_threshold: f64,
+  truncation_error: f64,
+  // The site where the state is not orthonormal. Every site to the left of it is left
+  // orthonormal, and every site to the right of it right orthonormal, which makes the
+  // truncations optimal and lets measurements look at a single site.
+  center: usize,
+}
+
+// The tensor of one qubit. Element (bit, left, right) is at (bit * left + l) * right + r.
+#[derive(Debug, Clone, PartialEq)]
+struct Site {
+  left: usize,
+  right: usize,
+  elements: Vec<Complex64>,
+}
+
+impl Site {
+  fn get(&self, bit: usize, l: usize, r: usize) -> Complex64 {
+    self.elements[(bit * self.left + l) * self.right + r]
+  }
+
+  // The matrix of the site for one value of the bit
+  fn matrix(&self, bit: usize) -> &[Complex64] {
+    let size = self.left * self.right;
+    &self.elements[bit * size..(bit + 1) * size]
+  }
+
+  fn norm_sqr(&self, bit: usize) -> f64 {
+    self.matrix(bit).iter().map(|x| x.norm_sqr()).sum()
+  }
+
+  // Applies a single-qubit matrix to the bit
+  fn apply(&mut self, gate: &Gate) {
+    let m = gate.matrix();
+    let size = self.left * self.right;
+    for i in 0..size {
+      let (zero, one) = (self.elements[i], self.elements[size + i]);
+      self.elements[i] = m[0][0] * zero + m[0][1] * one;
+      self.elements[size + i] = m[1][0] * zero + m[1][1] * one;
+    }
+  }
+}
+
+impl MatrixProductState {
+  /// Creates a register of `num_qubits` qubits, all of them in the state |0>.
+  /// Bonds are never larger than `max_bond_dimension`, and singular values whose squares are
+  /// below `truncation_threshold` are dropped.
+  ///
+  /// Panics if there are no qubits, or the maximum bond dimension is zero.
+  pub fn new(
+    num_qubits: usize,
+    max_bond_dimension: usize,
+    truncation_threshold: f64,
+  ) -> MatrixProductState {
+    assert!(
+      num_qubits > 0,
+      "a matrix product state needs at least one qubit"
+    );
+    assert!(
+      max_bond_dimension > 0,
+      "the maximum bond dimension must be positive"
+    );
+    let site = Site {
+      left: 1,
+      right: 1,
+      elements: vec![COMPLEX_ONE, COMPLEX_ZERO],
+    };
+    MatrixProductState {
+      sites: vec![site; num_qubits],
+      max_bond_dimension,
+      truncation_threshold,
+      truncation_error: 0.0,
+      center: 0,
+    }
+  }
+
+  pub fn num_qubits(&self) -> usize {
+    self.sites.len()
+  }
+
+  pub fn max_bond_dimension(&self) -> usize {
+    self.max_bond_dimension
+  }
+
+  pub fn truncation_threshold(&self) -> f64 {
+    self.truncation_threshold
+  }
+
+  /// The total weight of the singular values dropped so far. Roughly, one minus the fidelity
+  /// of the simulated state with the exact one.
+  pub fn truncation_error(&self) -> f64 {
+    self.truncation_error
+  }
+
+  /// The dimension of each bond, between qubit i and i + 1.
+  pub fn bond_dimensions(&self) -> Vec<usize> {
+    self.sites[..self.sites.len() - 1]
+      .iter()
+      .map(|site| site.right)
+      .collect()
+  }
+
+  /// The amplitude of a single basis state, given by the bit of every qubit, qubit 0 first.
+  /// Unlike an index, this works for any number of qubits.
+  ///
+  /// Panics if there is not exactly one bit for each qubit.
+  pub fn amplitude(&self, bits: &[bool]) -> Complex64 {
+    assert!(
+      bits.len() == self.num_qubits(),
+      "a basis state of {} qubits needs {} bits, not {}",
+      self.num_qubits(),
+      self.num_qubits(),
+      bits.len()
+    );
+    let mut vector = vec![COMPLEX_ONE];
+    for (site, bit) in self.sites.iter().zip(bits) {
+      vector = multiply_vector(&vector, site, *bit as usize);
+    }
+    vector[0]
+  }
+
+  /// All of the amplitudes, as a state vector. This takes 2^n memory, like any state vector.
+  ///
+  /// Panics if there are too many qubits to index the state vector.
+  pub fn to_state_vector(&self) -> StateVector {
+    let n = self.num_qubits();
+    assert!(
+      n < usize::BITS as usize,
+      "too many qubits for a state vector"
+    );
+    StateVector::from_amplitudes(
+      (0..1 << n)
+        .map(|index| self.amplitude(&index_bits(index, n)))
+        .collect(),
+    )
+  }
+
+  /// Measures every qubit `shots` times, without collapsing the state, and counts how many
+  /// times each basis state was observed, like `StateVector::sample`.
+  ///
+  /// Each shot picks the bits one qubit at a time, from the probability of each bit given the
+  /// ones before it, so this never needs the full state vector.
+  pub fn sample<R: RngCore + ?Sized>(
+    &mut self,
+    shots: usize,
+    rng: &mut R,
+  ) -> BTreeMap<String, usize> {
+    // With the center on the first site, everything to the right of any site is orthonormal,
+    // so the probabilities only depend on the sites chosen so far
+    self.move_center(0);
+    let mut counts = BTreeMap::new();
+    for _ in 0..shots {
+      let mut vector = vec![COMPLEX_ONE];
+      let mut bits = String::new();
+      for site in &self.sites {
+        let zero = multiply_vector(&vector, site, 0);
+        let one = multiply_vector(&vector, site, 1);
+        let p_zero: f64 = zero.iter().map(|x| x.norm_sqr()).sum();
+        let p_one: f64 = one.iter().map(|x| x.norm_sqr()).sum();
+        let (bit, chosen, p) = if rng.gen::<f64>() * (p_zero + p_one) < p_one {
+          ('1', one, p_one)
+        } else {
+          ('0', zero, p_zero)
+        };
+        vector = chosen.iter().map(|x| x / p.sqrt()).collect();
+        bits.push(bit);
+      }
+      *counts.entry(bits).or_insert(0) += 1;
+    }
+    counts
+  }
+
+  /// Applies a single-qubit gate to the target qubit.
+  pub fn apply(&mut self, gate: &Gate, target: usize) {
+    self.check_qubit(target);
+    // A unitary keeps the site orthonormal, wherever the center is
+    self.sites[target].apply(gate);
+  }
+
+  /// Applies a unitary matrix on several qubits, with `qubits[0]` as the most significant bit.
+  pub fn apply_unitary(&mut self, matrix: &[Vec<Complex64>], qubits: &[usize]) {
+    assert!(
+      matrix.len() == 1 << qubits.len(),
+      "a unitary on {} qubits must be a {1}x{1} matrix",
+      qubits.len(),
+      1 << qubits.len()
+    );
+    for (i, qubit) in qubits.iter().enumerate() {
+      self.check_qubit(*qubit);
+      assert!(
+        !qubits[..i].contains(qubit),
+        "qubit {} used twice in the same gate",
+        qubit
+      );
+    }
+    // Bring the qubits next to each other, in order, right after the first one
+    let mut sorted = qubits.to_vec();
+    sorted.sort_unstable();
+    let first = sorted[0];
+    let mut swaps = Vec::new();
+    for (offset, qubit) in sorted.iter().enumerate().skip(1) {
+      for site in (first + offset..*qubit).rev() {
+        self.swap_sites(site);
+        swaps.push(site);
+      }
+    }
+    // The matrix has the bits in the order of `qubits`, but the sites are in sorted order
+    let position = |bits: usize| -> usize {
+      sorted.iter().enumerate().fold(0, |index, (k, qubit)| {
+        let bit = (bits >> (sorted.len() - 1 - k)) & 1;
+        let place = qubits.iter().position(|q| q == qubit).unwrap();
+        index | bit << (qubits.len() - 1 - place)
+      })
+    };
+    let size = matrix.len();
+    let permuted: Matrix = (0..size)
+      .map(|row| {
+        (0..size)
+          .map(|column| matrix[position(row)][position(column)])
+          .collect()
+      })
+      .collect();
+    self.apply_contiguous(&permuted, first, qubits.len());
+    for site in swaps.into_iter().rev() {
+      self.swap_sites(site);
+    }
+  }
+
+  /// Applies a gate to the target qubit, when all of the control qubits are |1>.
+  pub fn apply_controlled(&mut self, gate: &Gate, controls: &[usize], target: usize) {
+    if controls.is_empty() {
+      return self.apply(gate, target);
+    }
+    let m = gate.matrix();
+    let mut matrix = identity(2 << controls.len());
+    let last = matrix.len() - 2;
+    for (i, row) in m.iter().enumerate() {
+      matrix[last + i][last..].copy_from_slice(row);
+    }
+    let qubits: Vec<usize> = controls.iter().chain(&[target]).copied().collect();
+    self.apply_unitary(&matrix, &qubits);
+  }
+
+  /// Swaps qubits `a` and `b`, when all of the control qubits are |1>.
+  pub fn apply_controlled_swap(&mut self, controls: &[usize], a: usize, b: usize) {
+    let mut matrix = identity(4 << controls.len());
+    let last = matrix.len() - 4;
+    matrix[last + 1][last + 1] = COMPLEX_ZERO;
+    matrix[last + 2][last + 2] = COMPLEX_ZERO;
+    matrix[last + 1][last + 2] = COMPLEX_ONE;
+    matrix[last + 2][last + 1] = COMPLEX_ONE;
+    let qubits: Vec<usize> = controls.iter().chain(&[a, b]).copied().collect();
+    self.apply_unitary(&matrix, &qubits);
+  }
+
+  /// The iSWAP gate: swaps qubits `a` and `b`, multiplying |01> and |10> by i.
+  pub fn iswap(&mut self, a: usize, b: usize) {
+    let mut matrix = identity(4);
+    let i = Complex64::new(0.0, 1.0);
+    matrix[1][1] = COMPLEX_ZERO;
+    matrix[2][2] = COMPLEX_ZERO;
+    matrix[1][2] = i;
+    matrix[2][1] = i;
+    self.apply_unitary(&matrix, &[a, b]);
+  }
+
+  /// The probability that measuring the qubit gives |1>.
+  pub fn probability_of_one(&mut self, qubit: usize) -> f64 {
+    self.check_qubit(qubit);
+    self.move_center(qubit);
+    let site = &self.sites[qubit];
+    site.norm_sqr(1) / (site.norm_sqr(0) + site.norm_sqr(1))
+  }
+
+  /// Measures a single qubit in the computational basis.
+  /// Returns `false` for |0> and `true` for |1>, and collapses the state to match.
+  pub fn measure<R: RngCore + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> bool {
+    let outcome = rng.gen::<f64>() < self.probability_of_one(qubit);
+    let site = &mut self.sites[qubit];
+    let p = site.norm_sqr(outcome as usize);
+    let size = site.left * site.right;
+    for (i, x) in site.elements.iter_mut().enumerate() {
+      if (i >= size) == outcome {
+        *x /= p.sqrt();
+      } else {
+        *x = COMPLEX_ZERO;
+      }
+    }
+    outcome
+  }
+
+  /// Applies a noise channel to the target qubit along a single trajectory, like
+  /// `StateVector::apply_channel`. Returns the index of the Kraus operator that was applied.
+  pub fn apply_channel<R: RngCore + ?Sized>(
+    &mut self,
+    channel: &Channel,
+    target: usize,
+    rng: &mut R,
+  ) -> usize {
+    self.check_qubit(target);
+    self.move_center(target);
+    let branches: Vec<Site> = channel
+      .kraus_operators()
+      .iter()
+      .map(|operator| {
+        let mut site = self.sites[target].clone();
+        site.apply(operator);
+        site
+      })
+      .collect();
+    let norms: Vec<f64> = branches
+      .iter()
+      .map(|site| site.norm_sqr(0) + site.norm_sqr(1))
+      .collect();
+    let chosen = Channel::choose(&norms, rng);
+    let mut site = branches[chosen].clone();
+    for x in site.elements.iter_mut() {
+      *x /= norms[chosen].sqrt();
+    }
+    self.sites[target] = site;
+    chosen
+  }
+
+  // Swaps the qubits on sites `site` and `site + 1`
+  fn swap_sites(&mut self, site: usize) {
+    let mut swap = identity(4);
+    swap[1][1] = COMPLEX_ZERO;
+    swap[2][2] = COMPLEX_ZERO;
+    swap[1][2] = COMPLEX_ONE;
+    swap[2][1] = COMPLEX_ONE;
+    self.apply_contiguous(&swap, site, 2);
+  }
+
+  // Applies a matrix to `count` sites in a row, starting from `first`, by contracting them
+  // into a single tensor and splitting it back up with singular value decompositions
+  fn apply_contiguous(&mut self, matrix: &[Vec<Complex64>], first: usize, count: usize) {
+    self.move_center(first);
+    // The combined tensor, with element (left, bits, right) at (l * 2^count + bits) * right + r
+    let left = self.sites[first].left;
+    let mut right = self.sites[first].right;
+    let mut tensor: Vec<Complex64> = (0..left)
+      .flat_map(|l| {
+        let site = &self.sites[first];
+        (0..2).flat_map(move |bit| (0..site.right).map(move |r| site.get(bit, l, r)))
+      })
+      .collect();
+    let mut bits = 2;
+    for site in &self.sites[first + 1..first + count] {
+      let mut combined = vec![COMPLEX_ZERO; left * bits * 2 * site.right];
+      for outer in 0..left * bits {
+        for middle in 0..right {
+          let x = tensor[outer * right + middle];
+          if x == COMPLEX_ZERO {
+            continue;
+          }
+          for bit in 0..2 {
+            for r in 0..site.right {
+              combined[((outer * 2) + bit) * site.right + r] += x * site.get(bit, middle, r);
+            }
+          }
+        }
+      }
+      tensor = combined;
+      bits *= 2;
+      right = site.right;
+    }
+    // Apply the matrix to the bits
+    let mut applied = vec![COMPLEX_ZERO; tensor.len()];
+    for l in 0..left {
+      for (row, matrix_row) in matrix.iter().enumerate() {
+        for (column, m) in matrix_row.iter().enumerate() {
+          if *m == COMPLEX_ZERO {
+            continue;
+          }
+          for r in 0..right {
+            applied[(l * bits + row) * right + r] += m * tensor[(l * bits + column) * right + r];
+          }
+        }
+      }
+    }
+    // Split the first site off, one at a time, moving the center along to the right
+    let mut rest = applied;
+    let mut rest_left = left;
+    for offset in 0..count - 1 {
+      bits /= 2;
+      // As a matrix with rows (left, bit) and columns (remaining bits, right)
+      let columns = bits * right;
+      let matrix: Matrix = (0..rest_left * 2)
+        .map(|row| rest[row * columns..(row + 1) * columns].to_vec())
+        .collect();
+      let (u, singular_values, v) = svd(&matrix);
+      let kept = self.truncate(&singular_values);
+      let norm: f64 = singular_values.iter().map(|s| s * s).sum::<f64>().sqrt();
+      let kept_norm: f64 = singular_values[..kept]
+        .iter()
+        .map(|s| s * s)
+        .sum::<f64>()
+        .sqrt();
+      let mut site = Site {
+        left: rest_left,
+        right: kept,
+        elements: vec![COMPLEX_ZERO; 2 * rest_left * kept],
+      };
+      for l in 0..rest_left {
+        for bit in 0..2 {
+          let start = (bit * rest_left + l) * kept;
+          site.elements[start..start + kept].copy_from_slice(&u[l * 2 + bit][..kept]);
+        }
+      }
+      self.sites[first + offset] = site;
+      // The rest is Σ V†, scaled so that the state keeps its norm
+      rest = (0..kept)
+        .flat_map(|k| {
+          let scale = singular_values[k] * norm / kept_norm;
+          let v = &v;
+          (0..columns).map(move |column| v[column][k].conj() * scale)
+        })
+        .collect();
+      rest_left = kept;
+    }
+    // The last site takes what is left, with rows (left, bit)
+    let last = first + count - 1;
+    self.sites[last] = Site {
+      left: rest_left,
+      right,
+      elements: (0..2)
+        .flat_map(|bit| {
+          let rest = &rest;
+          (0..rest_left).flat_map(move |l| (0..right).map(move |r| rest[(l * 2 + bit) * right + r]))
+        })
+        .collect(),
+    };
+    self.center = last;
+  }
+
+  // How many singular values to keep, adding the weight of the rest to the truncation error
+  fn truncate(&mut self, singular_values: &[f64]) -> usize {
+    let total: f64 = singular_values.iter().map(|s| s * s).sum();
+    let kept = singular_values
+      .iter()
+      .take(self.max_bond_dimension)
+      .take_while(|s| *s * *s >= self.truncation_threshold.max(1e-28) * total)
+      .count()
+      .max(1);
+    let dropped: f64 = singular_values[kept..].iter().map(|s| s * s).sum();
+    self.truncation_error += dropped / total;
+    kept
+  }
+
+  // Moves the center to the given site, without truncating anything
+  fn move_center(&mut self, target: usize) {
+    while self.center < target {
+      let site = self.center;
+      self.shift_center(site, site + 1);
+    }
+    while self.center > target {
+      let site = self.center;
+      self.shift_center(site, site - 1);
+    }
+  }
+
+  // Makes the center site orthonormal with a singular value decomposition, and pushes the
+  // rest of it into the next site in the given direction
+  fn shift_center(&mut self, from: usize, to: usize) {
+    let site = &self.sites[from].clone();
+    let rightwards = to > from;
+    // Rows (left, bit) and columns right when moving right, or rows left and columns
+    // (bit, right) when moving left
+    let matrix: Matrix = if rightwards {
+      (0..site.left * 2)
+        .map(|row| {
+          (0..site.right)
+            .map(|r| site.get(row % 2, row / 2, r))
+            .collect()
+        })
+        .collect()
+    } else {
+      (0..site.left)
+        .map(|l| {
+          (0..2 * site.right)
+            .map(|column| site.get(column / site.right, l, column % site.right))
+            .collect()
+        })
+        .collect()
+    };
+    let (u, singular_values, v) = svd(&matrix);
+    // Drop only the singular values that are zero
+    let largest = singular_values[0];
+    let kept = singular_values
+      .iter()
+      .take_while(|s| **s > 1e-14 * largest)
+      .count()
+      .max(1);
+    let next = &self.sites[to].clone();
+    if rightwards {
+      self.sites[from] = Site {
+        left: site.left,
+        right: kept,
+        elements: (0..2)
+          .flat_map(|bit| {
+            let u = &u;
+            (0..site.left).flat_map(move |l| (0..kept).map(move |k| u[l * 2 + bit][k]))
+          })
+          .collect(),
+      };
+      // The next site becomes Σ V† times itself
+      let mut elements = vec![COMPLEX_ZERO; 2 * kept * next.right];
+      for bit in 0..2 {
+        for k in 0..kept {
+          for (m, v_row) in v.iter().enumerate() {
+            let factor = singular_values[k] * v_row[k].conj();
+            for r in 0..next.right {
+              elements[(bit * kept + k) * next.right + r] += factor * next.get(bit, m, r);
+            }
+          }
+        }
+      }
+      self.sites[to] = Site {
+        left: kept,
+        right: next.right,
+        elements,
+      };
+    } else {
+      self.sites[from] = Site {
+        left: kept,
+        right: site.right,
+        elements: (0..2)
+          .flat_map(|bit| {
+            let v = &v;
+            (0..kept)
+              .flat_map(move |k| (0..site.right).map(move |r| v[bit * site.right + r][k].conj()))
+          })
+          .collect(),
+      };
+      // The previous site becomes itself times U Σ
+      let mut elements = vec![COMPLEX_ZERO; 2 * next.left * kept];
+      for bit in 0..2 {
+        for l in 0..next.left {
+          for (m, u_row) in u.iter().enumerate() {
+            let x = next.get(bit, l, m);
+            for k in 0..kept {
+              elements[(bit * next.left + l) * kept + k] += x * u_row[k] * singular_values[k];
+            }
+          }
+        }
+      }
+      self.sites[to] = Site {
+        left: next.left,
+        right: kept,
+        elements,
+      };
+    }
+    self.center = to;
+  }
+
+  fn check_qubit(&self, qubit: usize) {
+    assert!(
+      qubit < self.num_qubits(),
+      "qubit {} out of range for {} qubits",
+      qubit,
+      self.num_qubits()
+    );
+  }
+}
+
+// The bits of a basis state index, with qubit 0 as the most significant bit
+fn index_bits(index: usize, num_qubits: usize) -> Vec<bool> {
+  (0..num_qubits)
+    .map(|qubit| (index >> (num_qubits - 1 - qubit)) & 1 == 1)
+    .collect()
+}
+
+// A row vector times the matrix of a site for one value of the bit
+fn multiply_vector(vector: &[Complex64], site: &Site, bit: usize) -> Vec<Complex64> {
+  (0..site.right)
+    .map(|r| {
+      vector
+        .iter()
+        .enumerate()
+        .map(|(l, x)| x * site.get(bit, l, r))
+        .sum()
+    })
+    .collect()
+}
+
+impl Backend for MatrixProductState {
+  fn num_qubits(&self) -> usize {
+    self.sites.len()
+  }
+
+  fn apply_gate(
+    &mut self,
+    kind: &GateKind,
+    controls: &[usize],
+    target: usize,
+  ) -> Result<(), SimulationError> {
+    self.apply_controlled(&kind.gate(), controls, target);
+    Ok(())
+  }
+
+  fn apply_controlled_swap(
+    &mut self,
+    controls: &[usize],
+    a: usize,
+    b: usize,
+  ) -> Result<(), SimulationError> {
+    MatrixProductState::apply_controlled_swap(self, controls, a, b);
+    Ok(())
+  }
+
+  fn iswap(&mut self, a: usize, b: usize) -> Result<(), SimulationError> {
+    MatrixProductState::iswap(self, a, b);
+    Ok(())
+  }
+
+  fn apply_unitary(
+    &mut self,
+    matrix: &[Vec<Complex64>],
+    qubits: &[usize],
+  ) -> Result<(), SimulationError> {
+    MatrixProductState::apply_unitary(self, matrix, qubits);
+    Ok(())
+  }
+
+  fn measure<R: RngCore + ?Sized>(
+    &mut self,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<bool, SimulationError> {
+    Ok(MatrixProductState::measure(self, qubit, rng))
+  }
+
+  fn apply_channel<R: RngCore + ?Sized>(
+    &mut self,
+    channel: &Channel,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<(), SimulationError> {
+    MatrixProductState::apply_channel(self, channel, qubit, rng);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+fn assert_matches_state_vector(mps: &MatrixProductState, state: &StateVector) {
+  for (index, amplitude) in state.amplitudes().iter().enumerate() {
+    let bits = index_bits(index, mps.num_qubits());
+    assert!(
+      (mps.amplitude(&bits) - amplitude).norm() < 1e-10,
+      "{}: {} {}",
+      index,
+      mps.amplitude(&bits),
+      amplitude
+    );
+  }
+}
+
+#[test]
+fn exact_without_truncation() {
+  use crate::circuit::Circuit;
+  use crate::noise::NoiseModel;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(27);
+  let mut circuit = Circuit::new(5, 0);
+  circuit
+    .h(0)
+    .cx(0, 3)
+    .ry(0.4, 4)
+    .ccx(4, 0, 2)
+    .u3(0.3, 1.1, -0.6, 1)
+    .cswap(2, 4, 0)
+    .iswap(3, 1)
+    .controlled(GateKind::Phase(0.9), &[1], 4)
+    .swap(0, 4)
+    .controlled(GateKind::Rx(1.3), &[3, 0], 1);
+  let unitary = Gate::u3(0.7, 0.2, 1.9);
+  circuit.push(crate::circuit::Instruction::Unitary {
+    matrix: {
+      // The two-qubit unitary u ⊗ u, as a matrix
+      let m = unitary.matrix();
+      (0..4)
+        .map(|row| {
+          (0..4)
+            .map(|column| m[row / 2][column / 2] * m[row % 2][column % 2])
+            .collect()
+        })
+        .collect()
+    },
+    qubits: vec![4, 1],
+  });
+  let expected = circuit.run(&mut rng).state;
+  let mps = circuit
+    .run_on(
+      MatrixProductState::new(5, 64, 0.0),
+      &NoiseModel::new(),
+      &mut rng,
+    )
+    .unwrap()
+    .state;
+  assert_matches_state_vector(&mps, &expected);
+  assert!(mps.truncation_error() < 1e-20);
+  assert!(mps.bond_dimensions().iter().all(|bond| *bond <= 4));
+}
+
+#[test]
+fn product_states_keep_small_bonds() {
+  let mut mps = MatrixProductState::new(40, 8, 1e-12);
+  for qubit in 0..40 {
+    mps.apply(&Gate::ry(0.1 * qubit as f64), qubit);
+  }
+  // Entangle and disentangle again
+  mps.apply_controlled(&crate::gate::PAULI_X, &[3], 30);
+  mps.apply_controlled(&crate::gate::PAULI_X, &[3], 30);
+  assert!(mps.bond_dimensions().iter().all(|bond| *bond == 1));
+  let amplitude: f64 = (0..40).map(|qubit| (0.05 * qubit as f64).cos()).product();
+  assert!((mps.amplitude(&[false; 40]).re - amplitude).abs() < 1e-12);
+}
+
+#[test]
+fn bond_dimension_limit_truncates() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(28);
+  let mut exact = MatrixProductState::new(8, 64, 0.0);
+  let mut truncated = MatrixProductState::new(8, 2, 0.0);
+  for layer in 0..4 {
+    for qubit in 0..8 {
+      let gate = Gate::u3(rng.gen_range(0.0..3.0), rng.gen_range(0.0..3.0), 0.5);
+      exact.apply(&gate, qubit);
+      truncated.apply(&gate, qubit);
+    }
+    for qubit in (layer % 2..7).step_by(2) {
+      exact.apply_controlled(&crate::gate::PAULI_X, &[qubit], qubit + 1);
+      truncated.apply_controlled(&crate::gate::PAULI_X, &[qubit], qubit + 1);
+    }
+  }
+  assert!(exact.truncation_error() < 1e-20);
+  assert!(exact.bond_dimensions().iter().any(|bond| *bond > 2));
+  assert!(truncated.bond_dimensions().iter().all(|bond| *bond <= 2));
+  assert!(truncated.truncation_error() > 1e-3);
+  // The truncated state is still normalized, and close to the exact one
+  let bits: Vec<Vec<bool>> = (0..256).map(|i| index_bits(i, 8)).collect();
+  let overlap: Complex64 = bits
+    .iter()
+    .map(|b| exact.amplitude(b).conj() * truncated.amplitude(b))
+    .sum();
+  let norm: f64 = bits.iter().map(|b| truncated.amplitude(b).norm_sqr()).sum();
+  assert!((norm - 1.0).abs() < 1e-10);
+  assert!(overlap.norm_sqr() > 1.0 - 2.0 * truncated.truncation_error() - 0.1);
+  assert!(overlap.norm_sqr() < 1.0 - 1e-4);
+}
+
+#[test]
+fn samples_and_measurements_on_many_qubits() {
+  use crate::circuit::Circuit;
+  use crate::noise::NoiseModel;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(29);
+  // A GHZ state on 80 qubits is far too big for a state vector, or even to index its
+  // amplitudes with a u64, but needs bonds of 2
+  let n = 80;
+  let mut circuit = Circuit::new(n, 0);
+  circuit.h(0);
+  for qubit in 1..n {
+    circuit.cx(qubit - 1, qubit);
+  }
+  let mut mps = circuit
+    .run_on(
+      MatrixProductState::new(n, 4, 1e-12),
+      &NoiseModel::new(),
+      &mut rng,
+    )
+    .unwrap()
+    .state;
+  assert!(mps.bond_dimensions().iter().all(|bond| *bond == 2));
+  assert!((mps.amplitude(&vec![false; n]).re - 0.5f64.sqrt()).abs() < 1e-10);
+  assert!((mps.amplitude(&vec![true; n]).re - 0.5f64.sqrt()).abs() < 1e-10);
+  let mut one_flipped = vec![true; n];
+  one_flipped[70] = false;
+  assert!(mps.amplitude(&one_flipped).norm() < 1e-10);
+  let counts = mps.sample(100, &mut rng);
+  assert_eq!(counts.len(), 2);
+  assert!((30..70).contains(&counts[&"0".repeat(n)]), "{:?}", counts);
+  // Measuring one qubit collapses them all
+  let first = mps.measure(n / 2, &mut rng);
+  assert!((mps.probability_of_one(0) - first as u8 as f64).abs() < 1e-10);
+  assert!((mps.probability_of_one(n - 1) - first as u8 as f64).abs() < 1e-10);
+}
+
+#[test]
+fn runs_circuits_with_measurements_and_noise() {
+  use crate::circuit::Circuit;
+  use crate::noise::NoiseModel;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(30);
+  let mut circuit = Circuit::new(3, 3);
+  circuit
+    .h(0)
+    .cx(0, 2)
+    .measure(2, 2)
+    .reset(0)
+    .x(1)
+    .measure_all();
+  let counts = circuit
+    .sample_on(
+      200,
+      &MatrixProductState::new(3, 8, 0.0),
+      &NoiseModel::new(),
+      &mut rng,
+    )
+    .unwrap();
+  assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["010", "011"]);
+  let mut noise = NoiseModel::new();
+  noise.add_gate_error("x", Channel::bit_flip(1.0));
+  let counts = circuit
+    .sample_on(50, &MatrixProductState::new(3, 8, 0.0), &noise, &mut rng)
+    .unwrap();
+  assert!(counts.keys().all(|bits| bits.starts_with("00")));
+}
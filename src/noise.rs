@@ -0,0 +1,280 @@
+use crate::backend::{Backend, SimulationError};
+use crate::channel::{Channel, ValidChannel};
+use crate::circuit::Instruction;
+use rand::{Rng, RngCore};
+use std::collections::BTreeMap;
+
+/// Describes how noisy the hardware is, so that the same circuit can be run ideally or
+/// noisily without changing it. See `Circuit::run_noisy` and `Circuit::run_on`.
+///
+/// Gate errors are noise channels applied right after every gate with a given name, on each
+/// qubit the gate touches, controls included. The names are the same as in `GateKind::name`,
+/// with a "c" in front for every control: "h", "cx", "ccx", "swap", "cswap", "iswap", and
+/// "unitary" for custom gates. An error can apply on every qubit, or only on one of them.
+///
+/// Readout errors change the classical bit a measurement writes, after the qubit collapsed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NoiseModel {
+  gate_errors: BTreeMap<String, Vec<Channel>>,
+  qubit_gate_errors: BTreeMap<(String, usize), Vec<Channel>>,
+  readout_error: Option<ReadoutError>,
+  qubit_readout_errors: BTreeMap<usize, ReadoutError>,
+}
+
+impl NoiseModel {
+  /// A noise model without any noise.
+  pub fn new() -> NoiseModel {
+    NoiseModel::default()
+  }
+
+  /// Whether the model has no noise at all, so that running with it is the same as an
+  /// ideal run.
+  pub fn is_ideal(&self) -> bool {
+    self == &NoiseModel::default()
+  }
+
+  /// Adds a channel after every gate with the given name, on every qubit the gate touches.
+  /// Errors added to the same gate are applied in the order they were added.
+  ///
+  /// Panics if the channel is not CPTP.
+  pub fn add_gate_error(&mut self, gate: &str, channel: Channel) -> &mut Self {
+    assert!(channel.is_cptp(), "the error on '{}' is not CPTP", gate);
+    self
+      .gate_errors
+      .entry(gate.to_string())
+      .or_default()
+      .push(channel);
+    self
+  }
+
+  /// Adds a channel after every gate with the given name that touches the qubit, only on
+  /// that qubit. It comes after the errors that apply to every qubit.
+  ///
+  /// Panics if the channel is not CPTP.
+  pub fn add_gate_error_on(&mut self, gate: &str, qubit: usize, channel: Channel) -> &mut Self {
+    assert!(
+      channel.is_cptp(),
+      "the error on '{}' on qubit {} is not CPTP",
+      gate,
+      qubit
+    );
+    self
+      .qubit_gate_errors
+      .entry((gate.to_string(), qubit))
+      .or_default()
+      .push(channel);
+    self
+  }
+
+  /// Sets the readout error of every qubit that has no readout error of its own.
+  pub fn set_readout_error(&mut self, error: ReadoutError) -> &mut Self {
+    self.readout_error = Some(error);
+    self
+  }
+
+  /// Sets the readout error of a single qubit.
+  pub fn set_readout_error_on(&mut self, qubit: usize, error: ReadoutError) -> &mut Self {
+    self.qubit_readout_errors.insert(qubit, error);
+    self
+  }
+
+  /// The channels applied to the qubit after a gate with the given name, in order.
+  pub fn gate_errors(&self, gate: &str, qubit: usize) -> Vec<&Channel> {
+    let everywhere = self.gate_errors.get(gate).into_iter().flatten();
+    let here = self
+      .qubit_gate_errors
+      .get(&(gate.to_string(), qubit))
+      .into_iter()
+      .flatten();
+    everywhere.chain(here).collect()
+  }
+
+  /// The readout error of a qubit, if it has one.
+  pub fn readout_error(&self, qubit: usize) -> Option<&ReadoutError> {
+    self
+      .qubit_readout_errors
+      .get(&qubit)
+      .or(self.readout_error.as_ref())
+  }
+
+  // Applies the errors that follow an instruction, if it is a gate
+  pub(crate) fn after_instruction<B: Backend, R: RngCore + ?Sized>(
+    &self,
+    instruction: &Instruction,
+    state: &mut B,
+    rng: &mut R,
+  ) -> Result<(), SimulationError> {
+    let (name, qubits): (String, Vec<usize>) = match instruction {
+      Instruction::Gate {
+        kind,
+        target,
+        controls,
+      } => (
+        format!("{}{}", "c".repeat(controls.len()), kind.name()),
+        controls.iter().chain(&[*target]).copied().collect(),
+      ),
+      Instruction::Swap { a, b, controls } => (
+        format!("{}swap", "c".repeat(controls.len())),
+        controls.iter().chain(&[*a, *b]).copied().collect(),
+      ),
+      Instruction::ISwap { a, b } => ("iswap".to_string(), vec![*a, *b]),
+      Instruction::Unitary { qubits, .. } => ("unitary".to_string(), qubits.clone()),
+      _ => return Ok(()),
+    };
+    for qubit in qubits {
+      for channel in self.gate_errors(&name, qubit) {
+        state.apply_channel(channel, qubit, rng)?;
+      }
+    }
+    Ok(())
+  }
+
+  // The classical bit that a measurement of the qubit writes, given the actual outcome
+  pub(crate) fn read<R: RngCore + ?Sized>(&self, qubit: usize, outcome: bool, rng: &mut R) -> bool {
+    match self.readout_error(qubit) {
+      Some(error) => error.read(outcome, rng),
+      None => outcome,
+    }
+  }
+}
+
+/// The chance of reading out the wrong classical bit from a measurement.
+/// `matrix()[actual][read]` is the probability of reading `read` when the qubit was
+/// measured as `actual`, so each row adds up to 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReadoutError {
+  matrix: [[f64; 2]; 2],
+}
+
+impl ReadoutError {
+  /// Creates a readout error from its matrix of probabilities.
+  ///
+  /// Panics if a row has a negative probability or does not add up to 1.
+  pub fn new(matrix: [[f64; 2]; 2]) -> ReadoutError {
+    for row in &matrix {
+      assert!(
+        row.iter().all(|p| *p >= 0.0) && (row[0] + row[1] - 1.0).abs() < 1e-12,
+        "the rows of a readout error must be probabilities that add up to 1, got {:?}",
+        matrix
+      );
+    }
+    ReadoutError { matrix }
+  }
+
+  /// Reads out 1 as 0 with probability `p10`, and 0 as 1 with probability `p01`.
+  pub fn asymmetric(p01: f64, p10: f64) -> ReadoutError {
+    ReadoutError::new([[1.0 - p01, p01], [p10, 1.0 - p10]])
+  }
+
+  /// Reads out the wrong bit with probability `p`, whatever the outcome.
+  pub fn symmetric(p: f64) -> ReadoutError {
+    ReadoutError::asymmetric(p, p)
+  }
+
+  pub fn matrix(&self) -> [[f64; 2]; 2] {
+    self.matrix
+  }
+
+  /// The classical bit read out for the given measurement outcome.
+  pub fn read<R: RngCore + ?Sized>(&self, outcome: bool, rng: &mut R) -> bool {
+    let flip = self.matrix[outcome as usize][!outcome as usize];
+    outcome ^ (rng.gen::<f64>() < flip)
+  }
+}
+
+#[test]
+fn gate_errors_by_name_and_qubit() {
+  let mut noise = NoiseModel::new();
+  assert!(noise.is_ideal());
+  noise
+    .add_gate_error("cx", Channel::depolarizing(0.1))
+    .add_gate_error_on("cx", 1, Channel::bit_flip(0.2))
+    .add_gate_error("h", Channel::phase_flip(0.3));
+  assert!(!noise.is_ideal());
+  assert_eq!(
+    noise.gate_errors("cx", 1),
+    vec![&Channel::depolarizing(0.1), &Channel::bit_flip(0.2)]
+  );
+  assert_eq!(
+    noise.gate_errors("cx", 0),
+    vec![&Channel::depolarizing(0.1)]
+  );
+  assert!(noise.gate_errors("x", 0).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "not CPTP")]
+fn gate_errors_must_be_cptp() {
+  use crate::gate::PAULI_X;
+  NoiseModel::new().add_gate_error("x", Channel::new(vec![PAULI_X, PAULI_X]));
+}
+
+#[test]
+fn readout_errors() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(16);
+  let mut noise = NoiseModel::new();
+  noise
+    .set_readout_error(ReadoutError::symmetric(1.0))
+    .set_readout_error_on(2, ReadoutError::asymmetric(0.0, 0.25));
+  assert!(!noise.read(0, true, &mut rng));
+  assert!(noise.read(1, false, &mut rng));
+  assert!(!noise.read(2, false, &mut rng));
+  let zeros = (0..1000).filter(|_| !noise.read(2, true, &mut rng)).count();
+  assert!((200..300).contains(&zeros), "{}", zeros);
+}
+
+#[test]
+fn ideal_and_noisy_runs_of_one_circuit() {
+  use crate::circuit::Circuit;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(17);
+  let mut circuit = Circuit::new(3, 3);
+  circuit.x(0).cx(0, 1).h(2).h(2).measure_all();
+  assert_eq!(circuit.sample(100, &mut rng)["110"], 100);
+  // A certain bit flip after every CNOT, only on its target
+  let mut noise = NoiseModel::new();
+  noise.add_gate_error_on("cx", 1, Channel::bit_flip(1.0));
+  assert_eq!(circuit.sample_noisy(100, &noise, &mut rng)["100"], 100);
+  // The Hadamards dephase the third qubit completely, so it reads out at random
+  let mut noise = NoiseModel::new();
+  noise.add_gate_error("h", Channel::phase_flip(0.5));
+  let counts = circuit.sample_noisy(1000, &noise, &mut rng);
+  assert!((400..600).contains(&counts["111"]), "{:?}", counts);
+  // And readout errors only change the classical bits
+  let mut noise = NoiseModel::new();
+  noise.set_readout_error_on(0, ReadoutError::symmetric(1.0));
+  let result = circuit.run_noisy(&noise, &mut rng);
+  assert_eq!(result.classical_bitstring(), "010");
+  assert_eq!(result.state.probabilities()[0b110], 1.0);
+}
+
+#[test]
+fn noisy_circuits_on_density_matrices() {
+  use crate::circuit::Circuit;
+  use crate::density_matrix::DensityMatrix;
+  use crate::ket::ValidQuantumState;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(18);
+  let mut circuit = Circuit::new(2, 0);
+  circuit.h(0).cx(0, 1);
+  let mut noise = NoiseModel::new();
+  noise.add_gate_error("cx", Channel::depolarizing(0.3));
+  let ideal = circuit
+    .run_on(DensityMatrix::new(2), &NoiseModel::new(), &mut rng)
+    .unwrap();
+  assert!((ideal.state.purity() - 1.0).abs() < 1e-12);
+  // Noise on a density matrix is exact, with no randomness involved
+  let noisy = circuit
+    .run_on(DensityMatrix::new(2), &noise, &mut rng)
+    .unwrap()
+    .state;
+  assert!(noisy.is_valid());
+  assert!(noisy.purity() < 0.8);
+  // Depolarizing flips each qubit with probability 0.2, and the pair stays correlated when
+  // both or neither flip
+  let expected = [0.34, 0.16, 0.16, 0.34];
+  for (p, expected) in noisy.probabilities().iter().zip(&expected) {
+    assert!((p - expected).abs() < 1e-12, "{:?}", noisy.probabilities());
+  }
+}
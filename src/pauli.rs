@@ -0,0 +1,597 @@
+use crate::gate::*;
+use crate::ket::COMPLEX_ZERO;
+use crate::state_vector::StateVector;
+use num_complex::Complex64;
+use rand::RngCore;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+
+/// One of the four single-qubit Pauli operators.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pauli {
+  I,
+  X,
+  Y,
+  Z,
+}
+
+impl Pauli {
+  /// The matrix of the operator, which is also a gate.
+  pub fn gate(&self) -> Gate {
+    match self {
+      Pauli::I => IDENTITY,
+      Pauli::X => PAULI_X,
+      Pauli::Y => PAULI_Y,
+      Pauli::Z => PAULI_Z,
+    }
+  }
+
+  fn symbol(&self) -> char {
+    match self {
+      Pauli::I => 'I',
+      Pauli::X => 'X',
+      Pauli::Y => 'Y',
+      Pauli::Z => 'Z',
+    }
+  }
+
+  fn from_symbol(symbol: char) -> Option<Pauli> {
+    match symbol {
+      'I' => Some(Pauli::I),
+      'X' => Some(Pauli::X),
+      'Y' => Some(Pauli::Y),
+      'Z' => Some(Pauli::Z),
+      _ => None,
+    }
+  }
+}
+
+/// A tensor product of Pauli operators on some of the qubits of a register, like Z0 Z1 X3.
+/// The qubits it does not mention have the identity on them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PauliString {
+  // Only the qubits with X, Y or Z on them
+  paulis: BTreeMap<usize, Pauli>,
+}
+
+impl PauliString {
+  /// The identity on every qubit.
+  pub fn new() -> PauliString {
+    PauliString::default()
+  }
+
+  /// Puts a Pauli operator on a qubit, replacing the one that was there.
+  pub fn set(&mut self, qubit: usize, pauli: Pauli) -> &mut Self {
+    if pauli == Pauli::I {
+      self.paulis.remove(&qubit);
+    } else {
+      self.paulis.insert(qubit, pauli);
+    }
+    self
+  }
+
+  /// The Pauli operator on a qubit.
+  pub fn get(&self, qubit: usize) -> Pauli {
+    self.paulis.get(&qubit).copied().unwrap_or(Pauli::I)
+  }
+
+  /// The qubits that have X, Y or Z on them, with their operators, in order.
+  pub fn paulis(&self) -> impl Iterator<Item = (usize, Pauli)> + '_ {
+    self.paulis.iter().map(|(qubit, pauli)| (*qubit, *pauli))
+  }
+
+  pub fn is_identity(&self) -> bool {
+    self.paulis.is_empty()
+  }
+
+  /// The exact expectation value <ψ|P|ψ>, which is always a real number between -1 and 1.
+  ///
+  /// Panics if the string acts on a qubit that the register does not have.
+  pub fn expectation(&self, state: &StateVector) -> f64 {
+    let n = state.num_qubits();
+    let mask = |qubit: usize| {
+      assert!(qubit < n, "qubit {} out of range for {} qubits", qubit, n);
+      1 << (n - 1 - qubit)
+    };
+    // P|i> = i^(number of Ys) (-1)^(bits under Y and Z) |i with the bits under X and Y flipped>
+    let mut flip = 0;
+    let mut sign = 0;
+    let mut phase = Complex64::from(1.0);
+    for (qubit, pauli) in self.paulis() {
+      match pauli {
+        Pauli::X => flip |= mask(qubit),
+        Pauli::Y => {
+          flip |= mask(qubit);
+          sign |= mask(qubit);
+          phase *= Complex64::i();
+        }
+        Pauli::Z => sign |= mask(qubit),
+        Pauli::I => {}
+      }
+    }
+    let amplitudes = state.amplitudes();
+    let sum: Complex64 = amplitudes
+      .iter()
+      .enumerate()
+      .filter(|(_, amplitude)| **amplitude != COMPLEX_ZERO)
+      .map(|(index, amplitude)| {
+        let parity = if (index & sign).count_ones() % 2 == 0 {
+          1.0
+        } else {
+          -1.0
+        };
+        amplitudes[index ^ flip].conj() * amplitude * parity
+      })
+      .sum();
+    let norm: f64 = amplitudes.iter().map(|a| a.norm_sqr()).sum();
+    (phase * sum).re / norm
+  }
+
+  // Whether a single measurement setting can measure both strings: on every qubit where
+  // both have an operator, they have the same one
+  fn qubitwise_commutes(&self, other: &PauliString) -> bool {
+    self
+      .paulis()
+      .all(|(qubit, pauli)| other.get(qubit) == Pauli::I || other.get(qubit) == pauli)
+  }
+}
+
+// Z0Z1X3, or I for the identity
+impl fmt::Display for PauliString {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.is_identity() {
+      return write!(f, "I");
+    }
+    for (qubit, pauli) in self.paulis() {
+      write!(f, "{}{}", pauli.symbol(), qubit)?;
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for PauliString {
+  type Err = PauliParseError;
+
+  /// Parses a string like "Z0 Z1 X3", "Z0*Z1*X3" or "Z0Z1X3".
+  fn from_str(text: &str) -> Result<PauliString, PauliParseError> {
+    let mut parser = Parser::new(text);
+    let string = parser.pauli_string()?;
+    parser.end()?;
+    Ok(string)
+  }
+}
+
+/// A weighted sum of Pauli strings, like the Hamiltonian 0.5 Z0 Z1 + 0.3 X2.
+/// It can be parsed from text like "0.5*Z0Z1 + 0.3*X2 - 1.1".
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PauliSum {
+  terms: Vec<(f64, PauliString)>,
+}
+
+impl PauliSum {
+  /// A sum with no terms, which is zero.
+  pub fn new() -> PauliSum {
+    PauliSum::default()
+  }
+
+  pub fn add_term(&mut self, coefficient: f64, string: PauliString) -> &mut Self {
+    self.terms.push((coefficient, string));
+    self
+  }
+
+  /// The terms of the sum, as (coefficient, Pauli string) pairs, in the order they were added.
+  pub fn terms(&self) -> &[(f64, PauliString)] {
+    &self.terms
+  }
+
+  /// The exact expectation value <ψ|H|ψ>, as the weighted sum of the expectation values of
+  /// the Pauli strings.
+  pub fn expectation(&self, state: &StateVector) -> f64 {
+    self
+      .terms
+      .iter()
+      .map(|(coefficient, string)| coefficient * string.expectation(state))
+      .sum()
+  }
+
+  /// Groups the terms that can be measured together: within a group, the strings agree on
+  /// every qubit they share, so one rotation of every qubit into the X, Y or Z basis measures
+  /// all of them. Returns the indices of the terms in each group, leaving out the identity,
+  /// which needs no measurement.
+  ///
+  /// Finding the fewest groups is hard, so this greedily puts each term into the first
+  /// group that it fits in.
+  pub fn measurement_groups(&self) -> Vec<Vec<usize>> {
+    let mut groups: Vec<(PauliString, Vec<usize>)> = Vec::new();
+    for (index, (_, string)) in self.terms.iter().enumerate() {
+      if string.is_identity() {
+        continue;
+      }
+      match groups
+        .iter_mut()
+        .find(|(basis, _)| basis.qubitwise_commutes(string))
+      {
+        Some((basis, members)) => {
+          for (qubit, pauli) in string.paulis() {
+            basis.set(qubit, pauli);
+          }
+          members.push(index);
+        }
+        None => groups.push((string.clone(), vec![index])),
+      }
+    }
+    groups.into_iter().map(|(_, members)| members).collect()
+  }
+
+  /// Estimates <ψ|H|ψ> the way hardware does: for every group of `measurement_groups`,
+  /// rotate the qubits into the basis of the group and measure all of them `shots` times.
+  /// Each Pauli string is then the average parity of the outcomes on its qubits.
+  ///
+  /// The estimate is off by about 1/sqrt(shots) per term, and converges to `expectation`.
+  pub fn estimate<R: RngCore + ?Sized>(
+    &self,
+    state: &StateVector,
+    shots: usize,
+    rng: &mut R,
+  ) -> f64 {
+    assert!(shots > 0, "estimating needs at least one shot");
+    // The identity terms are exact
+    let mut total: f64 = self
+      .terms
+      .iter()
+      .filter(|(_, string)| string.is_identity())
+      .map(|(coefficient, _)| coefficient)
+      .sum();
+    for group in self.measurement_groups() {
+      let mut rotated = state.clone();
+      let mut rotated_qubits = BTreeMap::new();
+      for index in &group {
+        for (qubit, pauli) in self.terms[*index].1.paulis() {
+          rotated_qubits.insert(qubit, pauli);
+        }
+      }
+      for (qubit, pauli) in rotated_qubits {
+        // H maps the eigenstates of X onto |0> and |1>, and H S† those of Y
+        match pauli {
+          Pauli::X => rotated.apply(&HADAMARD, qubit),
+          Pauli::Y => {
+            rotated.apply(&S_DAGGER, qubit);
+            rotated.apply(&HADAMARD, qubit);
+          }
+          Pauli::Z | Pauli::I => {}
+        }
+      }
+      let counts = rotated.sample(shots, rng);
+      for index in &group {
+        let (coefficient, string) = &self.terms[*index];
+        let sum: i64 = counts
+          .iter()
+          .map(|(bits, count)| {
+            let bits = bits.as_bytes();
+            let ones = string
+              .paulis()
+              .filter(|(qubit, _)| bits[*qubit] == b'1')
+              .count();
+            if ones % 2 == 0 {
+              *count as i64
+            } else {
+              -(*count as i64)
+            }
+          })
+          .sum();
+        total += coefficient * sum as f64 / shots as f64;
+      }
+    }
+    total
+  }
+}
+
+// 0.5*Z0Z1 - 0.3*X2 + 1.1
+impl fmt::Display for PauliSum {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.terms.is_empty() {
+      return write!(f, "0");
+    }
+    for (position, (coefficient, string)) in self.terms.iter().enumerate() {
+      if position == 0 {
+        if *coefficient < 0.0 {
+          write!(f, "-")?;
+        }
+      } else if *coefficient < 0.0 {
+        write!(f, " - ")?;
+      } else {
+        write!(f, " + ")?;
+      }
+      if string.is_identity() {
+        write!(f, "{}", coefficient.abs())?;
+      } else {
+        write!(f, "{}*{}", coefficient.abs(), string)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for PauliSum {
+  type Err = PauliParseError;
+
+  /// Parses a sum like "0.5*Z0Z1 + 0.3*X2 - 1.1". Every term is an optional coefficient,
+  /// and a Pauli string, unless the term is just a constant.
+  fn from_str(text: &str) -> Result<PauliSum, PauliParseError> {
+    let mut parser = Parser::new(text);
+    let mut sum = PauliSum::new();
+    let mut sign = parser.sign().unwrap_or(1.0);
+    loop {
+      let (coefficient, string) = parser.term()?;
+      sum.add_term(sign * coefficient, string);
+      match parser.sign() {
+        Some(next) => sign = next,
+        None => break,
+      }
+    }
+    parser.end()?;
+    Ok(sum)
+  }
+}
+
+/// An error in the text of a Pauli string or sum, and where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PauliParseError {
+  pub message: String,
+  /// The position of the error in the text, starting from 1.
+  pub column: usize,
+}
+
+impl PauliParseError {
+  fn new<S: Into<String>>(message: S, column: usize) -> PauliParseError {
+    PauliParseError {
+      message: message.into(),
+      column,
+    }
+  }
+}
+
+impl fmt::Display for PauliParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "column {}: {}", self.column, self.message)
+  }
+}
+
+impl std::error::Error for PauliParseError {}
+
+// The text is so simple that it is parsed one character at a time, without a lexer
+struct Parser<'a> {
+  text: &'a str,
+  chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+  fn new(text: &'a str) -> Parser<'a> {
+    Parser {
+      text,
+      chars: text.char_indices().peekable(),
+    }
+  }
+
+  fn peek(&mut self) -> Option<char> {
+    while matches!(self.chars.peek(), Some((_, c)) if c.is_whitespace()) {
+      self.chars.next();
+    }
+    self.chars.peek().map(|(_, c)| *c)
+  }
+
+  fn column(&mut self) -> usize {
+    self.peek();
+    let offset = self.chars.peek().map_or(self.text.len(), |(i, _)| *i);
+    self.text[..offset].chars().count() + 1
+  }
+
+  fn error<S: Into<String>>(&mut self, message: S) -> PauliParseError {
+    let column = self.column();
+    PauliParseError::new(message, column)
+  }
+
+  fn end(&mut self) -> Result<(), PauliParseError> {
+    match self.peek() {
+      None => Ok(()),
+      Some(c) => Err(self.error(format!("unexpected '{}'", c))),
+    }
+  }
+
+  // A + or - between terms
+  fn sign(&mut self) -> Option<f64> {
+    match self.peek() {
+      Some('+') => {
+        self.chars.next();
+        Some(1.0)
+      }
+      Some('-') => {
+        self.chars.next();
+        Some(-1.0)
+      }
+      _ => None,
+    }
+  }
+
+  fn term(&mut self) -> Result<(f64, PauliString), PauliParseError> {
+    match self.peek() {
+      Some(c) if c.is_ascii_digit() || c == '.' => {
+        let coefficient = self.number()?;
+        // The * after the coefficient can be left out, as in "0.5 Z0"
+        if self.peek() == Some('*') {
+          self.chars.next();
+          return Ok((coefficient, self.pauli_string()?));
+        }
+        if matches!(self.peek(), Some(c) if Pauli::from_symbol(c).is_some()) {
+          return Ok((coefficient, self.pauli_string()?));
+        }
+        Ok((coefficient, PauliString::new()))
+      }
+      _ => Ok((1.0, self.pauli_string()?)),
+    }
+  }
+
+  fn number(&mut self) -> Result<f64, PauliParseError> {
+    let column = self.column();
+    let start = self.chars.peek().map_or(self.text.len(), |(i, _)| *i);
+    let mut end = start;
+    let mut previous = ' ';
+    while let Some((i, c)) = self.chars.peek().copied() {
+      let exponent_sign = (c == '+' || c == '-') && (previous == 'e' || previous == 'E');
+      if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign) {
+        break;
+      }
+      self.chars.next();
+      end = i + c.len_utf8();
+      previous = c;
+    }
+    self.text[start..end].parse().map_err(|_| {
+      PauliParseError::new(
+        format!("invalid number '{}'", &self.text[start..end]),
+        column,
+      )
+    })
+  }
+
+  fn pauli_string(&mut self) -> Result<PauliString, PauliParseError> {
+    let mut string = PauliString::new();
+    let mut empty = true;
+    loop {
+      let column = self.column();
+      let pauli = match self.peek().and_then(Pauli::from_symbol) {
+        Some(pauli) => pauli,
+        None if empty => return Err(self.error("expected a Pauli operator X, Y, Z or I")),
+        None => return Ok(string),
+      };
+      self.chars.next();
+      empty = false;
+      // The digits have to follow the letter directly, so no skipping whitespace here
+      let mut digits = String::new();
+      while let Some((_, c)) = self.chars.peek().filter(|(_, c)| c.is_ascii_digit()) {
+        digits.push(*c);
+        self.chars.next();
+      }
+      if digits.is_empty() {
+        // A lone I is the identity, which does not need a qubit
+        if pauli == Pauli::I {
+          continue;
+        }
+        return Err(PauliParseError::new(
+          format!("expected a qubit number after {}", pauli.symbol()),
+          column + 1,
+        ));
+      }
+      let qubit: usize = digits
+        .parse()
+        .map_err(|_| PauliParseError::new(format!("qubit {} is too large", digits), column + 1))?;
+      if string.get(qubit) != Pauli::I {
+        return Err(PauliParseError::new(
+          format!("qubit {} appears twice in the same Pauli string", qubit),
+          column,
+        ));
+      }
+      string.set(qubit, pauli);
+      // Operators can be separated by *, as in Z0*Z1
+      if self.peek() == Some('*') {
+        self.chars.next();
+        empty = true;
+      }
+    }
+  }
+}
+
+#[test]
+fn parse_pauli_sums() {
+  let sum: PauliSum = "0.5*Z0Z1 + 0.3*X2".parse().unwrap();
+  let mut z0z1 = PauliString::new();
+  z0z1.set(0, Pauli::Z).set(1, Pauli::Z);
+  let mut x2 = PauliString::new();
+  x2.set(2, Pauli::X);
+  assert_eq!(sum.terms(), &[(0.5, z0z1.clone()), (0.3, x2)]);
+  assert_eq!("Z0 * Z1".parse::<PauliString>().unwrap(), z0z1);
+  assert_eq!("Z1 Z0 I4".parse::<PauliString>().unwrap(), z0z1);
+  let sum: PauliSum = "-1.5e-1 Y3 - X0*Z2 + 2 + I".parse().unwrap();
+  assert_eq!(sum.terms().len(), 4);
+  assert_eq!(sum.terms()[0].0, -0.15);
+  assert_eq!(sum.terms()[0].1.get(3), Pauli::Y);
+  assert_eq!(sum.terms()[1].0, -1.0);
+  assert!(sum.terms()[2].1.is_identity() && sum.terms()[3].1.is_identity());
+  // Displaying and parsing again gives the same sum
+  assert_eq!(sum.to_string(), "-0.15*Y3 - 1*X0Z2 + 2 + 1");
+  assert_eq!(sum.to_string().parse::<PauliSum>().unwrap(), sum);
+}
+
+#[test]
+fn pauli_parse_errors() {
+  let error = "0.5*Z0 + X".parse::<PauliSum>().unwrap_err();
+  assert_eq!(error.column, 11);
+  assert!(error.message.contains("qubit number"), "{}", error);
+  let error = "Z0Z0".parse::<PauliString>().unwrap_err();
+  assert!(error.message.contains("twice"), "{}", error);
+  assert_eq!(error.column, 3);
+  assert_eq!("0.5*".parse::<PauliSum>().unwrap_err().column, 5);
+  assert!("0.5*Q1".parse::<PauliSum>().is_err());
+  assert!("Z0 +".parse::<PauliSum>().is_err());
+  assert!("1.2.3*X0".parse::<PauliSum>().is_err());
+  assert!("Z0 X1)".parse::<PauliSum>().is_err());
+}
+
+#[test]
+fn exact_expectation_values() {
+  use crate::ket::*;
+  // |+> ⊗ |i> ⊗ |1>
+  let state = KET_PLUS.tensor(KET_PLUS_I).tensor(KET_ONE);
+  let expect = |text: &str| text.parse::<PauliSum>().unwrap().expectation(&state);
+  assert!((expect("X0") - 1.0).abs() < 1e-12);
+  assert!((expect("Y1") - 1.0).abs() < 1e-12);
+  assert!((expect("Z2") + 1.0).abs() < 1e-12);
+  assert!(expect("Z0").abs() < 1e-12);
+  assert!((expect("X0 Y1 Z2") + 1.0).abs() < 1e-12);
+  assert!((expect("0.5*X0Y1 + 0.3*Z2 - 2") - (0.5 - 0.3 - 2.0)).abs() < 1e-12);
+  // The same as the matrix of the string, applied to the state
+  let mut bell = StateVector::new(2);
+  bell.apply(&HADAMARD, 0);
+  bell.apply_controlled(&PAULI_X, &[0], 1);
+  bell.apply(&Gate::ry(0.4), 1);
+  for text in &["X0X1", "Y0Y1", "Z0Z1", "X0Z1", "Y0X1", "Z1"] {
+    let string: PauliString = text.parse().unwrap();
+    let mut applied = bell.clone();
+    for (qubit, pauli) in string.paulis() {
+      applied.apply(&pauli.gate(), qubit);
+    }
+    let expected = bell.inner(&applied);
+    assert!(
+      (string.expectation(&bell) - expected.re).abs() < 1e-12,
+      "{}",
+      text
+    );
+  }
+}
+
+#[test]
+fn grouping_by_measurement_basis() {
+  let sum: PauliSum = "Z0Z1 + Z1Z2 + X0 + 0.5 + X0X1 + Y2 + Z0".parse().unwrap();
+  assert_eq!(sum.measurement_groups(), vec![vec![0, 1, 6], vec![2, 4, 5]]);
+}
+
+#[test]
+fn estimates_converge_to_expectation() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(36);
+  let mut state = StateVector::new(3);
+  state.apply(&Gate::ry(0.7), 0);
+  state.apply_controlled(&PAULI_X, &[0], 1);
+  state.apply(&Gate::u3(1.1, 0.4, -0.2), 2);
+  state.apply_controlled(&Gate::rx(0.9), &[2], 1);
+  let hamiltonian: PauliSum = "0.5*Z0Z1 + 0.3*X2 - 0.7*Y1Y2 + 0.2*X0X1X2 + 1.5"
+    .parse()
+    .unwrap();
+  let exact = hamiltonian.expectation(&state);
+  let estimate = hamiltonian.estimate(&state, 20_000, &mut rng);
+  assert!((estimate - exact).abs() < 0.03, "{} {}", estimate, exact);
+  // Without any Pauli terms, there is nothing random
+  let constant: PauliSum = "1.5 - 0.5".parse().unwrap();
+  assert_eq!(constant.estimate(&state, 1, &mut rng), 1.0);
+}
@@ -0,0 +1,796 @@
+//! Reading OpenQASM programs into circuits, and writing circuits out as OpenQASM.
+//!
+//! OpenQASM 2.0 is supported in full, apart from opaque gates, which have no definition to
+//! simulate. The gates of "qelib1.inc" are built in, and become available with
+//! `include "qelib1.inc";` like in any other OpenQASM toolchain.
+//!
+//! OpenQASM 3 programs are supported in a subset, which covers dynamic circuits:
+//!
+//! - `qubit[n] q;`, `qubit q;`, `bit[n] c;` and `bit c;` declarations, as well as the old
+//!   `qreg` and `creg`. Sizes must be integer literals.
+//! - `include "stdgates.inc";`, gate definitions, and `gphase(θ);`.
+//! - The gate modifiers `inv @`, `pow(k) @` for integer `k` up to 1024 in absolute value, `ctrl(n) @` and `negctrl(n) @`.
+//! - Measurement, both `c[0] = measure q[0];` and `measure q[0] -> c[0];`, and `reset`,
+//!   anywhere in the program.
+//! - `if (condition) { ... } else { ... }` and `while (condition) { ... }`, where the
+//!   condition is `c == value`, `c != value`, `c` or `!c`, for a register or a bit `c`.
+//! - `for i in [start:end] { ... }`, `[start:step:end]` or `{a, b, c}`, with an optional type
+//!   in front of the variable. Ranges include their end, and the loop variable can be used
+//!   in gate parameters and indices.
+//!
+//! Classical variables and arithmetic, subroutines, `const`, `input`/`output`, timing and
+//! pulse level statements are not supported, and are reported as errors.
+//!
+//! `for` loops are unrolled and gate modifiers expanded while parsing, while `if` and `while`
+//! become `Instruction::If` and `Instruction::While`. Run the circuit with `Circuit::sample`
+//! to simulate it shot by shot, every shot starting from all qubits in `KET_ZERO`.
+//!
+//! Registers are laid out one after another in the order they are declared: with
+//! `qreg a[2]; qreg b[1];`, `a[0]` is qubit 0 of the circuit and `b[0]` is qubit 2.
+//! Classical registers are laid out the same way.
+//!
+//! Exported programs use a single quantum register `q`, and split the classical bits into
+//! registers `c0`, `c1`, ... so that every condition tests a whole register. Parameters are
+//! printed with enough digits to read back the exact same value.
+
+use crate::circuit::Circuit;
+use std::fmt;
+
+mod export;
+mod lexer;
+mod lower;
+mod parser;
+
+/// A position in the source text. Lines and columns both start from 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+  pub line: usize,
+  pub column: usize,
+}
+
+/// An error in an OpenQASM program, and where in the source it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+  pub message: String,
+  pub span: Span,
+}
+
+impl ParseError {
+  pub(crate) fn new<S: Into<String>>(message: S, span: Span) -> ParseError {
+    ParseError {
+      message: message.into(),
+      span,
+    }
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "line {}, column {}: {}",
+      self.span.line, self.span.column, self.message
+    )
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+pub use export::ExportError;
+
+/// Parses an OpenQASM 2.0 or 3 program into a circuit.
+pub fn parse(source: &str) -> Result<Circuit, ParseError> {
+  let tokens = lexer::tokenize(source)?;
+  let program = parser::parse_program(tokens)?;
+  if program.version.0 != 2 && program.version.0 != 3 {
+    return Err(ParseError::new(
+      format!("unsupported OpenQASM version {}", program.version.0),
+      program.version.1,
+    ));
+  }
+  lower::lower(&program)
+}
+
+/// Writes a circuit out as an OpenQASM 2.0 program, which `parse` can read back in.
+pub fn export(circuit: &Circuit) -> Result<String, ExportError> {
+  export::export(circuit)
+}
+
+#[cfg(test)]
+use crate::circuit::{Condition, GateKind, Instruction};
+
+#[cfg(test)]
+fn gate(kind: GateKind, controls: &[usize], target: usize) -> Instruction {
+  Instruction::Gate {
+    kind,
+    target,
+    controls: controls.to_vec(),
+  }
+}
+
+#[test]
+fn parse_bell_pair() {
+  let circuit = parse(
+    r#"
+    OPENQASM 2.0;
+    include "qelib1.inc";
+    qreg q[2];
+    creg c[2];
+    h q[0];
+    cx q[0], q[1];
+    measure q -> c;
+    "#,
+  )
+  .unwrap();
+  assert_eq!(circuit.num_qubits(), 2);
+  assert_eq!(circuit.num_clbits(), 2);
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      gate(GateKind::H, &[], 0),
+      gate(GateKind::X, &[0], 1),
+      Instruction::Measure { qubit: 0, clbit: 0 },
+      Instruction::Measure { qubit: 1, clbit: 1 },
+    ]
+  );
+}
+
+#[test]
+fn registers_are_laid_out_in_order() {
+  let circuit = parse(
+    r#"
+    OPENQASM 2.0;
+    include "qelib1.inc";
+    qreg a[2];
+    qreg b[3];
+    x b[1];
+    U(pi, 0, pi) a[1];
+    "#,
+  )
+  .unwrap();
+  assert_eq!(circuit.num_qubits(), 5);
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      gate(GateKind::X, &[], 3),
+      gate(
+        GateKind::U3(std::f64::consts::PI, 0.0, std::f64::consts::PI),
+        &[],
+        1
+      ),
+    ]
+  );
+}
+
+#[test]
+fn gates_broadcast_over_registers() {
+  let circuit = parse(
+    r#"
+    OPENQASM 2.0;
+    include "qelib1.inc";
+    qreg a[2];
+    qreg b[2];
+    qreg c[1];
+    cx a, b;
+    ccx c[0], a, b;
+    "#,
+  )
+  .unwrap();
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      gate(GateKind::X, &[0], 2),
+      gate(GateKind::X, &[1], 3),
+      gate(GateKind::X, &[4, 0], 2),
+      gate(GateKind::X, &[4, 1], 3),
+    ]
+  );
+}
+
+#[test]
+fn user_defined_gates_expand() {
+  let circuit = parse(
+    r#"
+    OPENQASM 2.0;
+    include "qelib1.inc";
+    gate rot(theta, phi) a { rz(phi) a; ry(theta / 2) a; }
+    gate pair(theta) a, b { rot(theta, -theta) b; barrier a, b; cx a, b; }
+    qreg q[2];
+    pair(pi) q[1], q[0];
+    "#,
+  )
+  .unwrap();
+  let pi = std::f64::consts::PI;
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      gate(GateKind::Rz(-pi), &[], 0),
+      gate(GateKind::Ry(pi / 2.0), &[], 0),
+      Instruction::Barrier { qubits: vec![1, 0] },
+      gate(GateKind::X, &[1], 0),
+    ]
+  );
+}
+
+#[test]
+fn conditional_reset_and_barrier() {
+  let circuit = parse(
+    r#"
+    OPENQASM 2.0;
+    include "qelib1.inc";
+    qreg q[1];
+    creg c[2];
+    measure q[0] -> c[1];
+    if (c == 2) x q[0];
+    reset q;
+    barrier q;
+    "#,
+  )
+  .unwrap();
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      Instruction::Measure { qubit: 0, clbit: 1 },
+      Instruction::If {
+        condition: Condition::new(vec![0, 1], 2),
+        then_branch: vec![gate(GateKind::X, &[], 0)],
+        else_branch: vec![],
+      },
+      Instruction::Reset { qubit: 0 },
+      Instruction::Barrier { qubits: vec![0] },
+    ]
+  );
+}
+
+#[test]
+fn parsed_circuit_runs() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+  // Teleport |1> from q[0] to q[2]
+  let circuit = parse(
+    r#"
+    OPENQASM 2.0;
+    include "qelib1.inc";
+    qreg q[3];
+    creg a[1];
+    creg b[1];
+    creg out[1];
+    x q[0];
+    h q[1];
+    cx q[1], q[2];
+    cx q[0], q[1];
+    h q[0];
+    measure q[0] -> a[0];
+    measure q[1] -> b[0];
+    if (b == 1) x q[2];
+    if (a == 1) z q[2];
+    measure q[2] -> out[0];
+    "#,
+  )
+  .unwrap();
+  for _ in 0..16 {
+    assert!(circuit.run(&mut rng).classical[2]);
+  }
+}
+
+#[test]
+fn standard_gates_need_include() {
+  let error = parse("OPENQASM 2.0;\nqreg q[1];\nh q[0];").unwrap_err();
+  assert_eq!(error.span, Span { line: 3, column: 1 });
+  assert!(error.message.starts_with("unknown gate 'h'"));
+  // The built in gates are always there
+  assert!(parse("OPENQASM 2.0;\nqreg q[2];\nU(0, 0, 0) q[0];\nCX q[0], q[1];").is_ok());
+}
+
+#[test]
+fn each_version_has_its_own_standard_library() {
+  let qasm2 = |line: &str| {
+    parse(&format!(
+      "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[5];\n{}",
+      line
+    ))
+  };
+  let qasm3 = |line: &str| {
+    parse(&format!(
+      "OPENQASM 3;\ninclude \"stdgates.inc\";\nqubit[5] q;\n{}",
+      line
+    ))
+  };
+  for line in &[
+    "rzz(0.1) q[0], q[1];",
+    "rxx(0.1) q[0], q[1];",
+    "c3x q[0], q[1], q[2], q[3];",
+    "c4x q[0], q[1], q[2], q[3], q[4];",
+    "cu3(0.1, 0.2, 0.3) q[0], q[1];",
+    "u0(1) q[0];",
+    "u(0.1, 0.2, 0.3) q[0];",
+    "cu1(0.1) q[0], q[1];",
+    "sxdg q[0];",
+  ] {
+    assert!(qasm2(line).is_ok(), "{}", line);
+    let error = qasm3(line).unwrap_err();
+    assert!(error.message.starts_with("unknown gate"), "{}", error);
+  }
+  // OpenQASM 2.0 has no gates without qubits, so call gphase on one to get past the parser
+  let error = qasm2("gphase(0.1) q[0];").unwrap_err();
+  assert!(
+    error.message.starts_with("unknown gate 'gphase'"),
+    "{}",
+    error
+  );
+  assert!(qasm3("gphase(0.1);").is_ok());
+  for line in &["phase(0.1) q[0];", "cphase(0.1) q[0], q[1];"] {
+    let error = qasm2(line).unwrap_err();
+    assert!(error.message.starts_with("unknown gate"), "{}", error);
+    assert!(qasm3(line).is_ok(), "{}", line);
+  }
+  // CX is built into OpenQASM 2.0, but comes from "stdgates.inc" in OpenQASM 3
+  let error = parse("OPENQASM 3;\nqubit[2] q;\nCX q[0], q[1];").unwrap_err();
+  assert!(
+    error.message.contains("include \"stdgates.inc\""),
+    "{}",
+    error
+  );
+  assert!(qasm3("CX q[0], q[1];").is_ok());
+}
+
+#[test]
+fn errors_have_positions() {
+  let cases = [
+    ("OPENQASM 2.0;\nqreg q[2];\nx q[2];", 3, 3, "out of range"),
+    (
+      "OPENQASM 2.0;\nqreg q[2];\ncx q[0], q[0];",
+      3,
+      1,
+      "more than once",
+    ),
+    (
+      "OPENQASM 2.0;\nqreg q[2];\n  rx(1) r[0];",
+      3,
+      9,
+      "unknown quantum register 'r'",
+    ),
+    (
+      "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nrx q[0];",
+      4,
+      1,
+      "takes 1 parameters",
+    ),
+    (
+      "OPENQASM 2.0;\ngate g a { h b; }",
+      2,
+      14,
+      "own qubit arguments",
+    ),
+    (
+      "OPENQASM 2.0;\nqreg q[1];\nqreg q[2];",
+      3,
+      1,
+      "already defined",
+    ),
+    (
+      "OPENQASM 2.0;\nqreg q[2];\nqreg r[3];\nCX q, r;",
+      4,
+      1,
+      "different sizes",
+    ),
+    (
+      "OPENQASM 4.0;\nqubit q;",
+      1,
+      10,
+      "unsupported OpenQASM version 4",
+    ),
+    (
+      "OPENQASM 2.0;\nqreg q[1];\nqubit q;",
+      3,
+      1,
+      "unknown gate 'qubit'",
+    ),
+  ];
+  for (source, line, column, message) in &cases {
+    let error = parse(source).unwrap_err();
+    assert_eq!(
+      error.span,
+      Span {
+        line: *line,
+        column: *column
+      },
+      "{}",
+      error
+    );
+    assert!(error.message.contains(message), "{}", error);
+  }
+}
+
+#[test]
+fn openqasm3_declarations_and_modifiers() {
+  let circuit = parse(
+    r#"
+    OPENQASM 3;
+    include "stdgates.inc";
+    qubit[2] q;
+    qubit a;
+    bit[2] c;
+    ctrl @ x q[0], a;
+    ctrl(2) @ h q[0], q[1], a;
+    negctrl @ z q[1], a;
+    inv @ s a;
+    pow(2) @ t a;
+    inv @ pow(-1) @ rx(0.5) a;
+    ctrl @ gphase(pi) q[0];
+    c[1] = measure a;
+    "#,
+  )
+  .unwrap();
+  assert_eq!((circuit.num_qubits(), circuit.num_clbits()), (3, 2));
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      gate(GateKind::X, &[0], 2),
+      gate(GateKind::H, &[0, 1], 2),
+      gate(GateKind::X, &[], 1),
+      gate(GateKind::Z, &[1], 2),
+      gate(GateKind::X, &[], 1),
+      gate(GateKind::Sdg, &[], 2),
+      gate(GateKind::T, &[], 2),
+      gate(GateKind::T, &[], 2),
+      gate(GateKind::Rx(0.5), &[], 2),
+      gate(GateKind::Phase(std::f64::consts::PI), &[], 0),
+      Instruction::Measure { qubit: 2, clbit: 1 },
+    ]
+  );
+}
+
+#[test]
+fn openqasm3_modifiers_on_user_gates() {
+  // A controlled gate definition picks up the global phase of its body
+  let circuit = parse(
+    r#"
+    OPENQASM 3.0;
+    qubit[2] q;
+    gate minus_x a { U(pi, 0, pi) a; gphase(pi); }
+    inv @ ctrl @ minus_x q[0], q[1];
+    "#,
+  )
+  .unwrap();
+  let pi = std::f64::consts::PI;
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      gate(GateKind::Phase(-pi), &[], 0),
+      gate(GateKind::U3(-pi, -pi, -0.0), &[0], 1),
+    ]
+  );
+}
+
+#[test]
+fn openqasm3_for_loops_unroll() {
+  let circuit = parse(
+    r#"
+    OPENQASM 3.0;
+    include "stdgates.inc";
+    qubit[4] q;
+    for int i in [0:2] {
+      cx q[i], q[i + 1];
+    }
+    for i in [3:-2:0] rz(i * pi) q[i];
+    for uint i in {1, 3} x q[i];
+    "#,
+  )
+  .unwrap();
+  let pi = std::f64::consts::PI;
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      gate(GateKind::X, &[0], 1),
+      gate(GateKind::X, &[1], 2),
+      gate(GateKind::X, &[2], 3),
+      gate(GateKind::Rz(3.0 * pi), &[], 3),
+      gate(GateKind::Rz(pi), &[], 1),
+      gate(GateKind::X, &[], 1),
+      gate(GateKind::X, &[], 3),
+    ]
+  );
+}
+
+#[test]
+fn openqasm3_if_else_and_while() {
+  let circuit = parse(
+    r#"
+    OPENQASM 3.0;
+    include "stdgates.inc";
+    qubit q;
+    bit[2] c;
+    c[0] = measure q;
+    if (c[0]) {
+      x q;
+    } else if (c != 2) {
+      h q;
+      reset q;
+    }
+    while (!c[1]) c[1] = measure q;
+    "#,
+  )
+  .unwrap();
+  assert_eq!(
+    circuit.instructions()[1],
+    Instruction::If {
+      condition: Condition::not_equal(vec![0], 0),
+      then_branch: vec![gate(GateKind::X, &[], 0)],
+      else_branch: vec![Instruction::If {
+        condition: Condition::not_equal(vec![0, 1], 2),
+        then_branch: vec![gate(GateKind::H, &[], 0), Instruction::Reset { qubit: 0 }],
+        else_branch: vec![],
+      }],
+    }
+  );
+  assert_eq!(
+    circuit.instructions()[2],
+    Instruction::While {
+      condition: Condition::new(vec![1], 0),
+      body: vec![Instruction::Measure { qubit: 0, clbit: 1 }],
+    }
+  );
+}
+
+#[test]
+fn openqasm3_dynamic_circuit_runs_shot_by_shot() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+  // Prepare |1> on the second qubit by measuring a |+> qubit and correcting the outcome,
+  // then keep flipping a coin until it comes up 1
+  let circuit = parse(
+    r#"
+    OPENQASM 3.0;
+    include "stdgates.inc";
+    qubit[2] q;
+    bit[3] c;
+    h q[0];
+    c[0] = measure q[0];
+    if (c[0] == 0) x q[0];
+    reset q[1];
+    ctrl @ x q[0], q[1];
+    c[1] = measure q[1];
+    while (c[2] == false) {
+      reset q[0];
+      h q[0];
+      c[2] = measure q[0];
+    }
+    "#,
+  )
+  .unwrap();
+  let counts = circuit.sample(200, &mut rng);
+  assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["011", "111"]);
+  assert!((70..130).contains(&counts["111"]), "{:?}", counts);
+}
+
+#[test]
+fn openqasm3_errors() {
+  let cases = [
+    (
+      "OPENQASM 3;\nqubit q;\nconst int n = 2;",
+      3,
+      1,
+      "'const' is not supported",
+    ),
+    (
+      "OPENQASM 3;\ninclude \"qelib1.inc\";",
+      2,
+      1,
+      "only \"stdgates.inc\"",
+    ),
+    ("OPENQASM 3;\nqubit q;\nh q;", 3, 1, "stdgates.inc"),
+    (
+      "OPENQASM 3;\nqubit q;\npow(0.5) @ U(0, 0, 0) q;",
+      3,
+      12,
+      "integer powers",
+    ),
+    (
+      "OPENQASM 3;\nqubit q;\npow(-9223372036854775808) @ U(0, 0, 0) q;",
+      3,
+      29,
+      "at most 1024",
+    ),
+    (
+      "OPENQASM 3;\nqubit q;\npow(1e15) @ U(0, 0, 0) q;",
+      3,
+      13,
+      "at most 1024",
+    ),
+    (
+      "OPENQASM 3;\nqubit[2] q;\nctrl @ ctrl(1e30) @ U(0, 0, 0) q[0], q[1];",
+      3,
+      21,
+      "not enough qubits",
+    ),
+    (
+      "OPENQASM 3;\nqubit q;\nctrl(2) @ U(0, 0, 0) q;",
+      3,
+      11,
+      "not enough qubits",
+    ),
+    (
+      "OPENQASM 3;\nqubit[2] q;\nfor i in [0:2] U(0, 0, 0) q[i];",
+      3,
+      27,
+      "out of range",
+    ),
+  ];
+  for (source, line, column, message) in &cases {
+    let error = parse(source).unwrap_err();
+    assert_eq!(
+      error.span,
+      Span {
+        line: *line,
+        column: *column
+      },
+      "{}",
+      error
+    );
+    assert!(error.message.contains(message), "{}", error);
+  }
+}
+
+#[test]
+fn composite_standard_gates() {
+  use num_complex::Complex64;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+  // rxx(π) is exp(-iπ/2 XX), taking |00> to -i|11>
+  let circuit =
+    parse("OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nrxx(pi) q[0], q[1];").unwrap();
+  let state = circuit.run(&mut rng).state;
+  assert!((state.amplitude(0b11) - Complex64::new(0.0, -1.0)).norm() < 1e-12);
+  // rzz(θ) multiplies |01> and |10> by e^(iθ)
+  let circuit =
+    parse("OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nx q[1];\nrzz(0.5) q[0], q[1];")
+      .unwrap();
+  let state = circuit.run(&mut rng).state;
+  assert!((state.amplitude(0b01) - Complex64::from_polar(1.0, 0.5)).norm() < 1e-12);
+}
+
+#[test]
+fn export_bell_pair() {
+  let mut circuit = Circuit::new(2, 2);
+  circuit.h(0).cx(0, 1).measure_all();
+  assert_eq!(
+    export(&circuit).unwrap(),
+    "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\ncreg c0[2];\n\
+     h q[0];\ncx q[0], q[1];\nmeasure q[0] -> c0[0];\nmeasure q[1] -> c0[1];\n"
+  );
+}
+
+#[test]
+fn export_round_trips() {
+  let mut circuit = Circuit::new(4, 3);
+  circuit
+    .h(0)
+    .x(1)
+    .y(2)
+    .z(3)
+    .s(0)
+    .sdg(1)
+    .t(2)
+    .tdg(3)
+    .i(0)
+    .rx(0.1, 0)
+    .ry(-1.0 / 3.0, 1)
+    .rz(std::f64::consts::E, 2)
+    .p(1e-10, 3)
+    .u3(0.1, 0.2, 0.3, 0)
+    .cx(0, 1)
+    .cy(1, 2)
+    .cz(2, 3)
+    .controlled(GateKind::H, &[3], 0)
+    .controlled(GateKind::Rx(0.7), &[0], 2)
+    .controlled(GateKind::Ry(0.8), &[1], 3)
+    .controlled(GateKind::Rz(0.9), &[2], 0)
+    .controlled(GateKind::Phase(1.1), &[3], 1)
+    .controlled(GateKind::U3(1.2, 1.3, 1.4), &[0], 3)
+    .ccx(0, 1, 2)
+    .controlled(GateKind::X, &[0, 1, 2], 3)
+    .swap(0, 3)
+    .cswap(1, 2, 0)
+    .barrier(&[0, 1])
+    .reset(2)
+    .measure(0, 0)
+    .measure(1, 2)
+    .push(Instruction::If {
+      condition: Condition::new(vec![0, 1], 1),
+      then_branch: vec![gate(GateKind::Ry(2.5), &[1], 2)],
+      else_branch: vec![],
+    });
+  let text = export(&circuit).unwrap();
+  assert_eq!(parse(&text).unwrap(), circuit, "{}", text);
+  // Conditional blocks come back as one condition per instruction
+  let mut circuit = Circuit::new(1, 1);
+  circuit.push(Instruction::If {
+    condition: Condition::new(vec![0], 1),
+    then_branch: vec![gate(GateKind::X, &[], 0), gate(GateKind::H, &[], 0)],
+    else_branch: vec![],
+  });
+  let text = export(&circuit).unwrap();
+  assert!(
+    text.ends_with("if (c0 == 1) x q[0];\nif (c0 == 1) h q[0];\n"),
+    "{}",
+    text
+  );
+}
+
+#[test]
+fn export_drops_conditional_barriers() {
+  let mut circuit = Circuit::new(2, 1);
+  circuit.measure(0, 0).push(Instruction::If {
+    condition: Condition::new(vec![0], 1),
+    then_branch: vec![
+      Instruction::Barrier { qubits: vec![0, 1] },
+      gate(GateKind::X, &[], 1),
+    ],
+    else_branch: vec![],
+  });
+  let text = export(&circuit).unwrap();
+  assert!(!text.contains("barrier"), "{}", text);
+  let mut expected = Circuit::new(2, 1);
+  expected.measure(0, 0).push(Instruction::If {
+    condition: Condition::new(vec![0], 1),
+    then_branch: vec![gate(GateKind::X, &[], 1)],
+    else_branch: vec![],
+  });
+  assert_eq!(parse(&text).unwrap(), expected, "{}", text);
+}
+
+#[test]
+fn export_round_trips_unitaries() {
+  use crate::gate::*;
+  use crate::state_vector::StateVector;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+  let custom = Gate::global_phase(0.4) * Gate::u3(0.5, -0.6, 2.7);
+  let mut circuit = Circuit::new(3, 0);
+  circuit
+    .h(0)
+    .h(1)
+    .rx(0.3, 2)
+    .unitary(custom, 1)
+    .controlled(GateKind::Custom(custom), &[0], 2)
+    .controlled(GateKind::T, &[2], 1)
+    .controlled(GateKind::Sdg, &[1], 0)
+    .iswap(0, 2);
+  let text = export(&circuit).unwrap();
+  let parsed = parse(&text).unwrap();
+  let expected = circuit.run(&mut rng).state;
+  let actual = parsed.run(&mut rng).state;
+  // The uncontrolled custom gate loses its global phase, so compare up to a global phase
+  let overlap: num_complex::Complex64 = expected
+    .amplitudes()
+    .iter()
+    .zip(actual.amplitudes())
+    .map(|(a, b)| a.conj() * b)
+    .sum();
+  assert!((overlap.norm() - 1.0).abs() < 1e-12, "{}", text);
+  assert!(actual != StateVector::new(3));
+}
+
+#[test]
+fn export_unsupported() {
+  let mut circuit = Circuit::new(3, 2);
+  circuit.controlled(GateKind::H, &[0, 1], 2);
+  assert!(export(&circuit).is_err());
+  let mut circuit = Circuit::new(1, 2);
+  circuit.push(Instruction::If {
+    condition: Condition::new(vec![1, 0], 1),
+    then_branch: vec![gate(GateKind::X, &[], 0)],
+    else_branch: vec![],
+  });
+  assert!(export(&circuit).is_err());
+  for angle in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+    let mut circuit = Circuit::new(2, 0);
+    circuit.rx(*angle, 0);
+    let error = export(&circuit).unwrap_err();
+    assert!(error.message.contains("not a finite number"), "{}", error);
+    let mut circuit = Circuit::new(2, 0);
+    circuit.controlled(GateKind::U3(0.1, *angle, 0.2), &[0], 1);
+    assert!(export(&circuit).is_err());
+  }
+}
@@ -0,0 +1,327 @@
+use crate::circuit::*;
+use std::f64::consts::PI;
+use std::fmt;
+use std::fmt::Write;
+
+/// A circuit that can not be written out as OpenQASM 2.0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportError {
+  pub message: String,
+}
+
+impl ExportError {
+  pub(crate) fn new<S: Into<String>>(message: S) -> ExportError {
+    ExportError {
+      message: message.into(),
+    }
+  }
+}
+
+impl fmt::Display for ExportError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+impl std::error::Error for ExportError {}
+
+// There is no iSWAP in "qelib1.inc", so define it when it is needed
+const ISWAP_DEFINITION: &str = "gate iswap a, b { s a; s b; h a; cx a, b; cx b, a; h b; }\n";
+
+pub(crate) fn export(circuit: &Circuit) -> Result<String, ExportError> {
+  let cregs = classical_registers(circuit)?;
+  let mut body = String::new();
+  let mut uses_iswap = false;
+  for instruction in circuit.instructions() {
+    uses_iswap |= write_instruction(&mut body, instruction, &cregs, "")?;
+  }
+  let mut out = String::from("OPENQASM 2.0;\ninclude \"qelib1.inc\";\n");
+  if uses_iswap {
+    out.push_str(ISWAP_DEFINITION);
+  }
+  if circuit.num_qubits() > 0 {
+    writeln!(out, "qreg q[{}];", circuit.num_qubits()).unwrap();
+  }
+  for (index, (start, end)) in cregs.iter().enumerate() {
+    writeln!(out, "creg c{}[{}];", index, end - start).unwrap();
+  }
+  out.push_str(&body);
+  Ok(out)
+}
+
+// OpenQASM 2.0 conditions always test a whole classical register. So split the classical bits
+// into registers, making sure that every condition lines up with exactly one register.
+// Returns the registers as (start, end) ranges of classical bits.
+fn classical_registers(circuit: &Circuit) -> Result<Vec<(usize, usize)>, ExportError> {
+  let mut conditions = Vec::new();
+  collect_conditions(circuit.instructions(), &mut conditions);
+  let mut ranges: Vec<(usize, usize)> = Vec::new();
+  for condition in conditions {
+    let start = condition.clbits.first().copied().unwrap_or(0);
+    let contiguous = condition
+      .clbits
+      .iter()
+      .enumerate()
+      .all(|(i, clbit)| *clbit == start + i);
+    if condition.clbits.is_empty() || !contiguous {
+      return Err(ExportError::new(
+        "conditions must test consecutive classical bits, in order",
+      ));
+    }
+    let range = (start, start + condition.clbits.len());
+    if ranges.contains(&range) {
+      continue;
+    }
+    if ranges
+      .iter()
+      .any(|(other_start, other_end)| range.0 < *other_end && *other_start < range.1)
+    {
+      return Err(ExportError::new(
+        "conditions test partly overlapping classical bits",
+      ));
+    }
+    ranges.push(range);
+  }
+  // Fill the gaps between the conditions with registers of their own
+  ranges.sort_unstable();
+  let mut registers = Vec::new();
+  let mut next = 0;
+  for (start, end) in ranges {
+    if next < start {
+      registers.push((next, start));
+    }
+    registers.push((start, end));
+    next = end;
+  }
+  if next < circuit.num_clbits() {
+    registers.push((next, circuit.num_clbits()));
+  }
+  Ok(registers)
+}
+
+fn collect_conditions<'a>(instructions: &'a [Instruction], out: &mut Vec<&'a Condition>) {
+  for instruction in instructions {
+    match instruction {
+      Instruction::If {
+        condition,
+        then_branch,
+        else_branch,
+      } => {
+        out.push(condition);
+        collect_conditions(then_branch, out);
+        collect_conditions(else_branch, out);
+      }
+      Instruction::While { condition, body } => {
+        out.push(condition);
+        collect_conditions(body, out);
+      }
+      _ => {}
+    }
+  }
+}
+
+fn clbit(cregs: &[(usize, usize)], clbit: usize) -> String {
+  let (index, (start, _)) = cregs
+    .iter()
+    .enumerate()
+    .find(|(_, (start, end))| *start <= clbit && clbit < *end)
+    .expect("every classical bit belongs to a register");
+  format!("c{}[{}]", index, clbit - start)
+}
+
+// Writes one instruction, each line starting with `prefix`.
+// Returns whether the instruction needs the iSWAP gate definition.
+fn write_instruction(
+  out: &mut String,
+  instruction: &Instruction,
+  cregs: &[(usize, usize)],
+  prefix: &str,
+) -> Result<bool, ExportError> {
+  match instruction {
+    Instruction::Gate {
+      kind,
+      target,
+      controls,
+    } => {
+      for line in gate_lines(kind, controls, *target)? {
+        writeln!(out, "{}{}", prefix, line).unwrap();
+      }
+    }
+    Instruction::Swap { a, b, controls } => match controls.as_slice() {
+      [] => writeln!(out, "{}swap q[{}], q[{}];", prefix, a, b).unwrap(),
+      [control] => writeln!(out, "{}cswap q[{}], q[{}], q[{}];", prefix, control, a, b).unwrap(),
+      _ => {
+        return Err(ExportError::new(
+          "swaps with more than one control are not supported",
+        ))
+      }
+    },
+    Instruction::ISwap { a, b } => {
+      writeln!(out, "{}iswap q[{}], q[{}];", prefix, a, b).unwrap();
+      return Ok(true);
+    }
+    Instruction::Measure { qubit, clbit: bit } => writeln!(
+      out,
+      "{}measure q[{}] -> {};",
+      prefix,
+      qubit,
+      clbit(cregs, *bit)
+    )
+    .unwrap(),
+    Instruction::Reset { qubit } => writeln!(out, "{}reset q[{}];", prefix, qubit).unwrap(),
+    Instruction::Barrier { qubits } => {
+      let qubits: Vec<String> = qubits.iter().map(|qubit| format!("q[{}]", qubit)).collect();
+      if !qubits.is_empty() {
+        writeln!(out, "{}barrier {};", prefix, qubits.join(", ")).unwrap();
+      }
+    }
+    Instruction::While { .. } => return Err(ExportError::new("OpenQASM 2.0 has no while loops")),
+    Instruction::Label(_) | Instruction::Jump { .. } => {
+      return Err(ExportError::new("OpenQASM 2.0 has no jumps"))
+    }
+    Instruction::Unitary { .. } => {
+      return Err(ExportError::new(
+        "OpenQASM 2.0 can not define gates from a matrix",
+      ))
+    }
+    Instruction::If {
+      condition,
+      then_branch,
+      else_branch,
+    } => {
+      if condition.comparison != Comparison::Equal {
+        return Err(ExportError::new(
+          "OpenQASM 2.0 conditions can only test for equality",
+        ));
+      }
+      if !else_branch.is_empty() {
+        return Err(ExportError::new(
+          "OpenQASM 2.0 has no else branches for conditions",
+        ));
+      }
+      let register = cregs
+        .iter()
+        .position(|(start, _)| Some(start) == condition.clbits.first())
+        .unwrap();
+      let prefix = format!("{}if (c{} == {}) ", prefix, register, condition.value);
+      let mut uses_iswap = false;
+      for (i, instruction) in then_branch.iter().enumerate() {
+        // A barrier can not be conditional in OpenQASM 2.0. It does not change the state, so
+        // leaving it out of the block does not change what the circuit does either.
+        if matches!(instruction, Instruction::Barrier { .. }) {
+          continue;
+        }
+        // Each instruction gets its own condition, so they must not change the register
+        // for the ones after them
+        let changes_condition = matches!(instruction, Instruction::Measure { clbit, .. } if condition.clbits.contains(clbit));
+        if matches!(
+          instruction,
+          Instruction::If { .. } | Instruction::While { .. }
+        ) || (changes_condition && i + 1 < then_branch.len())
+        {
+          return Err(ExportError::new(
+            "conditional blocks can only hold simple instructions in OpenQASM 2.0",
+          ));
+        }
+        uses_iswap |= write_instruction(out, instruction, cregs, &prefix)?;
+      }
+      return Ok(uses_iswap);
+    }
+  }
+  Ok(false)
+}
+
+// The lines of OpenQASM that apply a gate, using the gates of "qelib1.inc"
+fn gate_lines(
+  kind: &GateKind,
+  controls: &[usize],
+  target: usize,
+) -> Result<Vec<String>, ExportError> {
+  // NaN and infinity have no literal in OpenQASM, so they could not be read back in
+  let params = match kind {
+    GateKind::Rx(theta) | GateKind::Ry(theta) | GateKind::Rz(theta) | GateKind::Phase(theta) => {
+      vec![*theta]
+    }
+    GateKind::U3(theta, phi, lambda) => vec![*theta, *phi, *lambda],
+    GateKind::Custom(gate) => {
+      let (theta, phi, lambda, alpha) = gate.u3_angles();
+      vec![theta, phi, lambda, alpha]
+    }
+    _ => vec![],
+  };
+  if let Some(param) = params.iter().find(|param| !param.is_finite()) {
+    return Err(ExportError::new(format!(
+      "'{}' has a parameter that is not a finite number: {}",
+      kind.name(),
+      param
+    )));
+  }
+  let qubits: Vec<String> = controls
+    .iter()
+    .chain(&[target])
+    .map(|qubit| format!("q[{}]", qubit))
+    .collect();
+  let qubits = qubits.join(", ");
+  let line = |name: &str, params: &[f64]| {
+    if params.is_empty() {
+      format!("{} {};", name, qubits)
+    } else {
+      let params: Vec<String> = params.iter().map(|param| format!("{}", param)).collect();
+      format!("{}({}) {};", name, params.join(", "), qubits)
+    }
+  };
+  let lines = match (controls.len(), kind) {
+    (0, GateKind::I) => vec![line("id", &[])],
+    (0, GateKind::X) => vec![line("x", &[])],
+    (0, GateKind::Y) => vec![line("y", &[])],
+    (0, GateKind::Z) => vec![line("z", &[])],
+    (0, GateKind::H) => vec![line("h", &[])],
+    (0, GateKind::S) => vec![line("s", &[])],
+    (0, GateKind::Sdg) => vec![line("sdg", &[])],
+    (0, GateKind::T) => vec![line("t", &[])],
+    (0, GateKind::Tdg) => vec![line("tdg", &[])],
+    (0, GateKind::Rx(theta)) => vec![line("rx", &[*theta])],
+    (0, GateKind::Ry(theta)) => vec![line("ry", &[*theta])],
+    (0, GateKind::Rz(theta)) => vec![line("rz", &[*theta])],
+    (0, GateKind::Phase(lambda)) => vec![line("u1", &[*lambda])],
+    (0, GateKind::U3(theta, phi, lambda)) => vec![line("u3", &[*theta, *phi, *lambda])],
+    // The global phase of an uncontrolled gate can not be observed, so it can be dropped
+    (0, GateKind::Custom(gate)) => {
+      let (theta, phi, lambda, _) = gate.u3_angles();
+      vec![line("u3", &[theta, phi, lambda])]
+    }
+    (1, GateKind::I) => vec![format!("id q[{}];", target)],
+    (1, GateKind::X) => vec![line("cx", &[])],
+    (1, GateKind::Y) => vec![line("cy", &[])],
+    (1, GateKind::Z) => vec![line("cz", &[])],
+    (1, GateKind::H) => vec![line("ch", &[])],
+    (1, GateKind::S) => vec![line("cu1", &[PI / 2.0])],
+    (1, GateKind::Sdg) => vec![line("cu1", &[-PI / 2.0])],
+    (1, GateKind::T) => vec![line("cu1", &[PI / 4.0])],
+    (1, GateKind::Tdg) => vec![line("cu1", &[-PI / 4.0])],
+    (1, GateKind::Rx(theta)) => vec![line("crx", &[*theta])],
+    (1, GateKind::Ry(theta)) => vec![line("cry", &[*theta])],
+    (1, GateKind::Rz(theta)) => vec![line("crz", &[*theta])],
+    (1, GateKind::Phase(lambda)) => vec![line("cu1", &[*lambda])],
+    (1, GateKind::U3(theta, phi, lambda)) => vec![line("cu3", &[*theta, *phi, *lambda])],
+    // Once controlled, the global phase matters: it becomes a phase on the control qubit
+    (1, GateKind::Custom(gate)) => {
+      let (theta, phi, lambda, alpha) = gate.u3_angles();
+      vec![
+        line("cu3", &[theta, phi, lambda]),
+        format!("u1({}) q[{}];", alpha, controls[0]),
+      ]
+    }
+    (2, GateKind::X) => vec![line("ccx", &[])],
+    (3, GateKind::X) => vec![line("c3x", &[])],
+    (4, GateKind::X) => vec![line("c4x", &[])],
+    (count, kind) => {
+      return Err(ExportError::new(format!(
+        "'{}' with {} controls is not supported",
+        kind.name(),
+        count
+      )))
+    }
+  };
+  Ok(lines)
+}
@@ -0,0 +1,245 @@
+use super::{ParseError, Span};
+
+/// The smallest meaningful pieces of an OpenQASM program.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenKind {
+  Identifier(String),
+  Integer(u64),
+  Real(f64),
+  String(String),
+  Symbol(&'static str),
+  Eof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Token {
+  pub kind: TokenKind,
+  pub span: Span,
+}
+
+// Longer symbols come first, so that "==" is not read as two "="s
+const SYMBOLS: &[&str] = &[
+  "->", "==", "!=", "<=", ">=", "&&", "||", "++", "+=", "-=", ";", ",", "(", ")", "[", "]", "{",
+  "}", "+", "-", "*", "/", "^", "@", ":", "=", "<", ">", "!", "~",
+];
+
+/// Splits the source into tokens, skipping whitespace and comments.
+/// The last token is always `TokenKind::Eof`.
+pub(crate) fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+  let mut lexer = Lexer {
+    chars: source.chars().collect(),
+    position: 0,
+    line: 1,
+    column: 1,
+  };
+  let mut tokens = Vec::new();
+  loop {
+    lexer.skip_whitespace_and_comments()?;
+    let span = lexer.span();
+    let kind = match lexer.peek() {
+      None => {
+        tokens.push(Token {
+          kind: TokenKind::Eof,
+          span,
+        });
+        return Ok(tokens);
+      }
+      Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+        TokenKind::Identifier(lexer.take_while(|c| c.is_ascii_alphanumeric() || c == '_'))
+      }
+      Some(c)
+        if c.is_ascii_digit()
+          || (c == '.' && matches!(lexer.peek_at(1), Some(c) if c.is_ascii_digit())) =>
+      {
+        lexer.number(span)?
+      }
+      Some('"') => lexer.string(span)?,
+      Some(c) => match SYMBOLS.iter().find(|symbol| lexer.starts_with(symbol)) {
+        Some(symbol) => {
+          for _ in 0..symbol.len() {
+            lexer.next();
+          }
+          TokenKind::Symbol(symbol)
+        }
+        None => {
+          return Err(ParseError::new(
+            format!("unexpected character '{}'", c),
+            span,
+          ))
+        }
+      },
+    };
+    tokens.push(Token { kind, span });
+  }
+}
+
+struct Lexer {
+  chars: Vec<char>,
+  position: usize,
+  line: usize,
+  column: usize,
+}
+
+impl Lexer {
+  fn span(&self) -> Span {
+    Span {
+      line: self.line,
+      column: self.column,
+    }
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.peek_at(0)
+  }
+
+  fn peek_at(&self, offset: usize) -> Option<char> {
+    self.chars.get(self.position + offset).copied()
+  }
+
+  fn starts_with(&self, text: &str) -> bool {
+    text
+      .chars()
+      .enumerate()
+      .all(|(i, c)| self.peek_at(i) == Some(c))
+  }
+
+  fn next(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.position += 1;
+    if c == '\n' {
+      self.line += 1;
+      self.column = 1;
+    } else {
+      self.column += 1;
+    }
+    Some(c)
+  }
+
+  fn take_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> String {
+    let mut text = String::new();
+    while let Some(c) = self.peek().filter(|c| predicate(*c)) {
+      text.push(c);
+      self.next();
+    }
+    text
+  }
+
+  fn skip_whitespace_and_comments(&mut self) -> Result<(), ParseError> {
+    loop {
+      if matches!(self.peek(), Some(c) if c.is_whitespace()) {
+        self.next();
+      } else if self.starts_with("//") {
+        self.take_while(|c| c != '\n');
+      } else if self.starts_with("/*") {
+        let span = self.span();
+        while !self.starts_with("*/") {
+          if self.next().is_none() {
+            return Err(ParseError::new("unterminated comment", span));
+          }
+        }
+        self.next();
+        self.next();
+      } else {
+        return Ok(());
+      }
+    }
+  }
+
+  fn number(&mut self, span: Span) -> Result<TokenKind, ParseError> {
+    let mut text = self.take_while(|c| c.is_ascii_digit());
+    let mut real = false;
+    if self.peek() == Some('.') {
+      real = true;
+      self.next();
+      text.push('.');
+      text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+    }
+    if let Some(e) = self.peek().filter(|c| *c == 'e' || *c == 'E') {
+      let sign = self.peek_at(1).filter(|c| *c == '+' || *c == '-');
+      let digit_at = if sign.is_some() { 2 } else { 1 };
+      if matches!(self.peek_at(digit_at), Some(c) if c.is_ascii_digit()) {
+        real = true;
+        self.next();
+        text.push(e);
+        if let Some(sign) = sign {
+          self.next();
+          text.push(sign);
+        }
+        text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+      }
+    }
+    if real {
+      text
+        .parse()
+        .map(TokenKind::Real)
+        .map_err(|_| ParseError::new(format!("invalid number '{}'", text), span))
+    } else {
+      text
+        .parse()
+        .map(TokenKind::Integer)
+        .map_err(|_| ParseError::new(format!("integer '{}' is too large", text), span))
+    }
+  }
+
+  fn string(&mut self, span: Span) -> Result<TokenKind, ParseError> {
+    self.next();
+    let text = self.take_while(|c| c != '"' && c != '\n');
+    if self.next() != Some('"') {
+      return Err(ParseError::new("unterminated string", span));
+    }
+    Ok(TokenKind::String(text))
+  }
+}
+
+#[test]
+fn tokenize_statement() {
+  let tokens = tokenize("cx q[0], q[1];").unwrap();
+  let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+  assert_eq!(
+    kinds,
+    vec![
+      TokenKind::Identifier("cx".to_string()),
+      TokenKind::Identifier("q".to_string()),
+      TokenKind::Symbol("["),
+      TokenKind::Integer(0),
+      TokenKind::Symbol("]"),
+      TokenKind::Symbol(","),
+      TokenKind::Identifier("q".to_string()),
+      TokenKind::Symbol("["),
+      TokenKind::Integer(1),
+      TokenKind::Symbol("]"),
+      TokenKind::Symbol(";"),
+      TokenKind::Eof,
+    ]
+  );
+}
+
+#[test]
+fn tokenize_numbers_and_comments() {
+  let tokens = tokenize("2.0 /* block\ncomment */ 1e-3 .5 // line\n 7").unwrap();
+  let kinds: Vec<TokenKind> = tokens.iter().map(|token| token.kind.clone()).collect();
+  assert_eq!(
+    kinds,
+    vec![
+      TokenKind::Real(2.0),
+      TokenKind::Real(1e-3),
+      TokenKind::Real(0.5),
+      TokenKind::Integer(7),
+      TokenKind::Eof,
+    ]
+  );
+  assert_eq!(
+    tokens[1].span,
+    Span {
+      line: 2,
+      column: 12
+    }
+  );
+  assert_eq!(tokens[3].span, Span { line: 3, column: 2 });
+}
+
+#[test]
+fn tokenize_reports_position() {
+  let error = tokenize("h q;\n  $").unwrap_err();
+  assert_eq!(error.span, Span { line: 2, column: 3 });
+}
@@ -0,0 +1,836 @@
+use super::parser::{
+  ConditionExpression, Expression, GateCall, GateDefinition, LoopValues, Modifier, Operand,
+  Program, Statement,
+};
+use super::{ParseError, Span};
+use crate::circuit::*;
+use crate::gate::Gate;
+use num_complex::Complex64;
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+
+// Lowering walks the syntax tree, resolving register names into qubit and classical bit indices,
+// expanding user-defined gates, and building up the instructions of the circuit.
+// OpenQASM 3 gate modifiers and `for` loops are expanded away here too, while `if` and `while`
+// stay as classical control flow for the simulator to follow.
+
+pub(crate) fn lower(program: &Program) -> Result<Circuit, ParseError> {
+  let mut lowering = Lowering {
+    version: program.version.0,
+    ..Lowering::default()
+  };
+  let mut instructions = Vec::new();
+  for statement in &program.statements {
+    lowering.statement(statement, &mut instructions)?;
+  }
+  let mut circuit = Circuit::new(lowering.num_qubits, lowering.num_clbits);
+  for instruction in instructions {
+    circuit.push(instruction);
+  }
+  Ok(circuit)
+}
+
+struct Register {
+  name: String,
+  offset: usize,
+  size: usize,
+  // A single bit, declared without a size, which can not be indexed
+  scalar: bool,
+}
+
+#[derive(Default)]
+struct Lowering {
+  version: u64,
+  qregs: Vec<Register>,
+  cregs: Vec<Register>,
+  num_qubits: usize,
+  num_clbits: usize,
+  gates: HashMap<String, GateDefinition>,
+  opaque: HashSet<String>,
+  // Whether "qelib1.inc" (or "stdgates.inc" for OpenQASM 3) has been included,
+  // making the standard gates available
+  standard_gates: bool,
+  // The values of the enclosing `for` loop variables
+  variables: Parameters,
+}
+
+// Gate parameters are looked up by name while expanding a user-defined gate
+type Parameters = HashMap<String, f64>;
+
+// `pow(k) @` repeats the gate k times, so huge powers would never finish expanding
+const MAX_POWER: f64 = 1024.0;
+
+// The instructions a gate call expands into, and the global phase it picks up on the way.
+// The phase can not be observed, until the gate gets controlled.
+#[derive(Default)]
+struct Expansion {
+  instructions: Vec<Instruction>,
+  phase: f64,
+}
+
+impl Expansion {
+  fn invert(&mut self) {
+    self.instructions.reverse();
+    // Swaps and barriers are their own inverses
+    for instruction in &mut self.instructions {
+      if let Instruction::Gate { kind, .. } = instruction {
+        *kind = kind.dagger();
+      }
+    }
+    self.phase = -self.phase;
+  }
+
+  fn power(&mut self, exponent: i64) {
+    if exponent < 0 {
+      self.invert();
+    }
+    let instructions = std::mem::take(&mut self.instructions);
+    for _ in 0..exponent.abs() {
+      self.instructions.extend(instructions.iter().cloned());
+    }
+    self.phase *= exponent.abs() as f64;
+  }
+
+  fn control(&mut self, controls: &[usize]) {
+    for instruction in &mut self.instructions {
+      if let Instruction::Gate {
+        controls: existing, ..
+      }
+      | Instruction::Swap {
+        controls: existing, ..
+      } = instruction
+      {
+        *existing = controls.iter().chain(existing.iter()).copied().collect();
+      }
+    }
+    // A controlled global phase is a phase gate on the controls
+    if self.phase != 0.0 {
+      let (target, rest) = controls.split_last().unwrap();
+      self
+        .instructions
+        .push(gate(GateKind::Phase(self.phase), rest, *target));
+      self.phase = 0.0;
+    }
+  }
+
+  // Controlled on |0> instead of |1>: flip the controls before and after
+  fn negative_control(&mut self, controls: &[usize]) {
+    self.control(controls);
+    let flips: Vec<Instruction> = controls
+      .iter()
+      .map(|control| gate(GateKind::X, &[], *control))
+      .collect();
+    let instructions = std::mem::take(&mut self.instructions);
+    self.instructions = flips
+      .iter()
+      .cloned()
+      .chain(instructions)
+      .chain(flips.iter().cloned())
+      .collect();
+  }
+}
+
+// A gate modifier, with its exponent evaluated and its controls resolved into qubits
+enum ResolvedModifier {
+  Inv,
+  Pow(i64),
+  Ctrl(Vec<usize>),
+  NegCtrl(Vec<usize>),
+}
+
+impl Lowering {
+  fn statement(
+    &mut self,
+    statement: &Statement,
+    out: &mut Vec<Instruction>,
+  ) -> Result<(), ParseError> {
+    match statement {
+      Statement::Include { path, span } => {
+        if path != self.standard_library() {
+          return Err(ParseError::new(
+            format!(
+              "cannot include \"{}\", only \"{}\" is supported",
+              path,
+              self.standard_library()
+            ),
+            *span,
+          ));
+        }
+        self.standard_gates = true;
+      }
+      Statement::QubitRegister { name, size, span } => {
+        self.check_new_name(name, *span)?;
+        self.qregs.push(Register {
+          name: name.clone(),
+          offset: self.num_qubits,
+          size: size.unwrap_or(1) as usize,
+          scalar: size.is_none(),
+        });
+        self.num_qubits += size.unwrap_or(1) as usize;
+      }
+      Statement::ClassicalRegister { name, size, span } => {
+        self.check_new_name(name, *span)?;
+        self.cregs.push(Register {
+          name: name.clone(),
+          offset: self.num_clbits,
+          size: size.unwrap_or(1) as usize,
+          scalar: size.is_none(),
+        });
+        self.num_clbits += size.unwrap_or(1) as usize;
+      }
+      Statement::GateDefinition(definition) => self.define_gate(definition)?,
+      Statement::Opaque { name, span } => {
+        self.check_new_gate(name, *span)?;
+        self.opaque.insert(name.clone());
+      }
+      Statement::GateCall(call) => {
+        let operands = call
+          .qubits
+          .iter()
+          .map(|operand| self.qubit_operand(operand))
+          .collect::<Result<Vec<_>, ParseError>>()?;
+        for qubits in broadcast(&operands, call.span)? {
+          // The global phase of a whole gate can not be observed, so it is dropped
+          let mut expansion = Expansion::default();
+          self.call(call, &self.variables, &qubits, &mut expansion)?;
+          out.extend(expansion.instructions);
+        }
+      }
+      Statement::Measure { qubit, clbit, span } => {
+        let operands = vec![self.qubit_operand(qubit)?, self.clbit_operand(clbit)?];
+        for bits in broadcast(&operands, *span)? {
+          out.push(Instruction::Measure {
+            qubit: bits[0],
+            clbit: bits[1],
+          });
+        }
+      }
+      Statement::Reset { qubit, .. } => {
+        for qubit in self.qubit_operand(qubit)?.1 {
+          out.push(Instruction::Reset { qubit });
+        }
+      }
+      Statement::Barrier { qubits, .. } => {
+        let mut all = Vec::new();
+        for operand in qubits {
+          all.extend(self.qubit_operand(operand)?.1);
+        }
+        out.push(Instruction::Barrier { qubits: all });
+      }
+      Statement::If {
+        condition,
+        then_body,
+        else_body,
+        ..
+      } => {
+        let condition = self.condition(condition)?;
+        let then_branch = self.block(then_body)?;
+        let else_branch = self.block(else_body)?;
+        out.push(Instruction::If {
+          condition,
+          then_branch,
+          else_branch,
+        });
+      }
+      // Loops over known values are unrolled
+      Statement::For {
+        variable,
+        values,
+        body,
+        span,
+      } => {
+        let previous = self.variables.get(variable).copied();
+        for value in self.loop_values(values, *span)? {
+          self.variables.insert(variable.clone(), value);
+          for statement in body {
+            self.statement(statement, out)?;
+          }
+        }
+        match previous {
+          Some(value) => self.variables.insert(variable.clone(), value),
+          None => self.variables.remove(variable),
+        };
+      }
+      Statement::While {
+        condition, body, ..
+      } => {
+        let condition = self.condition(condition)?;
+        let body = self.block(body)?;
+        out.push(Instruction::While { condition, body });
+      }
+    }
+    Ok(())
+  }
+
+  fn block(&mut self, statements: &[Statement]) -> Result<Vec<Instruction>, ParseError> {
+    let mut instructions = Vec::new();
+    for statement in statements {
+      self.statement(statement, &mut instructions)?;
+    }
+    Ok(instructions)
+  }
+
+  fn condition(&self, condition: &ConditionExpression) -> Result<Condition, ParseError> {
+    let (_, clbits) = self.clbit_operand(&condition.operand)?;
+    Ok(Condition {
+      clbits,
+      value: condition.value,
+      comparison: condition.comparison,
+    })
+  }
+
+  fn loop_values(&self, values: &LoopValues, span: Span) -> Result<Vec<f64>, ParseError> {
+    let integer = |expression: &Expression| {
+      let value = evaluate(expression, &self.variables)?;
+      if value.fract() != 0.0 {
+        return Err(ParseError::new(
+          format!("loop range must be made of integers, found {}", value),
+          span,
+        ));
+      }
+      Ok(value as i64)
+    };
+    match values {
+      LoopValues::Set(values) => values
+        .iter()
+        .map(|value| evaluate(value, &self.variables))
+        .collect(),
+      LoopValues::Range { start, step, end } => {
+        let (start, end) = (integer(start)?, integer(end)?);
+        let step = match step {
+          Some(step) => integer(step)?,
+          None => 1,
+        };
+        if step == 0 {
+          return Err(ParseError::new("loop step cannot be zero", span));
+        }
+        // The end of the range is included
+        let mut values = Vec::new();
+        let mut value = start;
+        while (step > 0 && value <= end) || (step < 0 && value >= end) {
+          values.push(value as f64);
+          value += step;
+        }
+        Ok(values)
+      }
+    }
+  }
+
+  fn standard_library(&self) -> &'static str {
+    if self.version >= 3 {
+      "stdgates.inc"
+    } else {
+      "qelib1.inc"
+    }
+  }
+
+  // The gates that the standard library of this version defines
+  fn standard_library_gates(&self) -> &'static [&'static str] {
+    if self.version >= 3 {
+      STDGATES_INC
+    } else {
+      QELIB1_INC
+    }
+  }
+
+  fn check_new_name(&self, name: &str, span: Span) -> Result<(), ParseError> {
+    if self
+      .qregs
+      .iter()
+      .chain(&self.cregs)
+      .any(|register| register.name == name)
+    {
+      Err(ParseError::new(
+        format!("register '{}' is already defined", name),
+        span,
+      ))
+    } else {
+      Ok(())
+    }
+  }
+
+  fn check_new_gate(&self, name: &str, span: Span) -> Result<(), ParseError> {
+    if self.gates.contains_key(name) || self.opaque.contains(name) {
+      Err(ParseError::new(
+        format!("gate '{}' is already defined", name),
+        span,
+      ))
+    } else {
+      Ok(())
+    }
+  }
+
+  // The body of a gate can only use gates defined before it, and only the qubit
+  // arguments of the gate itself. Check that when the gate is defined, so that
+  // errors show up even if the gate is never used.
+  fn define_gate(&mut self, definition: &GateDefinition) -> Result<(), ParseError> {
+    self.check_new_gate(&definition.name, definition.span)?;
+    for statement in &definition.body {
+      let (operands, name) = match statement {
+        Statement::GateCall(call) => (&call.qubits, Some(call)),
+        Statement::Barrier { qubits, .. } => (qubits, None),
+        _ => unreachable!("gate bodies only contain gate calls and barriers"),
+      };
+      for operand in operands {
+        if operand.index.is_some() || !definition.qubits.contains(&operand.name) {
+          return Err(ParseError::new(
+            format!(
+              "gate '{}' can only refer to its own qubit arguments",
+              definition.name
+            ),
+            operand.span,
+          ));
+        }
+      }
+      if let Some(call) = name {
+        if !self.is_gate(&call.name) {
+          return Err(self.unknown_gate(&call.name, call.span));
+        }
+      }
+    }
+    self
+      .gates
+      .insert(definition.name.clone(), definition.clone());
+    Ok(())
+  }
+
+  fn is_gate(&self, name: &str) -> bool {
+    self.gates.contains_key(name)
+      || self.opaque.contains(name)
+      || match name {
+        // The gates built into the language itself
+        "U" => true,
+        "CX" if self.version < 3 => true,
+        "gphase" => self.version >= 3,
+        _ => self.standard_gates && self.standard_library_gates().contains(&name),
+      }
+  }
+
+  fn unknown_gate(&self, name: &str, span: Span) -> ParseError {
+    let hint = if !self.standard_gates && self.standard_library_gates().contains(&name) {
+      format!(" (is include \"{}\"; missing?)", self.standard_library())
+    } else {
+      String::new()
+    };
+    ParseError::new(format!("unknown gate '{}'{}", name, hint), span)
+  }
+
+  // Calls a gate with its modifiers. The controls of `ctrl @` and `negctrl @` come first in
+  // the qubit list, outermost modifier first, and the modifiers apply from the innermost out.
+  fn call(
+    &self,
+    call: &GateCall,
+    parameters: &Parameters,
+    qubits: &[usize],
+    out: &mut Expansion,
+  ) -> Result<(), ParseError> {
+    check_distinct(qubits, call.span)?;
+    let params = call
+      .params
+      .iter()
+      .map(|param| evaluate(param, parameters))
+      .collect::<Result<Vec<f64>, ParseError>>()?;
+    let mut modifiers = Vec::new();
+    let mut used = 0;
+    for modifier in &call.modifiers {
+      let modifier = match modifier {
+        Modifier::Inv => ResolvedModifier::Inv,
+        Modifier::Pow(exponent) => {
+          let exponent = evaluate(exponent, parameters)?;
+          if exponent.fract() != 0.0 {
+            return Err(ParseError::new(
+              format!(
+                "only integer powers of gates are supported, found {}",
+                exponent
+              ),
+              call.span,
+            ));
+          }
+          if exponent.abs() > MAX_POWER {
+            return Err(ParseError::new(
+              format!(
+                "powers of gates can be at most {} in absolute value, found {}",
+                MAX_POWER, exponent
+              ),
+              call.span,
+            ));
+          }
+          ResolvedModifier::Pow(exponent as i64)
+        }
+        Modifier::Ctrl(count) | Modifier::NegCtrl(count) => {
+          let count = match count {
+            Some(count) => evaluate(count, parameters)?,
+            None => 1.0,
+          };
+          if count.fract() != 0.0 || count < 1.0 {
+            return Err(ParseError::new(
+              format!(
+                "number of controls must be a positive integer, found {}",
+                count
+              ),
+              call.span,
+            ));
+          }
+          // Compare before converting, since a huge count would saturate
+          if count > (qubits.len() - used) as f64 {
+            return Err(ParseError::new(
+              format!("not enough qubits for the controls of gate '{}'", call.name),
+              call.span,
+            ));
+          }
+          let count = count as usize;
+          let controls = qubits[used..used + count].to_vec();
+          used += count;
+          if let Modifier::Ctrl(_) = modifier {
+            ResolvedModifier::Ctrl(controls)
+          } else {
+            ResolvedModifier::NegCtrl(controls)
+          }
+        }
+      };
+      modifiers.push(modifier);
+    }
+    let mut expansion = Expansion::default();
+    self.call_gate(
+      &call.name,
+      &params,
+      &qubits[used..],
+      call.span,
+      &mut expansion,
+    )?;
+    for modifier in modifiers.iter().rev() {
+      match modifier {
+        ResolvedModifier::Inv => expansion.invert(),
+        ResolvedModifier::Pow(exponent) => expansion.power(*exponent),
+        ResolvedModifier::Ctrl(controls) => expansion.control(controls),
+        ResolvedModifier::NegCtrl(controls) => expansion.negative_control(controls),
+      }
+    }
+    out.instructions.extend(expansion.instructions);
+    out.phase += expansion.phase;
+    Ok(())
+  }
+
+  fn call_gate(
+    &self,
+    name: &str,
+    params: &[f64],
+    qubits: &[usize],
+    span: Span,
+    out: &mut Expansion,
+  ) -> Result<(), ParseError> {
+    if !self.is_gate(name) {
+      return Err(self.unknown_gate(name, span));
+    }
+    if self.opaque.contains(name) {
+      return Err(ParseError::new(
+        format!("opaque gate '{}' has no definition to simulate", name),
+        span,
+      ));
+    }
+    let (num_params, num_qubits) = match self.gates.get(name) {
+      Some(definition) => (definition.params.len(), definition.qubits.len()),
+      None => standard_gate_arity(name).unwrap(),
+    };
+    if params.len() != num_params || qubits.len() != num_qubits {
+      return Err(ParseError::new(
+        format!(
+          "gate '{}' takes {} parameters and {} qubits, but was given {} and {}",
+          name,
+          num_params,
+          num_qubits,
+          params.len(),
+          qubits.len()
+        ),
+        span,
+      ));
+    }
+    let definition = match self.gates.get(name) {
+      Some(definition) => definition,
+      None if name == "gphase" => {
+        out.phase += params[0];
+        return Ok(());
+      }
+      None => {
+        out.instructions.extend(standard_gate(name, params, qubits));
+        return Ok(());
+      }
+    };
+    let parameters: Parameters = definition
+      .params
+      .iter()
+      .cloned()
+      .zip(params.iter().copied())
+      .collect();
+    let arguments: HashMap<&String, usize> = definition
+      .qubits
+      .iter()
+      .zip(qubits.iter().copied())
+      .collect();
+    for statement in &definition.body {
+      match statement {
+        Statement::GateCall(call) => {
+          let qubits: Vec<usize> = call
+            .qubits
+            .iter()
+            .map(|operand| arguments[&operand.name])
+            .collect();
+          self.call(call, &parameters, &qubits, out)?;
+        }
+        Statement::Barrier { qubits, .. } => out.instructions.push(Instruction::Barrier {
+          qubits: qubits
+            .iter()
+            .map(|operand| arguments[&operand.name])
+            .collect(),
+        }),
+        _ => unreachable!("gate bodies only contain gate calls and barriers"),
+      }
+    }
+    Ok(())
+  }
+
+  // Operands resolve to a list of indices, and whether they named a whole register
+  fn qubit_operand(&self, operand: &Operand) -> Result<(bool, Vec<usize>), ParseError> {
+    resolve_operand(&self.qregs, "quantum", operand, &self.variables)
+  }
+
+  fn clbit_operand(&self, operand: &Operand) -> Result<(bool, Vec<usize>), ParseError> {
+    resolve_operand(&self.cregs, "classical", operand, &self.variables)
+  }
+}
+
+fn resolve_operand(
+  registers: &[Register],
+  kind: &str,
+  operand: &Operand,
+  variables: &Parameters,
+) -> Result<(bool, Vec<usize>), ParseError> {
+  let register = registers
+    .iter()
+    .find(|register| register.name == operand.name)
+    .ok_or_else(|| {
+      ParseError::new(
+        format!("unknown {} register '{}'", kind, operand.name),
+        operand.span,
+      )
+    })?;
+  match &operand.index {
+    None => Ok((
+      !register.scalar,
+      (register.offset..register.offset + register.size).collect(),
+    )),
+    Some(index) => {
+      let index = evaluate(index, variables)?;
+      if index.fract() != 0.0 || index < 0.0 || index as usize >= register.size {
+        return Err(ParseError::new(
+          format!(
+            "index {} out of range for register '{}' of size {}",
+            index, register.name, register.size
+          ),
+          operand.span,
+        ));
+      }
+      Ok((false, vec![register.offset + index as usize]))
+    }
+  }
+}
+
+// Applying a gate to whole registers applies it to each of their bits in turn.
+// Every register must have the same size, and single bits are reused each time.
+fn broadcast(operands: &[(bool, Vec<usize>)], span: Span) -> Result<Vec<Vec<usize>>, ParseError> {
+  let mut size = None;
+  for (is_register, bits) in operands {
+    if *is_register {
+      if matches!(size, Some(size) if size != bits.len()) {
+        return Err(ParseError::new("registers have different sizes", span));
+      }
+      size = Some(bits.len());
+    }
+  }
+  let applications: Vec<Vec<usize>> = (0..size.unwrap_or(1))
+    .map(|i| {
+      operands
+        .iter()
+        .map(|(is_register, bits)| if *is_register { bits[i] } else { bits[0] })
+        .collect()
+    })
+    .collect();
+  Ok(applications)
+}
+
+fn check_distinct(qubits: &[usize], span: Span) -> Result<(), ParseError> {
+  for (i, qubit) in qubits.iter().enumerate() {
+    if qubits[..i].contains(qubit) {
+      return Err(ParseError::new(
+        "the same qubit is used more than once",
+        span,
+      ));
+    }
+  }
+  Ok(())
+}
+
+fn evaluate(expression: &Expression, parameters: &Parameters) -> Result<f64, ParseError> {
+  Ok(match expression {
+    Expression::Number(value) => *value,
+    Expression::Identifier(name, span) => match parameters.get(name) {
+      Some(value) => *value,
+      None if name == "pi" => PI,
+      None if name == "tau" => 2.0 * PI,
+      None if name == "euler" => std::f64::consts::E,
+      None => {
+        return Err(ParseError::new(
+          format!("unknown parameter '{}'", name),
+          *span,
+        ))
+      }
+    },
+    Expression::Negate(inner) => -evaluate(inner, parameters)?,
+    Expression::Binary(op, left, right) => {
+      let left = evaluate(left, parameters)?;
+      let right = evaluate(right, parameters)?;
+      match op {
+        '+' => left + right,
+        '-' => left - right,
+        '*' => left * right,
+        '/' => left / right,
+        '^' => left.powf(right),
+        _ => unreachable!("unknown operator {}", op),
+      }
+    }
+    Expression::Function(name, argument, span) => {
+      let argument = evaluate(argument, parameters)?;
+      match name.as_str() {
+        "sin" => argument.sin(),
+        "cos" => argument.cos(),
+        "tan" => argument.tan(),
+        "exp" => argument.exp(),
+        "ln" => argument.ln(),
+        "sqrt" => argument.sqrt(),
+        _ => {
+          return Err(ParseError::new(
+            format!("unknown function '{}'", name),
+            *span,
+          ))
+        }
+      }
+    }
+  })
+}
+
+// The gates of "qelib1.inc", the standard library of OpenQASM 2.0
+const QELIB1_INC: &[&str] = &[
+  "u3", "u2", "u1", "cx", "id", "u0", "u", "p", "x", "y", "z", "h", "s", "sdg", "t", "tdg", "rx",
+  "ry", "rz", "sx", "sxdg", "cz", "cy", "swap", "ch", "ccx", "cswap", "crx", "cry", "crz", "cu1",
+  "cp", "cu3", "csx", "cu", "rxx", "rzz", "c3x", "c4x",
+];
+
+// The gates of "stdgates.inc", the standard library of OpenQASM 3. It has no U, which is
+// built in, but has CX, which is only built into OpenQASM 2.0.
+const STDGATES_INC: &[&str] = &[
+  "p", "x", "y", "z", "h", "s", "sdg", "t", "tdg", "sx", "rx", "ry", "rz", "cx", "cy", "cz", "cp",
+  "crx", "cry", "crz", "ch", "swap", "ccx", "cswap", "cu", "CX", "phase", "cphase", "id", "u1",
+  "u2", "u3",
+];
+
+// The number of parameters and qubits of the built in gates, "U", "CX" and "gphase",
+// and the gates of "qelib1.inc" and "stdgates.inc"
+fn standard_gate_arity(name: &str) -> Option<(usize, usize)> {
+  Some(match name {
+    "id" | "x" | "y" | "z" | "h" | "s" | "sdg" | "t" | "tdg" | "sx" | "sxdg" => (0, 1),
+    "gphase" => (1, 0),
+    "rx" | "ry" | "rz" | "u1" | "p" | "phase" | "u0" => (1, 1),
+    "u2" => (2, 1),
+    "U" | "u3" | "u" => (3, 1),
+    "CX" | "cx" | "cy" | "cz" | "ch" | "csx" | "swap" => (0, 2),
+    "crx" | "cry" | "crz" | "cu1" | "cp" | "cphase" | "rzz" | "rxx" => (1, 2),
+    "cu3" => (3, 2),
+    "cu" => (4, 2),
+    "ccx" | "cswap" => (0, 3),
+    "c3x" => (0, 4),
+    "c4x" => (0, 5),
+    _ => return None,
+  })
+}
+
+// The square root of X, (1/2) [[1+i, 1-i], [1-i, 1+i]]
+fn sqrt_x() -> Gate {
+  let a = Complex64::new(0.5, 0.5);
+  let b = Complex64::new(0.5, -0.5);
+  Gate::new([[a, b], [b, a]])
+}
+
+fn gate(kind: GateKind, controls: &[usize], target: usize) -> Instruction {
+  Instruction::Gate {
+    kind,
+    target,
+    controls: controls.to_vec(),
+  }
+}
+
+// Builds the instructions for a standard gate. The parameter and qubit counts have already
+// been checked against `standard_gate_arity`.
+fn standard_gate(name: &str, p: &[f64], q: &[usize]) -> Vec<Instruction> {
+  let last = *q.last().unwrap();
+  let controls = &q[..q.len() - 1];
+  let kind = match name {
+    "id" | "u0" => GateKind::I,
+    "x" | "CX" | "cx" | "ccx" | "c3x" | "c4x" => GateKind::X,
+    "y" | "cy" => GateKind::Y,
+    "z" | "cz" => GateKind::Z,
+    "h" | "ch" => GateKind::H,
+    "s" => GateKind::S,
+    "sdg" => GateKind::Sdg,
+    "t" => GateKind::T,
+    "tdg" => GateKind::Tdg,
+    "sx" | "csx" => GateKind::Custom(sqrt_x()),
+    "sxdg" => GateKind::Custom(sqrt_x().dagger()),
+    "rx" | "crx" => GateKind::Rx(p[0]),
+    "ry" | "cry" => GateKind::Ry(p[0]),
+    "rz" | "crz" => GateKind::Rz(p[0]),
+    "u1" | "p" | "phase" | "cu1" | "cp" | "cphase" => GateKind::Phase(p[0]),
+    "u2" => GateKind::U3(PI / 2.0, p[0], p[1]),
+    "U" | "u3" | "u" | "cu3" => GateKind::U3(p[0], p[1], p[2]),
+    "swap" => {
+      return vec![Instruction::Swap {
+        a: q[0],
+        b: q[1],
+        controls: Vec::new(),
+      }]
+    }
+    "cswap" => {
+      return vec![Instruction::Swap {
+        a: q[1],
+        b: q[2],
+        controls: vec![q[0]],
+      }]
+    }
+    // Controlling e^(iγ) U is the same as controlling U, and then a phase on the control
+    "cu" => {
+      return vec![
+        gate(GateKind::U3(p[0], p[1], p[2]), &q[..1], q[1]),
+        gate(GateKind::Phase(p[3]), &[], q[0]),
+      ]
+    }
+    "rzz" => {
+      return vec![
+        gate(GateKind::X, &q[..1], q[1]),
+        gate(GateKind::Phase(p[0]), &[], q[1]),
+        gate(GateKind::X, &q[..1], q[1]),
+      ]
+    }
+    "rxx" => {
+      return vec![
+        gate(GateKind::H, &[], q[0]),
+        gate(GateKind::H, &[], q[1]),
+        gate(GateKind::X, &q[..1], q[1]),
+        gate(GateKind::Rz(p[0]), &[], q[1]),
+        gate(GateKind::X, &q[..1], q[1]),
+        gate(GateKind::H, &[], q[0]),
+        gate(GateKind::H, &[], q[1]),
+      ]
+    }
+    _ => unreachable!("unknown standard gate {}", name),
+  };
+  vec![gate(kind, controls, last)]
+}
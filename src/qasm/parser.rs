@@ -0,0 +1,826 @@
+use super::lexer::{Token, TokenKind};
+use super::{ParseError, Span};
+use crate::circuit::Comparison;
+
+// The parser turns the tokens into a syntax tree, without yet checking what the names refer to.
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Program {
+  pub version: (u64, Span),
+  pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Statement {
+  Include {
+    path: String,
+    span: Span,
+  },
+  // Registers declared without a size, like `qubit q;`, are single bits rather than registers
+  QubitRegister {
+    name: String,
+    size: Option<u64>,
+    span: Span,
+  },
+  ClassicalRegister {
+    name: String,
+    size: Option<u64>,
+    span: Span,
+  },
+  GateDefinition(GateDefinition),
+  Opaque {
+    name: String,
+    span: Span,
+  },
+  GateCall(GateCall),
+  Measure {
+    qubit: Operand,
+    clbit: Operand,
+    span: Span,
+  },
+  Reset {
+    qubit: Operand,
+    span: Span,
+  },
+  Barrier {
+    qubits: Vec<Operand>,
+    span: Span,
+  },
+  If {
+    condition: ConditionExpression,
+    then_body: Vec<Statement>,
+    else_body: Vec<Statement>,
+    span: Span,
+  },
+  For {
+    variable: String,
+    values: LoopValues,
+    body: Vec<Statement>,
+    span: Span,
+  },
+  While {
+    condition: ConditionExpression,
+    body: Vec<Statement>,
+    span: Span,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GateDefinition {
+  pub name: String,
+  pub params: Vec<String>,
+  pub qubits: Vec<String>,
+  pub body: Vec<Statement>,
+  pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GateCall {
+  pub modifiers: Vec<Modifier>,
+  pub name: String,
+  pub params: Vec<Expression>,
+  pub qubits: Vec<Operand>,
+  pub span: Span,
+}
+
+/// The OpenQASM 3 gate modifiers, like `ctrl @`. The count of controls is 1 if not given.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Modifier {
+  Inv,
+  Pow(Expression),
+  Ctrl(Option<Expression>),
+  NegCtrl(Option<Expression>),
+}
+
+/// A test on a classical register or bit, like `c == 2`, `c[0]` or `!c[0]`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ConditionExpression {
+  pub operand: Operand,
+  pub comparison: Comparison,
+  pub value: u64,
+}
+
+/// The values a `for` loop goes through: an inclusive range `[start:step:end]`, or a set `{a, b}`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum LoopValues {
+  Range {
+    start: Expression,
+    step: Option<Expression>,
+    end: Expression,
+  },
+  Set(Vec<Expression>),
+}
+
+/// A whole register, `q`, or a single bit of one, `q[1]`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Operand {
+  pub name: String,
+  pub index: Option<Expression>,
+  pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expression {
+  Number(f64),
+  Identifier(String, Span),
+  Negate(Box<Expression>),
+  Binary(char, Box<Expression>, Box<Expression>),
+  Function(String, Box<Expression>, Span),
+}
+
+pub(crate) fn parse_program(tokens: Vec<Token>) -> Result<Program, ParseError> {
+  let mut parser = Parser {
+    tokens,
+    position: 0,
+    version: 2,
+  };
+  parser.expect_keyword("OPENQASM")?;
+  let version = parser.version()?;
+  parser.expect(";")?;
+  parser.version = version.0;
+  let mut statements = Vec::new();
+  while parser.peek().kind != TokenKind::Eof {
+    statements.push(parser.statement()?);
+  }
+  Ok(Program {
+    version,
+    statements,
+  })
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+  // The major version of the program, which decides what syntax is allowed
+  version: u64,
+}
+
+// OpenQASM 3 statements that are outside the supported subset
+const UNSUPPORTED: &[&str] = &[
+  "def",
+  "defcal",
+  "cal",
+  "defcalgrammar",
+  "extern",
+  "input",
+  "output",
+  "let",
+  "const",
+  "int",
+  "uint",
+  "float",
+  "angle",
+  "bool",
+  "complex",
+  "duration",
+  "stretch",
+  "array",
+  "box",
+  "delay",
+  "break",
+  "continue",
+  "return",
+  "end",
+  "switch",
+];
+
+impl Parser {
+  fn peek(&self) -> &Token {
+    &self.tokens[self.position]
+  }
+
+  fn next(&mut self) -> Token {
+    let token = self.tokens[self.position].clone();
+    if token.kind != TokenKind::Eof {
+      self.position += 1;
+    }
+    token
+  }
+
+  fn error<T>(&self, expected: &str) -> Result<T, ParseError> {
+    let token = self.peek();
+    let found = match &token.kind {
+      TokenKind::Identifier(name) => format!("'{}'", name),
+      TokenKind::Integer(value) => format!("'{}'", value),
+      TokenKind::Real(value) => format!("'{}'", value),
+      TokenKind::String(text) => format!("\"{}\"", text),
+      TokenKind::Symbol(symbol) => format!("'{}'", symbol),
+      TokenKind::Eof => "end of file".to_string(),
+    };
+    Err(ParseError::new(
+      format!("expected {}, found {}", expected, found),
+      token.span,
+    ))
+  }
+
+  fn is_symbol(&self, symbol: &str) -> bool {
+    matches!(self.peek().kind, TokenKind::Symbol(found) if found == symbol)
+  }
+
+  fn is_keyword(&self, keyword: &str) -> bool {
+    matches!(&self.peek().kind, TokenKind::Identifier(name) if name == keyword)
+  }
+
+  fn eat(&mut self, symbol: &str) -> bool {
+    let found = self.is_symbol(symbol);
+    if found {
+      self.next();
+    }
+    found
+  }
+
+  fn expect(&mut self, symbol: &str) -> Result<Span, ParseError> {
+    if self.is_symbol(symbol) {
+      Ok(self.next().span)
+    } else {
+      self.error(&format!("'{}'", symbol))
+    }
+  }
+
+  fn expect_keyword(&mut self, keyword: &str) -> Result<Span, ParseError> {
+    if self.is_keyword(keyword) {
+      Ok(self.next().span)
+    } else {
+      self.error(&format!("'{}'", keyword))
+    }
+  }
+
+  fn identifier(&mut self) -> Result<(String, Span), ParseError> {
+    match self.peek().kind.clone() {
+      TokenKind::Identifier(name) => Ok((name, self.next().span)),
+      _ => self.error("an identifier"),
+    }
+  }
+
+  fn integer(&mut self) -> Result<u64, ParseError> {
+    match self.peek().kind {
+      TokenKind::Integer(value) => {
+        self.next();
+        Ok(value)
+      }
+      _ => self.error("an integer"),
+    }
+  }
+
+  fn version(&mut self) -> Result<(u64, Span), ParseError> {
+    let token = self.next();
+    match token.kind {
+      TokenKind::Integer(major) => Ok((major, token.span)),
+      TokenKind::Real(version) if version.fract() == 0.0 => Ok((version as u64, token.span)),
+      _ => Err(ParseError::new("expected a version number", token.span)),
+    }
+  }
+
+  fn statement(&mut self) -> Result<Statement, ParseError> {
+    let span = self.peek().span;
+    let keyword = match &self.peek().kind {
+      TokenKind::Identifier(name) => name.clone(),
+      _ => return self.error("a statement"),
+    };
+    match keyword.as_str() {
+      "include" => {
+        self.next();
+        let path = match self.next().kind {
+          TokenKind::String(path) => path,
+          _ => return Err(ParseError::new("expected a file name", span)),
+        };
+        self.expect(";")?;
+        Ok(Statement::Include { path, span })
+      }
+      "qreg" | "creg" => {
+        self.next();
+        let (name, _) = self.identifier()?;
+        self.expect("[")?;
+        let size = Some(self.integer()?);
+        self.expect("]")?;
+        self.expect(";")?;
+        if keyword == "qreg" {
+          Ok(Statement::QubitRegister { name, size, span })
+        } else {
+          Ok(Statement::ClassicalRegister { name, size, span })
+        }
+      }
+      // In OpenQASM 3 the size comes first, `qubit[2] q;`, and can be left out for a single bit
+      "qubit" | "bit" if self.version >= 3 => {
+        self.next();
+        let mut size = None;
+        if self.eat("[") {
+          size = Some(self.integer()?);
+          self.expect("]")?;
+        }
+        let (name, _) = self.identifier()?;
+        self.expect(";")?;
+        if keyword == "qubit" {
+          Ok(Statement::QubitRegister { name, size, span })
+        } else {
+          Ok(Statement::ClassicalRegister { name, size, span })
+        }
+      }
+      "gate" => {
+        self.next();
+        Ok(Statement::GateDefinition(self.gate_definition(span)?))
+      }
+      "opaque" => {
+        self.next();
+        let (name, _) = self.identifier()?;
+        if self.eat("(") && !self.eat(")") {
+          self.identifier_list()?;
+          self.expect(")")?;
+        }
+        self.identifier_list()?;
+        self.expect(";")?;
+        Ok(Statement::Opaque { name, span })
+      }
+      "measure" => {
+        self.next();
+        let qubit = self.operand()?;
+        self.expect("->")?;
+        let clbit = self.operand()?;
+        self.expect(";")?;
+        Ok(Statement::Measure { qubit, clbit, span })
+      }
+      "reset" => {
+        self.next();
+        let qubit = self.operand()?;
+        self.expect(";")?;
+        Ok(Statement::Reset { qubit, span })
+      }
+      "barrier" => {
+        self.next();
+        let qubits = self.operand_list()?;
+        self.expect(";")?;
+        Ok(Statement::Barrier { qubits, span })
+      }
+      "if" if self.version >= 3 => {
+        self.next();
+        self.expect("(")?;
+        let condition = self.condition()?;
+        self.expect(")")?;
+        let then_body = self.body()?;
+        let mut else_body = Vec::new();
+        if self.is_keyword("else") {
+          self.next();
+          else_body = self.body()?;
+        }
+        Ok(Statement::If {
+          condition,
+          then_body,
+          else_body,
+          span,
+        })
+      }
+      // OpenQASM 2.0 can only compare a whole register, and guard a single operation
+      "if" => {
+        self.next();
+        self.expect("(")?;
+        let (name, register_span) = self.identifier()?;
+        self.expect("==")?;
+        let value = self.integer()?;
+        self.expect(")")?;
+        let body = self.statement()?;
+        match body {
+          Statement::GateCall(_) | Statement::Measure { .. } | Statement::Reset { .. } => {
+            Ok(Statement::If {
+              condition: ConditionExpression {
+                operand: Operand {
+                  name,
+                  index: None,
+                  span: register_span,
+                },
+                comparison: Comparison::Equal,
+                value,
+              },
+              then_body: vec![body],
+              else_body: Vec::new(),
+              span,
+            })
+          }
+          _ => Err(ParseError::new(
+            "only gates, measurements and resets can be conditional",
+            span,
+          )),
+        }
+      }
+      "for" if self.version >= 3 => {
+        self.next();
+        // The loop variable may have a type in front of it, `for uint i in ...`
+        let (mut variable, _) = self.identifier()?;
+        if !self.is_keyword("in") {
+          if self.eat("[") {
+            self.expression()?;
+            self.expect("]")?;
+          }
+          variable = self.identifier()?.0;
+        }
+        self.expect_keyword("in")?;
+        let values = if self.eat("[") {
+          let start = self.expression()?;
+          self.expect(":")?;
+          let second = self.expression()?;
+          let values = if self.eat(":") {
+            LoopValues::Range {
+              start,
+              step: Some(second),
+              end: self.expression()?,
+            }
+          } else {
+            LoopValues::Range {
+              start,
+              step: None,
+              end: second,
+            }
+          };
+          self.expect("]")?;
+          values
+        } else if self.eat("{") {
+          let mut values = vec![self.expression()?];
+          while self.eat(",") {
+            values.push(self.expression()?);
+          }
+          self.expect("}")?;
+          LoopValues::Set(values)
+        } else {
+          return self.error("a range or a set of values");
+        };
+        let body = self.body()?;
+        Ok(Statement::For {
+          variable,
+          values,
+          body,
+          span,
+        })
+      }
+      "while" if self.version >= 3 => {
+        self.next();
+        self.expect("(")?;
+        let condition = self.condition()?;
+        self.expect(")")?;
+        let body = self.body()?;
+        Ok(Statement::While {
+          condition,
+          body,
+          span,
+        })
+      }
+      _ if self.version >= 3 && UNSUPPORTED.contains(&keyword.as_str()) => Err(ParseError::new(
+        format!("'{}' is not supported", keyword),
+        span,
+      )),
+      _ if self.version >= 3 => {
+        // Either a measurement assigned to a bit, `c[0] = measure q[0];`, or a gate call
+        let start = self.position;
+        if let Ok(clbit) = self.operand() {
+          if self.eat("=") {
+            self.expect_keyword("measure")?;
+            let qubit = self.operand()?;
+            self.expect(";")?;
+            return Ok(Statement::Measure { qubit, clbit, span });
+          }
+        }
+        self.position = start;
+        Ok(Statement::GateCall(self.gate_call()?))
+      }
+      _ => Ok(Statement::GateCall(self.gate_call()?)),
+    }
+  }
+
+  // The body of an `if`, `else`, `for` or `while`: a block in braces, or a single statement
+  fn body(&mut self) -> Result<Vec<Statement>, ParseError> {
+    if !self.eat("{") {
+      return Ok(vec![self.statement()?]);
+    }
+    let mut statements = Vec::new();
+    while !self.eat("}") {
+      if self.peek().kind == TokenKind::Eof {
+        return self.error("'}'");
+      }
+      statements.push(self.statement()?);
+    }
+    Ok(statements)
+  }
+
+  fn condition(&mut self) -> Result<ConditionExpression, ParseError> {
+    if self.eat("!") {
+      return Ok(ConditionExpression {
+        operand: self.operand()?,
+        comparison: Comparison::Equal,
+        value: 0,
+      });
+    }
+    let operand = self.operand()?;
+    let (comparison, value) = if self.eat("==") {
+      (Comparison::Equal, self.condition_value()?)
+    } else if self.eat("!=") {
+      (Comparison::NotEqual, self.condition_value()?)
+    } else {
+      // A bare register or bit is true when it is not zero
+      (Comparison::NotEqual, 0)
+    };
+    Ok(ConditionExpression {
+      operand,
+      comparison,
+      value,
+    })
+  }
+
+  fn condition_value(&mut self) -> Result<u64, ParseError> {
+    if self.is_keyword("true") || self.is_keyword("false") {
+      return Ok((self.identifier()?.0 == "true") as u64);
+    }
+    self.integer()
+  }
+
+  fn gate_definition(&mut self, span: Span) -> Result<GateDefinition, ParseError> {
+    let (name, _) = self.identifier()?;
+    let mut params = Vec::new();
+    if self.eat("(") && !self.eat(")") {
+      params = self.identifier_list()?;
+      self.expect(")")?;
+    }
+    let qubits = self.identifier_list()?;
+    self.expect("{")?;
+    let mut body = Vec::new();
+    while !self.eat("}") {
+      let statement_span = self.peek().span;
+      if self.is_keyword("barrier") {
+        self.next();
+        let qubits = self.operand_list()?;
+        self.expect(";")?;
+        body.push(Statement::Barrier {
+          qubits,
+          span: statement_span,
+        });
+      } else {
+        body.push(Statement::GateCall(self.gate_call()?));
+      }
+    }
+    Ok(GateDefinition {
+      name,
+      params,
+      qubits,
+      body,
+      span,
+    })
+  }
+
+  fn gate_call(&mut self) -> Result<GateCall, ParseError> {
+    let mut modifiers = Vec::new();
+    while self.version >= 3 {
+      let modifier = if self.is_keyword("inv") {
+        self.next();
+        Modifier::Inv
+      } else if self.is_keyword("pow") {
+        self.next();
+        self.expect("(")?;
+        let exponent = self.expression()?;
+        self.expect(")")?;
+        Modifier::Pow(exponent)
+      } else if self.is_keyword("ctrl") || self.is_keyword("negctrl") {
+        let (keyword, _) = self.identifier()?;
+        let mut count = None;
+        if self.eat("(") {
+          count = Some(self.expression()?);
+          self.expect(")")?;
+        }
+        if keyword == "ctrl" {
+          Modifier::Ctrl(count)
+        } else {
+          Modifier::NegCtrl(count)
+        }
+      } else {
+        break;
+      };
+      self.expect("@")?;
+      modifiers.push(modifier);
+    }
+    let (name, span) = self.identifier()?;
+    let mut params = Vec::new();
+    if self.eat("(") && !self.eat(")") {
+      params.push(self.expression()?);
+      while self.eat(",") {
+        params.push(self.expression()?);
+      }
+      self.expect(")")?;
+    }
+    // Only `gphase` acts on no qubits at all
+    let qubits = if self.version >= 3 && self.is_symbol(";") {
+      Vec::new()
+    } else {
+      self.operand_list()?
+    };
+    self.expect(";")?;
+    Ok(GateCall {
+      modifiers,
+      name,
+      params,
+      qubits,
+      span,
+    })
+  }
+
+  fn identifier_list(&mut self) -> Result<Vec<String>, ParseError> {
+    let mut names = vec![self.identifier()?.0];
+    while self.eat(",") {
+      names.push(self.identifier()?.0);
+    }
+    Ok(names)
+  }
+
+  fn operand(&mut self) -> Result<Operand, ParseError> {
+    let (name, span) = self.identifier()?;
+    let index = if self.eat("[") {
+      let index = self.expression()?;
+      self.expect("]")?;
+      Some(index)
+    } else {
+      None
+    };
+    Ok(Operand { name, index, span })
+  }
+
+  fn operand_list(&mut self) -> Result<Vec<Operand>, ParseError> {
+    let mut operands = vec![self.operand()?];
+    while self.eat(",") {
+      operands.push(self.operand()?);
+    }
+    Ok(operands)
+  }
+
+  // Expressions follow the usual precedence rules:
+  // + and - bind the loosest, then * and /, then unary minus, and ^ the tightest.
+  fn expression(&mut self) -> Result<Expression, ParseError> {
+    let mut left = self.term()?;
+    loop {
+      let op = if self.eat("+") {
+        '+'
+      } else if self.eat("-") {
+        '-'
+      } else {
+        return Ok(left);
+      };
+      left = Expression::Binary(op, Box::new(left), Box::new(self.term()?));
+    }
+  }
+
+  fn term(&mut self) -> Result<Expression, ParseError> {
+    let mut left = self.unary()?;
+    loop {
+      let op = if self.eat("*") {
+        '*'
+      } else if self.eat("/") {
+        '/'
+      } else {
+        return Ok(left);
+      };
+      left = Expression::Binary(op, Box::new(left), Box::new(self.unary()?));
+    }
+  }
+
+  fn unary(&mut self) -> Result<Expression, ParseError> {
+    if self.eat("-") {
+      Ok(Expression::Negate(Box::new(self.unary()?)))
+    } else {
+      self.power()
+    }
+  }
+
+  fn power(&mut self) -> Result<Expression, ParseError> {
+    let base = self.primary()?;
+    if self.eat("^") {
+      Ok(Expression::Binary(
+        '^',
+        Box::new(base),
+        Box::new(self.unary()?),
+      ))
+    } else {
+      Ok(base)
+    }
+  }
+
+  fn primary(&mut self) -> Result<Expression, ParseError> {
+    let token = self.peek().clone();
+    match token.kind {
+      TokenKind::Integer(value) => {
+        self.next();
+        Ok(Expression::Number(value as f64))
+      }
+      TokenKind::Real(value) => {
+        self.next();
+        Ok(Expression::Number(value))
+      }
+      TokenKind::Identifier(name) => {
+        self.next();
+        if self.eat("(") {
+          let argument = self.expression()?;
+          self.expect(")")?;
+          Ok(Expression::Function(name, Box::new(argument), token.span))
+        } else {
+          Ok(Expression::Identifier(name, token.span))
+        }
+      }
+      TokenKind::Symbol("(") => {
+        self.next();
+        let inner = self.expression()?;
+        self.expect(")")?;
+        Ok(inner)
+      }
+      _ => self.error("an expression"),
+    }
+  }
+}
+
+#[cfg(test)]
+fn parse_source(source: &str) -> Result<Program, ParseError> {
+  parse_program(super::lexer::tokenize(source)?)
+}
+
+#[test]
+fn parse_header_and_registers() {
+  let program = parse_source("OPENQASM 2.0;\nqreg q[2];\ncreg c[2];").unwrap();
+  assert_eq!(program.version.0, 2);
+  assert_eq!(
+    program.statements[0],
+    Statement::QubitRegister {
+      name: "q".to_string(),
+      size: Some(2),
+      span: Span { line: 2, column: 1 }
+    }
+  );
+}
+
+#[test]
+fn parse_expression_precedence() {
+  let program = parse_source("OPENQASM 2.0; rz(-pi/2 + 2*3^2) q;").unwrap();
+  let call = match &program.statements[0] {
+    Statement::GateCall(call) => call,
+    other => panic!("{:?}", other),
+  };
+  let span = Span {
+    line: 1,
+    column: 19,
+  };
+  let expected = Expression::Binary(
+    '+',
+    Box::new(Expression::Binary(
+      '/',
+      Box::new(Expression::Negate(Box::new(Expression::Identifier(
+        "pi".to_string(),
+        span,
+      )))),
+      Box::new(Expression::Number(2.0)),
+    )),
+    Box::new(Expression::Binary(
+      '*',
+      Box::new(Expression::Number(2.0)),
+      Box::new(Expression::Binary(
+        '^',
+        Box::new(Expression::Number(3.0)),
+        Box::new(Expression::Number(2.0)),
+      )),
+    )),
+  );
+  assert_eq!(call.params, vec![expected]);
+}
+
+#[test]
+fn parse_error_points_at_token() {
+  let error = parse_source("OPENQASM 2.0;\nqreg q[2]\nh q;").unwrap_err();
+  assert_eq!(error.span, Span { line: 3, column: 1 });
+  assert_eq!(error.message, "expected ';', found 'h'");
+}
+
+#[test]
+fn parse_openqasm3_control_flow() {
+  let program = parse_source(
+    "OPENQASM 3; qubit[2] q; bit c; for uint i in [0:1] { ctrl @ inv @ x q[0], q[1]; } \
+     c = measure q[0]; if (!c) reset q[0]; else { h q[0]; } while (c == true) c = measure q[1];",
+  )
+  .unwrap();
+  assert_eq!(program.statements.len(), 6);
+  match &program.statements[2] {
+    Statement::For { variable, body, .. } => {
+      assert_eq!(variable, "i");
+      match &body[0] {
+        Statement::GateCall(call) => {
+          assert_eq!(call.modifiers, vec![Modifier::Ctrl(None), Modifier::Inv]);
+          assert_eq!(call.qubits.len(), 2);
+        }
+        other => panic!("{:?}", other),
+      }
+    }
+    other => panic!("{:?}", other),
+  }
+  match &program.statements[4] {
+    Statement::If {
+      condition,
+      then_body,
+      else_body,
+      ..
+    } => {
+      assert_eq!(condition.comparison, Comparison::Equal);
+      assert_eq!(condition.value, 0);
+      assert_eq!((then_body.len(), else_body.len()), (1, 1));
+    }
+    other => panic!("{:?}", other),
+  }
+}
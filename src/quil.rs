@@ -0,0 +1,381 @@
+//! Reading Quil programs into circuits, and writing circuits out as Quil.
+//!
+//! The supported part of Quil covers what is needed to describe circuits:
+//!
+//! - `DECLARE name BIT[n]` memory regions, laid out one after another in the order they are
+//!   declared, like OpenQASM classical registers.
+//! - The standard gates, `I`, `X`, `Y`, `Z`, `H`, `S`, `T`, `PHASE`, `RX`, `RY`, `RZ`, `CZ`,
+//!   `CNOT`, `CCNOT`, `CPHASE`, `CPHASE00`, `CPHASE01`, `CPHASE10`, `SWAP`, `CSWAP`, `ISWAP`,
+//!   `PSWAP` and `XY`, under any number of `DAGGER` and `CONTROLLED` modifiers.
+//! - `DEFGATE` with a matrix, with or without parameters. Single-qubit definitions become
+//!   `GateKind::Custom` gates, and larger ones `Instruction::Unitary`.
+//! - `MEASURE q ro[i]`, `RESET` and `RESET q`.
+//! - `LABEL`, `JUMP`, `JUMP-WHEN` and `JUMP-UNLESS`, which become `Instruction::Label` and
+//!   `Instruction::Jump`.
+//!
+//! `PRAGMA` and `NOP` are skipped. Classical arithmetic, `HALT`, `WAIT`, `DEFCIRCUIT` and
+//! the pulse level instructions are reported as errors.
+//!
+//! The circuit has as many qubits as the highest qubit index used, plus one. Like Quil's own
+//! gate matrices, the matrices of multi-qubit gates have the first qubit as their most
+//! significant bit.
+//!
+//! Exported programs keep all classical bits in a single region `ro`. When the last qubit is
+//! not used, they end with an `I` gate on it, so that reading them back gives the same number
+//! of qubits. Ifs and while loops are written out with labels and jumps, which only works for
+//! conditions that test a single bit.
+
+use crate::circuit::Circuit;
+
+pub use crate::qasm::{ExportError, ParseError, Span};
+
+mod export;
+mod lexer;
+mod parser;
+
+/// Parses a Quil program into a circuit.
+pub fn parse(source: &str) -> Result<Circuit, ParseError> {
+  parser::parse_program(lexer::tokenize(source)?)
+}
+
+/// Writes a circuit out as a Quil program, which `parse` can read back in.
+pub fn export(circuit: &Circuit) -> Result<String, ExportError> {
+  export::export(circuit)
+}
+
+#[cfg(test)]
+use crate::circuit::{Condition, GateKind, Instruction};
+
+#[cfg(test)]
+fn gate(kind: GateKind, controls: &[usize], target: usize) -> Instruction {
+  Instruction::Gate {
+    kind,
+    target,
+    controls: controls.to_vec(),
+  }
+}
+
+#[test]
+fn parse_bell_pair() {
+  let circuit = parse(
+    "
+    DECLARE ro BIT[2]
+    H 0
+    CNOT 0 1
+    MEASURE 0 ro[0]
+    MEASURE 1 ro[1]
+    ",
+  )
+  .unwrap();
+  assert_eq!((circuit.num_qubits(), circuit.num_clbits()), (2, 2));
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      gate(GateKind::H, &[], 0),
+      gate(GateKind::X, &[0], 1),
+      Instruction::Measure { qubit: 0, clbit: 0 },
+      Instruction::Measure { qubit: 1, clbit: 1 },
+    ]
+  );
+}
+
+#[test]
+fn modifiers_take_the_first_qubits() {
+  let circuit = parse(
+    "CONTROLLED DAGGER S 2 0; DAGGER CONTROLLED RX(pi/2) 0 1\nCONTROLLED CCNOT 3 2 1 0\n\
+     CONTROLLED SWAP 1 2 3; DAGGER CPHASE01(1.5) 0 1",
+  )
+  .unwrap();
+  let pi = std::f64::consts::PI;
+  assert_eq!(
+    circuit.instructions(),
+    &[
+      gate(GateKind::Sdg, &[2], 0),
+      gate(GateKind::Rx(-pi / 2.0), &[0], 1),
+      gate(GateKind::X, &[3, 2, 1], 0),
+      Instruction::Swap {
+        a: 2,
+        b: 3,
+        controls: vec![1],
+      },
+      gate(GateKind::X, &[], 0),
+      gate(GateKind::Phase(-1.5), &[0], 1),
+      gate(GateKind::X, &[], 0),
+    ]
+  );
+}
+
+#[test]
+fn defgate_matrices() {
+  use crate::gate::{Gate, Unitary};
+  use num_complex::Complex64;
+  let circuit = parse(
+    "
+# A square root of NOT, a parametric phase, and a two qubit gate
+DEFGATE SQRT-X:
+    0.5+0.5i, 0.5-0.5i
+    0.5-0.5i, 0.5+0.5i
+
+DEFGATE MY-PHASE(%alpha) AS MATRIX:
+    1, 0
+    0, exp(i*%alpha)
+
+DEFGATE DOUBLE:
+    0, 0, 0, 1
+    0, 1, 0, 0
+    0, 0, 1, 0
+    1, 0, 0, 0
+
+SQRT-X 0
+MY-PHASE(pi) 1
+CONTROLLED DOUBLE 2 0 1
+",
+  )
+  .unwrap();
+  let (half, half_conj) = (Complex64::new(0.5, 0.5), Complex64::new(0.5, -0.5));
+  assert_eq!(
+    circuit.instructions()[0],
+    gate(
+      GateKind::Custom(Gate::new([[half, half_conj], [half_conj, half]])),
+      &[],
+      0
+    )
+  );
+  match &circuit.instructions()[1] {
+    Instruction::Gate {
+      kind: GateKind::Custom(gate),
+      target: 1,
+      ..
+    } => {
+      assert!(gate.is_unitary());
+      assert!((gate.matrix()[1][1] + 1.0).norm() < 1e-12);
+    }
+    other => panic!("{:?}", other),
+  }
+  match &circuit.instructions()[2] {
+    Instruction::Unitary { matrix, qubits } => {
+      assert_eq!(qubits, &vec![2, 0, 1]);
+      assert_eq!(matrix.len(), 8);
+      assert_eq!(matrix[4][7], Complex64::new(1.0, 0.0));
+      assert_eq!(matrix[3][3], Complex64::new(1.0, 0.0));
+    }
+    other => panic!("{:?}", other),
+  }
+}
+
+#[test]
+fn jumps_and_labels() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(4);
+  // Flip a coin until it comes up heads, then count it in the second bit
+  let circuit = parse(
+    "
+    DECLARE coin BIT
+    DECLARE done BIT
+    LABEL @flip
+    RESET 0
+    H 0
+    MEASURE 0 coin
+    JUMP-UNLESS @flip coin[0]
+    JUMP-WHEN @skip done
+    X 1
+    LABEL @skip
+    MEASURE 1 done
+    PRAGMA INITIAL_REWIRING \"NAIVE\"
+    ",
+  )
+  .unwrap();
+  assert_eq!(
+    circuit.instructions()[4],
+    Instruction::Jump {
+      label: "flip".to_string(),
+      condition: Some(Condition::new(vec![0], 0)),
+    }
+  );
+  let counts = circuit.sample(20, &mut rng);
+  assert_eq!(counts["11"], 20);
+}
+
+#[test]
+fn export_bell_pair() {
+  let mut circuit = Circuit::new(2, 2);
+  circuit.h(0).cx(0, 1).measure_all();
+  assert_eq!(
+    export(&circuit).unwrap(),
+    "DECLARE ro BIT[2]\nH 0\nCNOT 0 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+  );
+}
+
+#[test]
+fn export_round_trips() {
+  use crate::gate::Gate;
+  use num_complex::Complex64;
+  use rand::SeedableRng;
+  let mut circuit = Circuit::new(4, 2);
+  circuit
+    .h(0)
+    .sdg(1)
+    .controlled(GateKind::Tdg, &[0, 1], 2)
+    .u3(0.1, 0.2, 0.3, 3)
+    .controlled(GateKind::U3(1.0, 2.0, 3.0), &[3], 0)
+    .controlled(GateKind::Phase(0.25), &[1, 2], 3)
+    .ccx(0, 1, 2)
+    .controlled(GateKind::X, &[0, 1, 2], 3)
+    .controlled(GateKind::Ry(-0.5), &[2], 1)
+    .unitary(Gate::rx(0.3) * Gate::rz(0.7), 2)
+    .cswap(0, 1, 2)
+    .iswap(3, 1)
+    .push(Instruction::Unitary {
+      matrix: vec![
+        vec![Complex64::new(0.0, 1.0), Complex64::new(0.0, 0.0)],
+        vec![Complex64::new(0.0, 0.0), Complex64::new(-1.0, 0.0)],
+      ],
+      qubits: vec![0],
+    });
+  let exported = parse(&export(&circuit).unwrap()).unwrap();
+  let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+  let expected = circuit.run(&mut rng).state;
+  let state = exported.run(&mut rng).state;
+  for (a, b) in state.amplitudes().iter().zip(expected.amplitudes()) {
+    assert!((a - b).norm() < 1e-12);
+  }
+}
+
+#[test]
+fn export_keeps_unused_qubits() {
+  let mut circuit = Circuit::new(3, 0);
+  circuit.h(0);
+  let source = export(&circuit).unwrap();
+  assert_eq!(source, "H 0\nI 2\n");
+  assert_eq!(parse(&source).unwrap().num_qubits(), 3);
+  // Nothing is added when the last qubit is already used
+  let mut circuit = Circuit::new(3, 1);
+  circuit.h(0).measure(2, 0);
+  assert_eq!(
+    export(&circuit).unwrap(),
+    "DECLARE ro BIT[1]\nH 0\nMEASURE 2 ro[0]\n"
+  );
+}
+
+#[test]
+fn export_control_flow_as_jumps() {
+  use rand::SeedableRng;
+  let mut circuit = Circuit::new(2, 2);
+  circuit.h(0).measure(0, 0).push(Instruction::If {
+    condition: Condition::new(vec![0], 1),
+    then_branch: vec![gate(GateKind::X, &[], 1)],
+    else_branch: vec![gate(GateKind::H, &[], 1)],
+  });
+  circuit.push(Instruction::While {
+    condition: Condition::not_equal(vec![1], 1),
+    body: vec![
+      Instruction::Reset { qubit: 1 },
+      gate(GateKind::H, &[], 1),
+      Instruction::Measure { qubit: 1, clbit: 1 },
+    ],
+  });
+  let source = export(&circuit).unwrap();
+  assert!(source.contains("JUMP-UNLESS @else0 ro[0]\nX 1\nJUMP @end0\nLABEL @else0\nH 1\n"));
+  assert!(source.contains("LABEL @loop1\nJUMP-WHEN @end1 ro[1]\n"));
+  // Both circuits use the random numbers in the same order, so they give the same counts
+  let exported = parse(&source).unwrap();
+  let counts = circuit.sample(50, &mut rand::rngs::StdRng::seed_from_u64(6));
+  assert_eq!(
+    exported.sample(50, &mut rand::rngs::StdRng::seed_from_u64(6)),
+    counts
+  );
+}
+
+#[test]
+fn export_labels_do_not_clash() {
+  let mut circuit = Circuit::new(1, 1);
+  circuit
+    .push(Instruction::Label("else0".to_string()))
+    .measure(0, 0)
+    .push(Instruction::If {
+      condition: Condition::new(vec![0], 0),
+      then_branch: vec![gate(GateKind::X, &[], 0)],
+      else_branch: vec![],
+    })
+    .push(Instruction::Label("end1".to_string()));
+  let source = export(&circuit).unwrap();
+  assert!(source.contains("JUMP-WHEN @else2 ro[0]\n"), "{}", source);
+  parse(&source).unwrap();
+}
+
+#[test]
+fn export_unsupported() {
+  let mut circuit = Circuit::new(1, 2);
+  circuit.push(Instruction::If {
+    condition: Condition::new(vec![0, 1], 3),
+    then_branch: vec![gate(GateKind::X, &[], 0)],
+    else_branch: vec![],
+  });
+  assert!(export(&circuit).is_err());
+  let mut circuit = Circuit::new(1, 0);
+  circuit.rx(f64::NAN, 0);
+  let error = export(&circuit).unwrap_err();
+  assert!(error.message.contains("not a finite number"), "{}", error);
+  let mut circuit = Circuit::new(2, 0);
+  circuit.controlled(GateKind::Phase(f64::INFINITY), &[0], 1);
+  assert!(export(&circuit).is_err());
+  let mut circuit = Circuit::new(1, 0);
+  circuit.push(Instruction::Unitary {
+    matrix: vec![
+      vec![num_complex::Complex64::new(f64::NAN, 0.0); 2],
+      vec![num_complex::Complex64::new(0.0, 0.0); 2],
+    ],
+    qubits: vec![0],
+  });
+  assert!(export(&circuit).is_err());
+}
+
+#[test]
+fn errors_have_positions() {
+  let cases = [
+    ("H 0 0", 1, 1, "more than once"),
+    ("DECLARE ro BIT\nMEASURE 0 ro[1]", 2, 11, "out of range"),
+    ("DECLARE ro REAL[2]", 1, 12, "only BIT memory"),
+    ("MEASURE 0", 1, 1, "memory reference"),
+    ("FOO 0", 1, 1, "unknown gate 'FOO'"),
+    ("RX 0", 1, 1, "takes 1 parameters"),
+    ("CONTROLLED X 0", 1, 12, "takes 0 parameters and 1 qubits"),
+    ("DEFGATE G:\n    1, 1\n    0, 1", 1, 9, "not unitary"),
+    ("DEFGATE G:\n    1, 0, 0\n    0, 1, 0", 1, 9, "square"),
+    ("\n  HALT", 2, 3, "HALT is not supported"),
+    ("JUMP end", 1, 6, "expected a label"),
+    ("X 0\nJUMP @nowhere", 2, 6, "unknown label @nowhere"),
+    (
+      "DECLARE ro BIT\nJUMP-WHEN @end ro[0]\nLABEL @start",
+      2,
+      11,
+      "unknown label @end",
+    ),
+    ("LABEL @a\nX 0\nLABEL @a", 3, 7, "already defined on line 1"),
+    (
+      "X 18446744073709551615",
+      1,
+      3,
+      "qubit index 18446744073709551615 is too large",
+    ),
+    (
+      "DECLARE a BIT[18446744073709551615]\nDECLARE b BIT[2]",
+      2,
+      15,
+      "memory region 'b' is too large",
+    ),
+  ];
+  for (source, line, column, message) in &cases {
+    let error = parse(source).unwrap_err();
+    assert_eq!(
+      error.span,
+      Span {
+        line: *line,
+        column: *column
+      },
+      "{}",
+      error
+    );
+    assert!(error.message.contains(message), "{}", error);
+  }
+}
@@ -0,0 +1,304 @@
+use super::ExportError;
+use crate::circuit::*;
+use num_complex::Complex64;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+// U3 has no counterpart among the standard Quil gates, so define it when it is needed
+const U3_DEFINITION: &str = "DEFGATE U3(%theta, %phi, %lambda):
+    cos(%theta/2), -cis(%lambda)*sin(%theta/2)
+    cis(%phi)*sin(%theta/2), cis(%phi+%lambda)*cos(%theta/2)
+";
+
+pub(crate) fn export(circuit: &Circuit) -> Result<String, ExportError> {
+  let mut writer = Writer::default();
+  collect_labels(circuit.instructions(), &mut writer.labels);
+  writer.instructions(circuit.instructions())?;
+  let mut out = String::new();
+  if circuit.num_clbits() > 0 {
+    writeln!(out, "DECLARE ro BIT[{}]", circuit.num_clbits()).unwrap();
+  }
+  if writer.uses_u3 {
+    out.push_str(U3_DEFINITION);
+  }
+  for (index, matrix) in writer.matrices.iter().enumerate() {
+    writeln!(out, "DEFGATE UNITARY{}:", index).unwrap();
+    for row in matrix {
+      let entries: Vec<String> = row.iter().map(|entry| complex(*entry)).collect();
+      writeln!(out, "    {}", entries.join(", ")).unwrap();
+    }
+  }
+  out.push_str(&writer.body);
+  // The parser counts the qubits from the highest index it sees, so make sure the last
+  // qubit shows up, even if nothing happens to it
+  if writer.num_qubits < circuit.num_qubits() {
+    writeln!(out, "I {}", circuit.num_qubits() - 1).unwrap();
+  }
+  Ok(out)
+}
+
+#[derive(Default)]
+struct Writer {
+  body: String,
+  uses_u3: bool,
+  // The matrices of the gates that need a definition of their own, UNITARY0, UNITARY1, ...
+  matrices: Vec<Vec<Vec<Complex64>>>,
+  // Counts the ifs and loops, to give each one labels of its own
+  blocks: usize,
+  // The labels of the circuit itself, which the labels of the blocks must not clash with
+  labels: HashSet<String>,
+  // One more than the highest qubit written out so far
+  num_qubits: usize,
+}
+
+impl Writer {
+  fn instructions(&mut self, instructions: &[Instruction]) -> Result<(), ExportError> {
+    for instruction in instructions {
+      self.instruction(instruction)?;
+    }
+    Ok(())
+  }
+
+  fn line(&mut self, line: String) {
+    self.body.push_str(&line);
+    self.body.push('\n');
+  }
+
+  fn instruction(&mut self, instruction: &Instruction) -> Result<(), ExportError> {
+    match instruction {
+      Instruction::Gate {
+        kind,
+        target,
+        controls,
+      } => {
+        let line = self.gate(kind, controls, *target)?;
+        self.line(line);
+      }
+      Instruction::Swap { a, b, controls } => {
+        let (prefix, name, controls) = match controls.split_last() {
+          Some((last, rest)) => (rest, "CSWAP", vec![*last]),
+          None => (&controls[..], "SWAP", vec![]),
+        };
+        let line = format!(
+          "{}{} {}",
+          "CONTROLLED ".repeat(prefix.len()),
+          name,
+          self.qubits(prefix.iter().chain(&controls).chain(&[*a, *b]))
+        );
+        self.line(line);
+      }
+      Instruction::ISwap { a, b } => {
+        let line = format!("ISWAP {}", self.qubits(&[*a, *b]));
+        self.line(line);
+      }
+      Instruction::Unitary {
+        matrix,
+        qubits: targets,
+      } => {
+        let index = self.matrix_index(matrix)?;
+        let line = format!("UNITARY{} {}", index, self.qubits(targets));
+        self.line(line);
+      }
+      Instruction::Measure { qubit, clbit } => {
+        let line = format!("MEASURE {} ro[{}]", self.qubits(&[*qubit]), clbit);
+        self.line(line);
+      }
+      Instruction::Reset { qubit } => {
+        let line = format!("RESET {}", self.qubits(&[*qubit]));
+        self.line(line);
+      }
+      // Quil has no barriers, and they do not change what the circuit does
+      Instruction::Barrier { .. } => {}
+      Instruction::If {
+        condition,
+        then_branch,
+        else_branch,
+      } => {
+        let (clbit, when) = bit_test(condition)?;
+        let block = self.next_block();
+        self.jump_unless(&format!("else{}", block), clbit, when);
+        self.instructions(then_branch)?;
+        if else_branch.is_empty() {
+          self.line(format!("LABEL @else{}", block));
+        } else {
+          self.line(format!("JUMP @end{}", block));
+          self.line(format!("LABEL @else{}", block));
+          self.instructions(else_branch)?;
+          self.line(format!("LABEL @end{}", block));
+        }
+      }
+      Instruction::While { condition, body } => {
+        let (clbit, when) = bit_test(condition)?;
+        let block = self.next_block();
+        self.line(format!("LABEL @loop{}", block));
+        self.jump_unless(&format!("end{}", block), clbit, when);
+        self.instructions(body)?;
+        self.line(format!("JUMP @loop{}", block));
+        self.line(format!("LABEL @end{}", block));
+      }
+      Instruction::Label(label) => self.line(format!("LABEL @{}", label)),
+      Instruction::Jump { label, condition } => match condition {
+        None => self.line(format!("JUMP @{}", label)),
+        Some(condition) => {
+          let (clbit, when) = bit_test(condition)?;
+          let jump = if when { "JUMP-WHEN" } else { "JUMP-UNLESS" };
+          self.line(format!("{} @{} ro[{}]", jump, label, clbit));
+        }
+      },
+    }
+    Ok(())
+  }
+
+  // The number of the next block, skipping any whose labels the circuit already uses
+  fn next_block(&mut self) -> usize {
+    loop {
+      let block = self.blocks;
+      self.blocks += 1;
+      let clashes = ["else", "end", "loop"]
+        .iter()
+        .any(|prefix| self.labels.contains(&format!("{}{}", prefix, block)));
+      if !clashes {
+        return block;
+      }
+    }
+  }
+
+  // Jumps to the label, unless the classical bit reads `when`
+  fn jump_unless(&mut self, label: &str, clbit: usize, when: bool) {
+    let jump = if when { "JUMP-UNLESS" } else { "JUMP-WHEN" };
+    self.line(format!("{} @{} ro[{}]", jump, label, clbit));
+  }
+
+  // The qubit operands of an instruction, keeping track of the highest qubit
+  fn qubits<'a, I: IntoIterator<Item = &'a usize>>(&mut self, qubits: I) -> String {
+    let qubits: Vec<String> = qubits
+      .into_iter()
+      .map(|qubit| {
+        self.num_qubits = self.num_qubits.max(qubit + 1);
+        qubit.to_string()
+      })
+      .collect();
+    qubits.join(" ")
+  }
+
+  fn matrix_index(&mut self, matrix: &[Vec<Complex64>]) -> Result<usize, ExportError> {
+    // NaN and infinity have no literal in Quil, so they could not be read back in
+    if let Some(entry) = matrix.iter().flatten().find(|entry| !entry.is_finite()) {
+      return Err(ExportError::new(format!(
+        "a gate matrix has an entry that is not a finite number: {}",
+        entry
+      )));
+    }
+    Ok(
+      match self.matrices.iter().position(|other| other == matrix) {
+        Some(index) => index,
+        None => {
+          self.matrices.push(matrix.to_vec());
+          self.matrices.len() - 1
+        }
+      },
+    )
+  }
+
+  // The line of Quil that applies a gate, using the standard gates where there is one.
+  // Any other controls become CONTROLLED modifiers, which take the first qubits.
+  fn gate(
+    &mut self,
+    kind: &GateKind,
+    controls: &[usize],
+    target: usize,
+  ) -> Result<String, ExportError> {
+    // NaN and infinity have no literal in Quil, so they could not be read back in
+    let params = match kind {
+      GateKind::Rx(theta) | GateKind::Ry(theta) | GateKind::Rz(theta) | GateKind::Phase(theta) => {
+        vec![*theta]
+      }
+      GateKind::U3(theta, phi, lambda) => vec![*theta, *phi, *lambda],
+      _ => vec![],
+    };
+    if let Some(param) = params.iter().find(|param| !param.is_finite()) {
+      return Err(ExportError::new(format!(
+        "'{}' has a parameter that is not a finite number: {}",
+        kind.name(),
+        param
+      )));
+    }
+    let (name, built_in) = match (kind, controls.len()) {
+      (GateKind::X, 1) => ("CNOT".to_string(), 1),
+      (GateKind::X, count) if count >= 2 => ("CCNOT".to_string(), 2),
+      (GateKind::Z, count) if count >= 1 => ("CZ".to_string(), 1),
+      (GateKind::Phase(lambda), count) if count >= 1 => (format!("CPHASE({})", lambda), 1),
+      (kind, _) => (self.gate_name(kind)?, 0),
+    };
+    let modifiers = controls.len() - built_in;
+    Ok(format!(
+      "{}{} {}",
+      "CONTROLLED ".repeat(modifiers),
+      name,
+      self.qubits(controls.iter().chain(&[target]))
+    ))
+  }
+
+  fn gate_name(&mut self, kind: &GateKind) -> Result<String, ExportError> {
+    Ok(match kind {
+      GateKind::I => "I".to_string(),
+      GateKind::X => "X".to_string(),
+      GateKind::Y => "Y".to_string(),
+      GateKind::Z => "Z".to_string(),
+      GateKind::H => "H".to_string(),
+      GateKind::S => "S".to_string(),
+      GateKind::Sdg => "DAGGER S".to_string(),
+      GateKind::T => "T".to_string(),
+      GateKind::Tdg => "DAGGER T".to_string(),
+      GateKind::Rx(theta) => format!("RX({})", theta),
+      GateKind::Ry(theta) => format!("RY({})", theta),
+      GateKind::Rz(theta) => format!("RZ({})", theta),
+      GateKind::Phase(lambda) => format!("PHASE({})", lambda),
+      GateKind::U3(theta, phi, lambda) => {
+        self.uses_u3 = true;
+        format!("U3({}, {}, {})", theta, phi, lambda)
+      }
+      GateKind::Custom(gate) => {
+        let matrix: Vec<Vec<Complex64>> = gate.matrix().iter().map(|row| row.to_vec()).collect();
+        format!("UNITARY{}", self.matrix_index(&matrix)?)
+      }
+    })
+  }
+}
+
+// Complex numbers are written as `re+imi`, with enough digits to read back the same value
+fn complex(value: Complex64) -> String {
+  format!("{}{:+}i", value.re, value.im)
+}
+
+fn collect_labels(instructions: &[Instruction], labels: &mut HashSet<String>) {
+  for instruction in instructions {
+    match instruction {
+      Instruction::Label(label) => {
+        labels.insert(label.clone());
+      }
+      Instruction::If {
+        then_branch,
+        else_branch,
+        ..
+      } => {
+        collect_labels(then_branch, labels);
+        collect_labels(else_branch, labels);
+      }
+      Instruction::While { body, .. } => collect_labels(body, labels),
+      _ => {}
+    }
+  }
+}
+
+// Quil can only jump on a single bit. Returns the bit, and the value it must have for the
+// condition to hold.
+fn bit_test(condition: &Condition) -> Result<(usize, bool), ExportError> {
+  match (&condition.clbits[..], condition.comparison, condition.value) {
+    ([clbit], Comparison::Equal, value) if value <= 1 => Ok((*clbit, value == 1)),
+    ([clbit], Comparison::NotEqual, value) if value <= 1 => Ok((*clbit, value == 0)),
+    _ => Err(ExportError::new(
+      "Quil conditions can only test a single bit against 0 or 1",
+    )),
+  }
+}
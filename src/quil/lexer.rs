@@ -0,0 +1,226 @@
+use super::{ParseError, Span};
+
+/// The smallest meaningful pieces of a Quil program.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenKind {
+  Identifier(String),
+  Integer(u64),
+  Real(f64),
+  Imaginary(f64),
+  Parameter(String),
+  Label(String),
+  String(String),
+  Symbol(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Token {
+  pub kind: TokenKind,
+  pub span: Span,
+}
+
+/// Quil is line based: every instruction is on a line of its own, and the rows of a gate
+/// matrix are indented under the `DEFGATE` line.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Line {
+  pub indented: bool,
+  pub tokens: Vec<Token>,
+  pub span: Span,
+}
+
+const SYMBOLS: &[&str] = &["(", ")", "[", "]", ",", ":", "+", "-", "*", "/", "^"];
+
+/// Splits the source into lines of tokens, skipping comments and empty lines.
+/// A `;` separates instructions just like a line break.
+pub(crate) fn tokenize(source: &str) -> Result<Vec<Line>, ParseError> {
+  let mut lines = Vec::new();
+  for (index, text) in source.lines().enumerate() {
+    let chars: Vec<char> = text.chars().collect();
+    let mut position = 0;
+    let mut line = Line {
+      indented: matches!(chars.first(), Some(c) if c.is_whitespace()),
+      tokens: Vec::new(),
+      span: Span {
+        line: index + 1,
+        column: 1,
+      },
+    };
+    loop {
+      while matches!(chars.get(position), Some(c) if c.is_whitespace()) {
+        position += 1;
+      }
+      let span = Span {
+        line: index + 1,
+        column: position + 1,
+      };
+      let c = match chars.get(position) {
+        None | Some('#') => break,
+        Some(c) => *c,
+      };
+      if c == ';' {
+        position += 1;
+        let next = Line {
+          indented: false,
+          tokens: Vec::new(),
+          span,
+        };
+        let finished = std::mem::replace(&mut line, next);
+        if !finished.tokens.is_empty() {
+          lines.push(finished);
+        }
+        continue;
+      }
+      let kind = if c.is_ascii_alphabetic() || c == '_' {
+        TokenKind::Identifier(name(&chars, &mut position))
+      } else if c == '%' || c == '@' {
+        position += 1;
+        let name = name(&chars, &mut position);
+        if name.is_empty() {
+          return Err(ParseError::new(
+            format!("expected a name after '{}'", c),
+            span,
+          ));
+        }
+        if c == '%' {
+          TokenKind::Parameter(name)
+        } else {
+          TokenKind::Label(name)
+        }
+      } else if c.is_ascii_digit()
+        || (c == '.' && matches!(chars.get(position + 1), Some(c) if c.is_ascii_digit()))
+      {
+        number(&chars, &mut position, span)?
+      } else if c == '"' {
+        let start = position + 1;
+        position = start;
+        while matches!(chars.get(position), Some(c) if *c != '"') {
+          position += 1;
+        }
+        if position == chars.len() {
+          return Err(ParseError::new("unterminated string", span));
+        }
+        position += 1;
+        TokenKind::String(chars[start..position - 1].iter().collect())
+      } else {
+        match SYMBOLS.iter().find(|symbol| symbol.starts_with(c)) {
+          Some(symbol) => {
+            position += 1;
+            TokenKind::Symbol(symbol)
+          }
+          None => {
+            return Err(ParseError::new(
+              format!("unexpected character '{}'", c),
+              span,
+            ))
+          }
+        }
+      };
+      if line.tokens.is_empty() {
+        line.span = span;
+      }
+      line.tokens.push(Token { kind, span });
+    }
+    if !line.tokens.is_empty() {
+      lines.push(line);
+    }
+  }
+  Ok(lines)
+}
+
+// Names can have dashes inside them, like JUMP-WHEN, as long as a letter follows the dash
+fn name(chars: &[char], position: &mut usize) -> String {
+  let start = *position;
+  while let Some(c) = chars.get(*position) {
+    let dash = *c == '-'
+      && matches!(chars.get(*position + 1), Some(c) if c.is_ascii_alphabetic() || *c == '_');
+    if c.is_ascii_alphanumeric() || *c == '_' || dash {
+      *position += 1;
+    } else {
+      break;
+    }
+  }
+  chars[start..*position].iter().collect()
+}
+
+// Numbers are integers or reals, and an `i` right after a number makes it imaginary
+fn number(chars: &[char], position: &mut usize, span: Span) -> Result<TokenKind, ParseError> {
+  let start = *position;
+  let digits = |position: &mut usize| {
+    while matches!(chars.get(*position), Some(c) if c.is_ascii_digit()) {
+      *position += 1;
+    }
+  };
+  digits(position);
+  let mut real = false;
+  if chars.get(*position) == Some(&'.') {
+    real = true;
+    *position += 1;
+    digits(position);
+  }
+  if matches!(chars.get(*position), Some('e') | Some('E')) {
+    let sign = matches!(chars.get(*position + 1), Some('+') | Some('-'));
+    let digit_at = *position + if sign { 2 } else { 1 };
+    if matches!(chars.get(digit_at), Some(c) if c.is_ascii_digit()) {
+      real = true;
+      *position = digit_at;
+      digits(position);
+    }
+  }
+  let text: String = chars[start..*position].iter().collect();
+  let imaginary = chars.get(*position) == Some(&'i')
+    && !matches!(chars.get(*position + 1), Some(c) if c.is_ascii_alphanumeric() || *c == '_');
+  if imaginary {
+    *position += 1;
+  }
+  if real || imaginary {
+    let value = text
+      .parse()
+      .map_err(|_| ParseError::new(format!("invalid number '{}'", text), span))?;
+    Ok(if imaginary {
+      TokenKind::Imaginary(value)
+    } else {
+      TokenKind::Real(value)
+    })
+  } else {
+    text
+      .parse()
+      .map(TokenKind::Integer)
+      .map_err(|_| ParseError::new(format!("integer '{}' is too large", text), span))
+  }
+}
+
+#[test]
+fn tokenize_lines() {
+  let lines = tokenize("DECLARE ro BIT[2] # memory\n\nH 0; CNOT 0 1\n").unwrap();
+  assert_eq!(lines.len(), 3);
+  assert_eq!(lines[0].tokens.len(), 6);
+  assert_eq!(
+    lines[1].tokens[0].kind,
+    TokenKind::Identifier("H".to_string())
+  );
+  assert_eq!(lines[2].span, Span { line: 3, column: 6 });
+}
+
+#[test]
+fn tokenize_names_and_numbers() {
+  let lines = tokenize("JUMP-WHEN @end-loop ro\n    1.5e-3i, -2i, %theta-pi").unwrap();
+  let kinds: Vec<TokenKind> = lines
+    .iter()
+    .flat_map(|line| line.tokens.iter().map(|token| token.kind.clone()))
+    .collect();
+  assert_eq!(
+    kinds,
+    vec![
+      TokenKind::Identifier("JUMP-WHEN".to_string()),
+      TokenKind::Label("end-loop".to_string()),
+      TokenKind::Identifier("ro".to_string()),
+      TokenKind::Imaginary(1.5e-3),
+      TokenKind::Symbol(","),
+      TokenKind::Symbol("-"),
+      TokenKind::Imaginary(2.0),
+      TokenKind::Symbol(","),
+      TokenKind::Parameter("theta-pi".to_string()),
+    ]
+  );
+  assert!(lines[1].indented);
+}
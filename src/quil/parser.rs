@@ -0,0 +1,964 @@
+use super::lexer::{Line, Token, TokenKind};
+use super::{ParseError, Span};
+use crate::approx::{is_identity, ComplexMargin};
+use crate::circuit::*;
+use crate::gate::Gate;
+use crate::ket::{COMPLEX_ONE, COMPLEX_ZERO};
+use num_complex::Complex64;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::f64::consts::PI;
+
+// Quil has no nesting apart from gate definitions, so the parser builds the circuit
+// directly, one line at a time.
+
+pub(crate) fn parse_program(lines: Vec<Line>) -> Result<Circuit, ParseError> {
+  let mut parser = Parser::default();
+  let mut lines = lines.into_iter().peekable();
+  while let Some(line) = lines.next() {
+    let mut tokens = Tokens::new(&line);
+    if tokens.is_keyword("DEFGATE") {
+      // The rows of the matrix are the indented lines that follow
+      let mut rows = Vec::new();
+      while let Some(row) = lines.next_if(|line| line.indented) {
+        rows.push(row);
+      }
+      parser.define_gate(&mut tokens, &rows)?;
+    } else {
+      parser.statement(&mut tokens)?;
+    }
+  }
+  // Jumps can go forward, so the labels are only all known at the end
+  if let Some((label, span)) = parser
+    .jumps
+    .iter()
+    .find(|(label, _)| !parser.labels.contains_key(label))
+  {
+    return Err(ParseError::new(
+      format!("jump to unknown label @{}", label),
+      *span,
+    ));
+  }
+  let mut circuit = Circuit::new(parser.num_qubits, parser.num_clbits);
+  for pending in parser.instructions {
+    match pending {
+      Pending::Instruction(instruction) => {
+        circuit.push(instruction);
+      }
+      Pending::ResetAll => {
+        for qubit in 0..parser.num_qubits {
+          circuit.reset(qubit);
+        }
+      }
+    }
+  }
+  Ok(circuit)
+}
+
+// RESET without a qubit resets all of them, and we only know how many there are at the end
+enum Pending {
+  Instruction(Instruction),
+  ResetAll,
+}
+
+struct Region {
+  name: String,
+  offset: usize,
+  size: usize,
+}
+
+#[derive(Clone)]
+struct GateDefinition {
+  params: Vec<String>,
+  matrix: Vec<Vec<Expression>>,
+}
+
+#[derive(Default)]
+struct Parser {
+  regions: Vec<Region>,
+  num_qubits: usize,
+  num_clbits: usize,
+  gates: HashMap<String, GateDefinition>,
+  instructions: Vec<Pending>,
+  labels: HashMap<String, Span>,
+  jumps: Vec<(String, Span)>,
+}
+
+// Instructions of Quil that are outside the supported subset
+const UNSUPPORTED: &[&str] = &[
+  "HALT",
+  "WAIT",
+  "FORKED",
+  "DEFCIRCUIT",
+  "DEFFRAME",
+  "DEFWAVEFORM",
+  "DEFCAL",
+  "PULSE",
+  "CAPTURE",
+  "RAW-CAPTURE",
+  "DELAY",
+  "FENCE",
+  "INCLUDE",
+  "MOVE",
+  "EXCHANGE",
+  "CONVERT",
+  "LOAD",
+  "STORE",
+  "NEG",
+  "NOT",
+  "AND",
+  "IOR",
+  "XOR",
+  "ADD",
+  "SUB",
+  "MUL",
+  "DIV",
+  "EQ",
+  "GT",
+  "GE",
+  "LT",
+  "LE",
+];
+
+impl Parser {
+  fn statement(&mut self, tokens: &mut Tokens) -> Result<(), ParseError> {
+    let (keyword, span) = tokens.identifier()?;
+    match keyword.as_str() {
+      "DECLARE" => {
+        let (name, name_span) = tokens.identifier()?;
+        if self.regions.iter().any(|region| region.name == name) {
+          return Err(ParseError::new(
+            format!("memory region '{}' is already declared", name),
+            name_span,
+          ));
+        }
+        let (kind, kind_span) = tokens.identifier()?;
+        if kind != "BIT" {
+          return Err(ParseError::new(
+            format!("only BIT memory is supported, not {}", kind),
+            kind_span,
+          ));
+        }
+        let (size, size_span) = if tokens.eat("[") {
+          let size = tokens.integer()?;
+          tokens.expect("]")?;
+          size
+        } else {
+          (1, name_span)
+        };
+        tokens.end()?;
+        let size = usize::try_from(size)
+          .ok()
+          .filter(|size| self.num_clbits.checked_add(*size).is_some())
+          .ok_or_else(|| {
+            ParseError::new(format!("memory region '{}' is too large", name), size_span)
+          })?;
+        self.regions.push(Region {
+          name,
+          offset: self.num_clbits,
+          size,
+        });
+        self.num_clbits += size;
+      }
+      "LABEL" => {
+        let (label, label_span) = tokens.label()?;
+        tokens.end()?;
+        if let Some(first) = self.labels.get(&label) {
+          return Err(ParseError::new(
+            format!("label @{} is already defined on line {}", label, first.line),
+            label_span,
+          ));
+        }
+        self.labels.insert(label.clone(), label_span);
+        self.push(Instruction::Label(label));
+      }
+      "JUMP" => {
+        let (label, label_span) = tokens.label()?;
+        tokens.end()?;
+        self.jumps.push((label.clone(), label_span));
+        self.push(Instruction::Jump {
+          label,
+          condition: None,
+        });
+      }
+      "JUMP-WHEN" | "JUMP-UNLESS" => {
+        let (label, label_span) = tokens.label()?;
+        let clbit = self.memory_reference(tokens)?;
+        tokens.end()?;
+        self.jumps.push((label.clone(), label_span));
+        let value = (keyword == "JUMP-WHEN") as u64;
+        self.push(Instruction::Jump {
+          label,
+          condition: Some(Condition::new(vec![clbit], value)),
+        });
+      }
+      "MEASURE" => {
+        let qubit = self.qubit(tokens)?;
+        if tokens.at_end() {
+          return Err(ParseError::new(
+            "MEASURE needs a memory reference to store the result in",
+            span,
+          ));
+        }
+        let clbit = self.memory_reference(tokens)?;
+        tokens.end()?;
+        self.push(Instruction::Measure { qubit, clbit });
+      }
+      "RESET" => {
+        if tokens.at_end() {
+          self.instructions.push(Pending::ResetAll);
+        } else {
+          let qubit = self.qubit(tokens)?;
+          tokens.end()?;
+          self.push(Instruction::Reset { qubit });
+        }
+      }
+      // Pragmas are hints for the compiler, and do not change what the program does
+      "PRAGMA" | "NOP" => {}
+      _ if UNSUPPORTED.contains(&keyword.as_str()) => {
+        return Err(ParseError::new(
+          format!("{} is not supported", keyword),
+          span,
+        ))
+      }
+      _ => {
+        tokens.position -= 1;
+        self.gate_application(tokens)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn push(&mut self, instruction: Instruction) {
+    self.instructions.push(Pending::Instruction(instruction));
+  }
+
+  fn qubit(&mut self, tokens: &mut Tokens) -> Result<usize, ParseError> {
+    let (qubit, span) = tokens.integer()?;
+    // The number of qubits is one more than the highest index, which has to fit as well
+    let qubit = usize::try_from(qubit)
+      .ok()
+      .filter(|qubit| qubit.checked_add(1).is_some())
+      .ok_or_else(|| ParseError::new(format!("qubit index {} is too large", qubit), span))?;
+    self.num_qubits = self.num_qubits.max(qubit + 1);
+    Ok(qubit)
+  }
+
+  // `ro[1]`, or just `ro` for the first bit of the region
+  fn memory_reference(&self, tokens: &mut Tokens) -> Result<usize, ParseError> {
+    let (name, span) = tokens.identifier()?;
+    let mut index = 0;
+    if tokens.eat("[") {
+      // An index too large for usize is out of range anyway
+      index = usize::try_from(tokens.integer()?.0).unwrap_or(usize::MAX);
+      tokens.expect("]")?;
+    }
+    let region = self
+      .regions
+      .iter()
+      .find(|region| region.name == name)
+      .ok_or_else(|| ParseError::new(format!("unknown memory region '{}'", name), span))?;
+    if index >= region.size {
+      return Err(ParseError::new(
+        format!(
+          "index {} out of range for memory region '{}' of size {}",
+          index, name, region.size
+        ),
+        span,
+      ));
+    }
+    Ok(region.offset + index)
+  }
+
+  fn define_gate(&mut self, tokens: &mut Tokens, rows: &[Line]) -> Result<(), ParseError> {
+    tokens.identifier()?;
+    let (name, span) = tokens.identifier()?;
+    if self.gates.contains_key(&name) || standard_gate_arity(&name).is_some() {
+      return Err(ParseError::new(
+        format!("gate '{}' is already defined", name),
+        span,
+      ));
+    }
+    let mut params = Vec::new();
+    if tokens.eat("(") {
+      loop {
+        match tokens.peek().map(|token| token.kind.clone()) {
+          Some(TokenKind::Parameter(param)) => params.push(param),
+          _ => return tokens.error("a parameter"),
+        }
+        tokens.next();
+        if !tokens.eat(",") {
+          break;
+        }
+      }
+      tokens.expect(")")?;
+    }
+    if tokens.is_keyword("AS") {
+      tokens.next();
+      let (kind, kind_span) = tokens.identifier()?;
+      if kind != "MATRIX" {
+        return Err(ParseError::new(
+          format!("only matrix gate definitions are supported, not {}", kind),
+          kind_span,
+        ));
+      }
+    }
+    tokens.expect(":")?;
+    tokens.end()?;
+    let mut matrix = Vec::new();
+    for row in rows {
+      let mut tokens = Tokens::new(row);
+      let mut entries = vec![tokens.expression()?];
+      while tokens.eat(",") {
+        entries.push(tokens.expression()?);
+      }
+      tokens.end()?;
+      matrix.push(entries);
+    }
+    let size = matrix.len();
+    if size < 2 || !size.is_power_of_two() || matrix.iter().any(|row| row.len() != size) {
+      return Err(ParseError::new(
+        format!(
+          "the matrix of gate '{}' must be square, with a power of two rows",
+          name
+        ),
+        span,
+      ));
+    }
+    let definition = GateDefinition { params, matrix };
+    // Without parameters, the matrix can be checked right away
+    if definition.params.is_empty() {
+      gate_matrix(&name, &definition, &[], span)?;
+    }
+    self.gates.insert(name, definition);
+    Ok(())
+  }
+
+  // A gate, possibly under DAGGER and CONTROLLED modifiers, applied to some qubits.
+  // Each CONTROLLED takes the first of the remaining qubits as its control.
+  fn gate_application(&mut self, tokens: &mut Tokens) -> Result<(), ParseError> {
+    let mut modifiers = Vec::new();
+    while tokens.is_keyword("DAGGER") || tokens.is_keyword("CONTROLLED") {
+      modifiers.push(tokens.identifier()?.0);
+    }
+    let (name, span) = tokens.identifier()?;
+    let mut params = Vec::new();
+    if tokens.eat("(") {
+      params.push(tokens.expression()?);
+      while tokens.eat(",") {
+        params.push(tokens.expression()?);
+      }
+      tokens.expect(")")?;
+    }
+    let mut qubits = Vec::new();
+    while !tokens.at_end() {
+      qubits.push(self.qubit(tokens)?);
+    }
+    for (i, qubit) in qubits.iter().enumerate() {
+      if qubits[..i].contains(qubit) {
+        return Err(ParseError::new(
+          "the same qubit is used more than once",
+          span,
+        ));
+      }
+    }
+    let params = params
+      .iter()
+      .map(|param| real(evaluate(param, &HashMap::new())?, span))
+      .collect::<Result<Vec<f64>, ParseError>>()?;
+    let num_controls = modifiers
+      .iter()
+      .filter(|modifier| *modifier == "CONTROLLED")
+      .count();
+    if qubits.len() < num_controls {
+      return Err(ParseError::new(
+        format!("not enough qubits for the controls of gate '{}'", name),
+        span,
+      ));
+    }
+    let (controls, targets) = qubits.split_at(num_controls);
+    let (num_params, num_qubits) = match self.gates.get(&name) {
+      Some(definition) => (
+        definition.params.len(),
+        definition.matrix.len().trailing_zeros() as usize,
+      ),
+      None => standard_gate_arity(&name)
+        .ok_or_else(|| ParseError::new(format!("unknown gate '{}'", name), span))?,
+    };
+    if params.len() != num_params || targets.len() != num_qubits {
+      return Err(ParseError::new(
+        format!(
+          "gate '{}' takes {} parameters and {} qubits, but was given {} and {}",
+          name,
+          num_params,
+          num_qubits,
+          params.len(),
+          targets.len()
+        ),
+        span,
+      ));
+    }
+    let mut instructions = match self.gates.get(&name) {
+      Some(definition) => {
+        let m = gate_matrix(&name, definition, &params, span)?;
+        if m.len() == 2 {
+          vec![Instruction::Gate {
+            kind: GateKind::Custom(Gate::new([[m[0][0], m[0][1]], [m[1][0], m[1][1]]])),
+            target: targets[0],
+            controls: Vec::new(),
+          }]
+        } else {
+          vec![Instruction::Unitary {
+            matrix: m,
+            qubits: targets.to_vec(),
+          }]
+        }
+      }
+      None => standard_gate(&name, &params, targets),
+    };
+    // The modifier closest to the gate applies first, and takes the last of the controls
+    let mut controls = controls.iter().rev();
+    for modifier in modifiers.iter().rev() {
+      if modifier == "DAGGER" {
+        instructions = instructions.iter().rev().map(dagger).collect();
+      } else {
+        let control = *controls.next().unwrap();
+        instructions = instructions
+          .iter()
+          .map(|instruction| controlled(instruction, control))
+          .collect();
+      }
+    }
+    for instruction in instructions {
+      self.push(instruction);
+    }
+    Ok(())
+  }
+}
+
+// Evaluates the matrix of a gate definition, and checks that it is unitary
+fn gate_matrix(
+  name: &str,
+  definition: &GateDefinition,
+  params: &[f64],
+  span: Span,
+) -> Result<Vec<Vec<Complex64>>, ParseError> {
+  let parameters: Parameters = definition
+    .params
+    .iter()
+    .cloned()
+    .zip(params.iter().map(|param| Complex64::new(*param, 0.0)))
+    .collect();
+  let matrix = definition
+    .matrix
+    .iter()
+    .map(|row| {
+      row
+        .iter()
+        .map(|entry| evaluate(entry, &parameters))
+        .collect()
+    })
+    .collect::<Result<Vec<Vec<Complex64>>, ParseError>>()?;
+  if !is_unitary(&matrix) {
+    return Err(ParseError::new(
+      format!("the matrix of gate '{}' is not unitary", name),
+      span,
+    ));
+  }
+  Ok(matrix)
+}
+
+// Matrices in a program are written out with a limited number of digits, so they get a
+// larger margin than gates built in code
+fn is_unitary(matrix: &[Vec<Complex64>]) -> bool {
+  let product: Vec<Vec<Complex64>> = (0..matrix.len())
+    .map(|i| {
+      (0..matrix.len())
+        .map(|j| {
+          (0..matrix.len())
+            .map(|k| matrix[k][i].conj() * matrix[k][j])
+            .sum()
+        })
+        .collect()
+    })
+    .collect();
+  is_identity(&product, ComplexMargin::zero().epsilon(1e-9))
+}
+
+// The conjugate transpose of a matrix
+fn dagger_matrix(matrix: &[Vec<Complex64>]) -> Vec<Vec<Complex64>> {
+  (0..matrix.len())
+    .map(|i| matrix.iter().map(|row| row[i].conj()).collect())
+    .collect()
+}
+
+// The matrix with one more qubit in front, that only acts when that qubit is |1>
+fn controlled_matrix(matrix: &[Vec<Complex64>]) -> Vec<Vec<Complex64>> {
+  let size = matrix.len();
+  (0..2 * size)
+    .map(|i| {
+      (0..2 * size)
+        .map(|j| match (i < size, j < size) {
+          (true, true) if i == j => COMPLEX_ONE,
+          (false, false) => matrix[i - size][j - size],
+          _ => COMPLEX_ZERO,
+        })
+        .collect()
+    })
+    .collect()
+}
+
+fn iswap_matrix() -> Vec<Vec<Complex64>> {
+  let (o, l, i) = (COMPLEX_ZERO, COMPLEX_ONE, Complex64::new(0.0, 1.0));
+  vec![
+    vec![l, o, o, o],
+    vec![o, o, i, o],
+    vec![o, i, o, o],
+    vec![o, o, o, l],
+  ]
+}
+
+fn dagger(instruction: &Instruction) -> Instruction {
+  match instruction {
+    Instruction::Gate {
+      kind,
+      target,
+      controls,
+    } => Instruction::Gate {
+      kind: kind.dagger(),
+      target: *target,
+      controls: controls.clone(),
+    },
+    Instruction::ISwap { a, b } => Instruction::Unitary {
+      matrix: dagger_matrix(&iswap_matrix()),
+      qubits: vec![*a, *b],
+    },
+    Instruction::Unitary { matrix, qubits } => Instruction::Unitary {
+      matrix: dagger_matrix(matrix),
+      qubits: qubits.clone(),
+    },
+    // Swaps are their own inverses
+    other => other.clone(),
+  }
+}
+
+fn controlled(instruction: &Instruction, control: usize) -> Instruction {
+  let mut instruction = instruction.clone();
+  match &mut instruction {
+    Instruction::Gate { controls, .. } | Instruction::Swap { controls, .. } => {
+      controls.insert(0, control)
+    }
+    Instruction::ISwap { a, b } => {
+      instruction = Instruction::Unitary {
+        matrix: controlled_matrix(&iswap_matrix()),
+        qubits: vec![control, *a, *b],
+      }
+    }
+    Instruction::Unitary { matrix, qubits } => {
+      *matrix = controlled_matrix(matrix);
+      qubits.insert(0, control);
+    }
+    _ => unreachable!("gates only expand into gates, swaps and unitaries"),
+  }
+  instruction
+}
+
+// The number of parameters and qubits of the standard Quil gates
+fn standard_gate_arity(name: &str) -> Option<(usize, usize)> {
+  Some(match name {
+    "I" | "X" | "Y" | "Z" | "H" | "S" | "T" => (0, 1),
+    "PHASE" | "RX" | "RY" | "RZ" => (1, 1),
+    "CZ" | "CNOT" | "SWAP" | "ISWAP" => (0, 2),
+    "CPHASE" | "CPHASE00" | "CPHASE01" | "CPHASE10" | "PSWAP" | "XY" => (1, 2),
+    "CCNOT" | "CSWAP" => (0, 3),
+    _ => return None,
+  })
+}
+
+fn gate(kind: GateKind, controls: &[usize], target: usize) -> Instruction {
+  Instruction::Gate {
+    kind,
+    target,
+    controls: controls.to_vec(),
+  }
+}
+
+// Builds the instructions for a standard gate. The parameter and qubit counts have already
+// been checked against `standard_gate_arity`.
+fn standard_gate(name: &str, p: &[f64], q: &[usize]) -> Vec<Instruction> {
+  let last = *q.last().unwrap();
+  let controls = &q[..q.len() - 1];
+  let kind = match name {
+    "I" => GateKind::I,
+    "X" | "CNOT" | "CCNOT" => GateKind::X,
+    "Y" => GateKind::Y,
+    "Z" | "CZ" => GateKind::Z,
+    "H" => GateKind::H,
+    "S" => GateKind::S,
+    "T" => GateKind::T,
+    "PHASE" | "CPHASE" => GateKind::Phase(p[0]),
+    "RX" => GateKind::Rx(p[0]),
+    "RY" => GateKind::Ry(p[0]),
+    "RZ" => GateKind::Rz(p[0]),
+    "SWAP" | "CSWAP" => {
+      return vec![Instruction::Swap {
+        a: q[q.len() - 2],
+        b: last,
+        controls: q[..q.len() - 2].to_vec(),
+      }]
+    }
+    "ISWAP" => return vec![Instruction::ISwap { a: q[0], b: q[1] }],
+    // CPHASE00 puts the phase on |00> instead of |11>, so flip both qubits around CPHASE
+    "CPHASE00" | "CPHASE01" | "CPHASE10" => {
+      let flipped: Vec<usize> = match name {
+        "CPHASE00" => vec![q[0], q[1]],
+        "CPHASE01" => vec![q[0]],
+        _ => vec![q[1]],
+      };
+      let flips = flipped.iter().map(|qubit| gate(GateKind::X, &[], *qubit));
+      return flips
+        .clone()
+        .chain(std::iter::once(gate(GateKind::Phase(p[0]), &q[..1], q[1])))
+        .chain(flips)
+        .collect();
+    }
+    "PSWAP" | "XY" => {
+      let (o, l) = (COMPLEX_ZERO, COMPLEX_ONE);
+      let (a, b) = if name == "PSWAP" {
+        (o, Complex64::from_polar(1.0, p[0]))
+      } else {
+        let half = p[0] / 2.0;
+        (
+          Complex64::new(half.cos(), 0.0),
+          Complex64::new(0.0, half.sin()),
+        )
+      };
+      return vec![Instruction::Unitary {
+        matrix: vec![
+          vec![l, o, o, o],
+          vec![o, a, b, o],
+          vec![o, b, a, o],
+          vec![o, o, o, l],
+        ],
+        qubits: q.to_vec(),
+      }];
+    }
+    _ => unreachable!("unknown standard gate {}", name),
+  };
+  vec![gate(kind, controls, last)]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expression {
+  Number(Complex64),
+  Parameter(String, Span),
+  Identifier(String, Span),
+  Negate(Box<Expression>),
+  Binary(char, Box<Expression>, Box<Expression>),
+  Function(String, Box<Expression>, Span),
+}
+
+// Gate parameters are looked up by name while evaluating a gate definition
+type Parameters = HashMap<String, Complex64>;
+
+fn evaluate(expression: &Expression, parameters: &Parameters) -> Result<Complex64, ParseError> {
+  Ok(match expression {
+    Expression::Number(value) => *value,
+    Expression::Parameter(name, span) => *parameters
+      .get(name)
+      .ok_or_else(|| ParseError::new(format!("unknown parameter '%{}'", name), *span))?,
+    Expression::Identifier(name, span) => match name.as_str() {
+      "pi" => Complex64::new(PI, 0.0),
+      "i" => Complex64::new(0.0, 1.0),
+      _ => {
+        return Err(ParseError::new(
+          format!("unknown constant '{}'", name),
+          *span,
+        ))
+      }
+    },
+    Expression::Negate(inner) => -evaluate(inner, parameters)?,
+    Expression::Binary(op, left, right) => {
+      let left = evaluate(left, parameters)?;
+      let right = evaluate(right, parameters)?;
+      match op {
+        '+' => left + right,
+        '-' => left - right,
+        '*' => left * right,
+        '/' => left / right,
+        '^' => left.powc(right),
+        _ => unreachable!("unknown operator {}", op),
+      }
+    }
+    Expression::Function(name, argument, span) => {
+      let argument = evaluate(argument, parameters)?;
+      match name.as_str() {
+        "sin" => argument.sin(),
+        "cos" => argument.cos(),
+        "sqrt" => argument.sqrt(),
+        "exp" => argument.exp(),
+        "cis" => (Complex64::new(0.0, 1.0) * argument).exp(),
+        _ => {
+          return Err(ParseError::new(
+            format!("unknown function '{}'", name),
+            *span,
+          ))
+        }
+      }
+    }
+  })
+}
+
+fn real(value: Complex64, span: Span) -> Result<f64, ParseError> {
+  if value.im.abs() > 1e-12 {
+    return Err(ParseError::new(
+      format!("gate parameters must be real, found {}", value),
+      span,
+    ));
+  }
+  Ok(value.re)
+}
+
+// A cursor over the tokens of one line
+struct Tokens<'a> {
+  tokens: &'a [Token],
+  position: usize,
+  // Where the line ends, for errors about missing tokens
+  end: Span,
+}
+
+impl<'a> Tokens<'a> {
+  fn new(line: &'a Line) -> Tokens<'a> {
+    let last = line.tokens.last().map_or(line.span, |token| token.span);
+    Tokens {
+      tokens: &line.tokens,
+      position: 0,
+      end: Span {
+        line: last.line,
+        column: last.column + 1,
+      },
+    }
+  }
+
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.position)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.peek().cloned();
+    if token.is_some() {
+      self.position += 1;
+    }
+    token
+  }
+
+  fn at_end(&self) -> bool {
+    self.position == self.tokens.len()
+  }
+
+  fn error<T>(&self, expected: &str) -> Result<T, ParseError> {
+    let (found, span) = match self.peek() {
+      None => ("end of line".to_string(), self.end),
+      Some(token) => {
+        let found = match &token.kind {
+          TokenKind::Identifier(name) => format!("'{}'", name),
+          TokenKind::Integer(value) => format!("'{}'", value),
+          TokenKind::Real(value) => format!("'{}'", value),
+          TokenKind::Imaginary(value) => format!("'{}i'", value),
+          TokenKind::Parameter(name) => format!("'%{}'", name),
+          TokenKind::Label(name) => format!("'@{}'", name),
+          TokenKind::String(text) => format!("\"{}\"", text),
+          TokenKind::Symbol(symbol) => format!("'{}'", symbol),
+        };
+        (found, token.span)
+      }
+    };
+    Err(ParseError::new(
+      format!("expected {}, found {}", expected, found),
+      span,
+    ))
+  }
+
+  fn end(&self) -> Result<(), ParseError> {
+    if self.at_end() {
+      Ok(())
+    } else {
+      self.error("end of line")
+    }
+  }
+
+  fn is_symbol(&self, symbol: &str) -> bool {
+    matches!(self.peek(), Some(Token { kind: TokenKind::Symbol(found), .. }) if *found == symbol)
+  }
+
+  fn is_keyword(&self, keyword: &str) -> bool {
+    matches!(self.peek(), Some(Token { kind: TokenKind::Identifier(name), .. }) if name == keyword)
+  }
+
+  fn eat(&mut self, symbol: &str) -> bool {
+    let found = self.is_symbol(symbol);
+    if found {
+      self.next();
+    }
+    found
+  }
+
+  fn expect(&mut self, symbol: &str) -> Result<(), ParseError> {
+    if self.eat(symbol) {
+      Ok(())
+    } else {
+      self.error(&format!("'{}'", symbol))
+    }
+  }
+
+  fn identifier(&mut self) -> Result<(String, Span), ParseError> {
+    match self.peek().cloned() {
+      Some(Token {
+        kind: TokenKind::Identifier(name),
+        span,
+      }) => {
+        self.next();
+        Ok((name, span))
+      }
+      _ => self.error("an identifier"),
+    }
+  }
+
+  fn integer(&mut self) -> Result<(u64, Span), ParseError> {
+    match self.peek() {
+      Some(Token {
+        kind: TokenKind::Integer(value),
+        span,
+      }) => {
+        let (value, span) = (*value, *span);
+        self.next();
+        Ok((value, span))
+      }
+      _ => self.error("an integer"),
+    }
+  }
+
+  fn label(&mut self) -> Result<(String, Span), ParseError> {
+    match self.peek().cloned() {
+      Some(Token {
+        kind: TokenKind::Label(name),
+        span,
+      }) => {
+        self.next();
+        Ok((name, span))
+      }
+      _ => self.error("a label"),
+    }
+  }
+
+  // Expressions follow the usual precedence rules:
+  // + and - bind the loosest, then * and /, then unary minus, and ^ the tightest.
+  fn expression(&mut self) -> Result<Expression, ParseError> {
+    let mut left = self.term()?;
+    loop {
+      let op = if self.eat("+") {
+        '+'
+      } else if self.eat("-") {
+        '-'
+      } else {
+        return Ok(left);
+      };
+      left = Expression::Binary(op, Box::new(left), Box::new(self.term()?));
+    }
+  }
+
+  fn term(&mut self) -> Result<Expression, ParseError> {
+    let mut left = self.unary()?;
+    loop {
+      let op = if self.eat("*") {
+        '*'
+      } else if self.eat("/") {
+        '/'
+      } else {
+        return Ok(left);
+      };
+      left = Expression::Binary(op, Box::new(left), Box::new(self.unary()?));
+    }
+  }
+
+  fn unary(&mut self) -> Result<Expression, ParseError> {
+    if self.eat("-") {
+      Ok(Expression::Negate(Box::new(self.unary()?)))
+    } else {
+      self.power()
+    }
+  }
+
+  fn power(&mut self) -> Result<Expression, ParseError> {
+    let base = self.primary()?;
+    if self.eat("^") {
+      Ok(Expression::Binary(
+        '^',
+        Box::new(base),
+        Box::new(self.unary()?),
+      ))
+    } else {
+      Ok(base)
+    }
+  }
+
+  fn primary(&mut self) -> Result<Expression, ParseError> {
+    let token = match self.peek() {
+      Some(token) => token.clone(),
+      None => return self.error("an expression"),
+    };
+    match token.kind {
+      TokenKind::Integer(value) => {
+        self.next();
+        Ok(Expression::Number(Complex64::new(value as f64, 0.0)))
+      }
+      TokenKind::Real(value) => {
+        self.next();
+        Ok(Expression::Number(Complex64::new(value, 0.0)))
+      }
+      TokenKind::Imaginary(value) => {
+        self.next();
+        Ok(Expression::Number(Complex64::new(0.0, value)))
+      }
+      TokenKind::Parameter(name) => {
+        self.next();
+        Ok(Expression::Parameter(name, token.span))
+      }
+      TokenKind::Identifier(name) => {
+        self.next();
+        if self.eat("(") {
+          let argument = self.expression()?;
+          self.expect(")")?;
+          Ok(Expression::Function(name, Box::new(argument), token.span))
+        } else {
+          Ok(Expression::Identifier(name, token.span))
+        }
+      }
+      TokenKind::Symbol("(") => {
+        self.next();
+        let inner = self.expression()?;
+        self.expect(")")?;
+        Ok(inner)
+      }
+      _ => self.error("an expression"),
+    }
+  }
+}
+
+#[test]
+fn controlled_matrix_adds_a_control_in_front() {
+  let x = vec![
+    vec![COMPLEX_ZERO, COMPLEX_ONE],
+    vec![COMPLEX_ONE, COMPLEX_ZERO],
+  ];
+  let cnot = controlled_matrix(&x);
+  assert_eq!(cnot[0][0], COMPLEX_ONE);
+  assert_eq!(cnot[1][1], COMPLEX_ONE);
+  assert_eq!(cnot[2][3], COMPLEX_ONE);
+  assert_eq!(cnot[3][2], COMPLEX_ONE);
+  assert!(is_unitary(&cnot));
+  assert!(!is_unitary(&[
+    vec![COMPLEX_ONE, COMPLEX_ONE],
+    vec![COMPLEX_ZERO, COMPLEX_ONE]
+  ]));
+}
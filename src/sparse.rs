@@ -0,0 +1,597 @@
+use crate::backend::{Backend, SimulationError};
+use crate::channel::Channel;
+use crate::circuit::GateKind;
+use crate::gate::Gate;
+use crate::ket::{COMPLEX_ONE, COMPLEX_ZERO};
+use crate::state_vector::StateVector;
+use num_complex::Complex64;
+use rand::{Rng, RngCore};
+use std::collections::{BTreeMap, HashMap};
+
+/// A state vector that only stores the amplitudes that are not zero, keyed by the index of
+/// their basis state. Qubit 0 is the most significant bit of the index, as in `StateVector`.
+///
+/// Many circuits, like GHZ preparation or classical oracles, only ever reach a handful of
+/// basis states, however many qubits they have. Those fit in a sparse state vector even when
+/// a dense one would need more memory than there is, up to 128 qubits.
+///
+/// Amplitudes that are exactly zero are always dropped. Amplitudes smaller than `epsilon`
+/// in absolute value are pruned after every operation as well,
+/// so the rounding errors of gates that cancel out do not pile up as tiny amplitudes.
+/// The state is renormalized after pruning. When every amplitude is smaller than `epsilon`,
+/// nothing is pruned, since that would leave no state at all.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseStateVector {
+  num_qubits: usize,
+  epsilon: f64,
+  amplitudes: HashMap<u128, Complex64>,
+}
+
+impl SparseStateVector {
+  /// Creates a register of `num_qubits` qubits, all of them in the state |0>.
+  ///
+  /// Panics if there are more than 128 qubits.
+  pub fn new(num_qubits: usize, epsilon: f64) -> SparseStateVector {
+    assert!(
+      num_qubits <= 128,
+      "a sparse state vector can have at most 128 qubits"
+    );
+    let mut amplitudes = HashMap::new();
+    amplitudes.insert(0, COMPLEX_ONE);
+    SparseStateVector {
+      num_qubits,
+      epsilon,
+      amplitudes,
+    }
+  }
+
+  pub fn num_qubits(&self) -> usize {
+    self.num_qubits
+  }
+
+  pub fn epsilon(&self) -> f64 {
+    self.epsilon
+  }
+
+  /// The number of basis states with an amplitude that is not zero.
+  pub fn num_nonzero(&self) -> usize {
+    self.amplitudes.len()
+  }
+
+  /// The amplitude of a single basis state.
+  pub fn amplitude(&self, index: u128) -> Complex64 {
+    self.amplitudes.get(&index).copied().unwrap_or(COMPLEX_ZERO)
+  }
+
+  /// The amplitudes that are not zero, in the order of their basis states.
+  pub fn nonzero_amplitudes(&self) -> Vec<(u128, Complex64)> {
+    let mut amplitudes: Vec<(u128, Complex64)> =
+      self.amplitudes.iter().map(|(i, a)| (*i, *a)).collect();
+    amplitudes.sort_by_key(|(index, _)| *index);
+    amplitudes
+  }
+
+  /// The label of a basis state as a string of bits, qubit 0 first. For example "01".
+  pub fn bitstring(&self, index: u128) -> String {
+    format!("{:0width$b}", index, width = self.num_qubits)
+  }
+
+  /// All of the amplitudes, as a dense state vector.
+  ///
+  /// Panics if there are too many qubits to index the dense vector.
+  pub fn to_state_vector(&self) -> StateVector {
+    assert!(
+      self.num_qubits < usize::BITS as usize,
+      "too many qubits for a dense state vector"
+    );
+    let mut amplitudes = vec![COMPLEX_ZERO; 1 << self.num_qubits];
+    for (index, amplitude) in &self.amplitudes {
+      amplitudes[*index as usize] = *amplitude;
+    }
+    StateVector::from_amplitudes(amplitudes)
+  }
+
+  /// Applies a single-qubit gate to the target qubit.
+  pub fn apply(&mut self, gate: &Gate, target: usize) {
+    self.apply_controlled(gate, &[], target);
+  }
+
+  /// Applies a gate to the target qubit, when all of the control qubits are |1>.
+  pub fn apply_controlled(&mut self, gate: &Gate, controls: &[usize], target: usize) {
+    let controls = self.controls_mask(controls, &[target]);
+    let mask = self.qubit_mask(target);
+    let m = gate.matrix();
+    let mut next = HashMap::with_capacity(self.amplitudes.len() * 2);
+    for (index, amplitude) in self.amplitudes.drain() {
+      if index & controls != controls {
+        *next.entry(index).or_insert(COMPLEX_ZERO) += amplitude;
+        continue;
+      }
+      // The amplitude of |..b..> goes into |..0..> and |..1..>, by column b of the matrix
+      let bit = (index & mask != 0) as usize;
+      for (row, entries) in m.iter().enumerate() {
+        if entries[bit] != COMPLEX_ZERO {
+          let target = if row == 0 {
+            index & !mask
+          } else {
+            index | mask
+          };
+          *next.entry(target).or_insert(COMPLEX_ZERO) += entries[bit] * amplitude;
+        }
+      }
+    }
+    self.amplitudes = next;
+    self.prune();
+  }
+
+  /// Swaps qubits `a` and `b`, when all of the control qubits are |1>.
+  pub fn apply_controlled_swap(&mut self, controls: &[usize], a: usize, b: usize) {
+    let controls = self.controls_mask(controls, &[a, b]);
+    let both = self.qubit_mask(a) | self.qubit_mask(b);
+    self.permute(|index| {
+      let differ = (index & both).count_ones() == 1;
+      if index & controls == controls && differ {
+        (index ^ both, COMPLEX_ONE)
+      } else {
+        (index, COMPLEX_ONE)
+      }
+    });
+  }
+
+  /// Swaps the states of qubits `a` and `b`.
+  pub fn swap(&mut self, a: usize, b: usize) {
+    self.apply_controlled_swap(&[], a, b);
+  }
+
+  /// The iSWAP gate: swaps qubits `a` and `b`, multiplying |01> and |10> by i.
+  pub fn iswap(&mut self, a: usize, b: usize) {
+    self.controls_mask(&[], &[a, b]);
+    let both = self.qubit_mask(a) | self.qubit_mask(b);
+    self.permute(|index| {
+      if (index & both).count_ones() == 1 {
+        (index ^ both, Complex64::new(0.0, 1.0))
+      } else {
+        (index, COMPLEX_ONE)
+      }
+    });
+  }
+
+  /// Applies a unitary matrix on several qubits, with `qubits[0]` as the most significant bit.
+  pub fn apply_unitary(&mut self, matrix: &[Vec<Complex64>], qubits: &[usize]) {
+    assert!(
+      matrix.len() == 1 << qubits.len() && matrix.iter().all(|row| row.len() == matrix.len()),
+      "a unitary on {} qubits must be a {1}x{1} matrix",
+      qubits.len(),
+      1 << qubits.len()
+    );
+    self.controls_mask(&[], qubits);
+    let masks: Vec<u128> = qubits.iter().map(|qubit| self.qubit_mask(*qubit)).collect();
+    let all = masks.iter().fold(0, |all, mask| all | mask);
+    // The basis state with the given bits on the qubits of the matrix
+    let spread = |base: u128, bits: usize| {
+      masks.iter().enumerate().fold(base, |index, (k, mask)| {
+        if bits >> (qubits.len() - 1 - k) & 1 == 1 {
+          index | mask
+        } else {
+          index
+        }
+      })
+    };
+    let mut next = HashMap::with_capacity(self.amplitudes.len());
+    for (index, amplitude) in self.amplitudes.drain() {
+      let column = masks
+        .iter()
+        .fold(0, |bits, mask| (bits << 1) | (index & mask != 0) as usize);
+      for (row, entries) in matrix.iter().enumerate() {
+        if entries[column] != COMPLEX_ZERO {
+          *next
+            .entry(spread(index & !all, row))
+            .or_insert(COMPLEX_ZERO) += entries[column] * amplitude;
+        }
+      }
+    }
+    self.amplitudes = next;
+    self.prune();
+  }
+
+  /// The probability that measuring the qubit gives |1>.
+  pub fn probability_of_one(&self, qubit: usize) -> f64 {
+    let mask = self.qubit_mask(qubit);
+    let (zero, one) = self
+      .amplitudes
+      .iter()
+      .fold((0.0, 0.0), |(zero, one), (index, a)| {
+        if index & mask == 0 {
+          (zero + a.norm_sqr(), one)
+        } else {
+          (zero, one + a.norm_sqr())
+        }
+      });
+    one / (zero + one)
+  }
+
+  /// Measures a single qubit of the register in the computational basis.
+  /// Returns `false` for |0> and `true` for |1>, with Born rule probabilities,
+  /// and collapses the state to match.
+  pub fn measure<R: RngCore + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> bool {
+    let mask = self.qubit_mask(qubit);
+    let outcome = rng.gen::<f64>() < self.probability_of_one(qubit);
+    self
+      .amplitudes
+      .retain(|index, _| (index & mask != 0) == outcome);
+    self.normalize();
+    outcome
+  }
+
+  /// Applies a noise channel to the target qubit along a single trajectory, like
+  /// `StateVector::apply_channel`. Returns the index of the Kraus operator that was applied.
+  pub fn apply_channel<R: RngCore + ?Sized>(
+    &mut self,
+    channel: &Channel,
+    target: usize,
+    rng: &mut R,
+  ) -> usize {
+    let branches: Vec<SparseStateVector> = channel
+      .kraus_operators()
+      .iter()
+      .map(|operator| {
+        let mut branch = self.clone();
+        branch.apply(operator, target);
+        branch
+      })
+      .collect();
+    let norms: Vec<f64> = branches.iter().map(|branch| branch.norm_sqr()).collect();
+    let chosen = Channel::choose(&norms, rng);
+    *self = branches[chosen].clone();
+    self.normalize();
+    chosen
+  }
+
+  /// Measures every qubit `shots` times, without collapsing the state, and counts how many
+  /// times each basis state was observed, like `StateVector::sample`.
+  pub fn sample<R: RngCore + ?Sized>(&self, shots: usize, rng: &mut R) -> BTreeMap<String, usize> {
+    // Go through the basis states in order, so that a seeded generator gives the same samples
+    let amplitudes = self.nonzero_amplitudes();
+    let cumulative: Vec<f64> = amplitudes
+      .iter()
+      .scan(0.0, |sum, (_, a)| {
+        *sum += a.norm_sqr();
+        Some(*sum)
+      })
+      .collect();
+    let total = cumulative.last().copied().unwrap_or(0.0);
+    let mut counts = BTreeMap::new();
+    for _ in 0..shots {
+      let point = rng.gen::<f64>() * total;
+      let position = cumulative
+        .partition_point(|sum| *sum <= point)
+        .min(cumulative.len() - 1);
+      *counts
+        .entry(self.bitstring(amplitudes[position].0))
+        .or_insert(0) += 1;
+    }
+    counts
+  }
+
+  fn norm_sqr(&self) -> f64 {
+    self.amplitudes.values().map(|a| a.norm_sqr()).sum()
+  }
+
+  fn normalize(&mut self) {
+    let scale = 1.0 / self.norm_sqr().sqrt();
+    for amplitude in self.amplitudes.values_mut() {
+      *amplitude *= scale;
+    }
+  }
+
+  // Drops the amplitudes that are too small to matter, and renormalizes what is left
+  fn prune(&mut self) {
+    // Amplitudes that cancel out exactly go whatever epsilon is, even when it is 0
+    self
+      .amplitudes
+      .retain(|_, amplitude| *amplitude != COMPLEX_ZERO);
+    let epsilon = self.epsilon;
+    let before = self.amplitudes.len();
+    let kept = self
+      .amplitudes
+      .values()
+      .filter(|amplitude| amplitude.norm() >= epsilon)
+      .count();
+    if kept == before || kept == 0 {
+      return;
+    }
+    self
+      .amplitudes
+      .retain(|_, amplitude| amplitude.norm() >= epsilon);
+    self.normalize();
+  }
+
+  // Moves every amplitude to a new basis state, multiplied by a phase
+  fn permute<F: Fn(u128) -> (u128, Complex64)>(&mut self, f: F) {
+    self.amplitudes = self
+      .amplitudes
+      .drain()
+      .map(|(index, amplitude)| {
+        let (index, phase) = f(index);
+        (index, amplitude * phase)
+      })
+      .collect();
+  }
+
+  fn qubit_mask(&self, qubit: usize) -> u128 {
+    assert!(
+      qubit < self.num_qubits,
+      "qubit {} out of range for {} qubits",
+      qubit,
+      self.num_qubits
+    );
+    1 << (self.num_qubits - 1 - qubit)
+  }
+
+  // The mask of the control qubits, after checking that no qubit is used twice
+  fn controls_mask(&self, controls: &[usize], targets: &[usize]) -> u128 {
+    let mut used = 0;
+    for qubit in controls.iter().chain(targets) {
+      let mask = self.qubit_mask(*qubit);
+      assert!(
+        used & mask == 0,
+        "qubit {} used twice in the same gate",
+        qubit
+      );
+      used |= mask;
+    }
+    controls
+      .iter()
+      .fold(0, |mask, control| mask | self.qubit_mask(*control))
+  }
+}
+
+impl From<StateVector> for SparseStateVector {
+  fn from(state: StateVector) -> SparseStateVector {
+    let amplitudes = state
+      .amplitudes()
+      .iter()
+      .enumerate()
+      .filter(|(_, a)| **a != COMPLEX_ZERO)
+      .map(|(index, a)| (index as u128, *a))
+      .collect();
+    SparseStateVector {
+      num_qubits: state.num_qubits(),
+      epsilon: 0.0,
+      amplitudes,
+    }
+  }
+}
+
+impl Backend for SparseStateVector {
+  fn num_qubits(&self) -> usize {
+    self.num_qubits
+  }
+
+  fn apply_gate(
+    &mut self,
+    kind: &GateKind,
+    controls: &[usize],
+    target: usize,
+  ) -> Result<(), SimulationError> {
+    self.apply_controlled(&kind.gate(), controls, target);
+    Ok(())
+  }
+
+  fn apply_controlled_swap(
+    &mut self,
+    controls: &[usize],
+    a: usize,
+    b: usize,
+  ) -> Result<(), SimulationError> {
+    SparseStateVector::apply_controlled_swap(self, controls, a, b);
+    Ok(())
+  }
+
+  fn iswap(&mut self, a: usize, b: usize) -> Result<(), SimulationError> {
+    SparseStateVector::iswap(self, a, b);
+    Ok(())
+  }
+
+  fn apply_unitary(
+    &mut self,
+    matrix: &[Vec<Complex64>],
+    qubits: &[usize],
+  ) -> Result<(), SimulationError> {
+    SparseStateVector::apply_unitary(self, matrix, qubits);
+    Ok(())
+  }
+
+  fn measure<R: RngCore + ?Sized>(
+    &mut self,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<bool, SimulationError> {
+    Ok(SparseStateVector::measure(self, qubit, rng))
+  }
+
+  fn apply_channel<R: RngCore + ?Sized>(
+    &mut self,
+    channel: &Channel,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<(), SimulationError> {
+    SparseStateVector::apply_channel(self, channel, qubit, rng);
+    Ok(())
+  }
+}
+
+#[test]
+fn matches_dense_state_vector() {
+  use crate::circuit::{Circuit, Instruction};
+  use crate::noise::NoiseModel;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(31);
+  let mut circuit = Circuit::new(4, 0);
+  circuit
+    .h(0)
+    .cx(0, 2)
+    .ry(0.4, 3)
+    .ccx(3, 0, 1)
+    .u3(0.3, 1.1, -0.6, 1)
+    .cswap(2, 3, 0)
+    .iswap(3, 1)
+    .push(Instruction::Unitary {
+      matrix: vec![
+        vec![COMPLEX_ZERO, COMPLEX_ONE, COMPLEX_ZERO, COMPLEX_ZERO],
+        vec![
+          COMPLEX_ZERO,
+          COMPLEX_ZERO,
+          Complex64::new(0.0, 1.0),
+          COMPLEX_ZERO,
+        ],
+        vec![COMPLEX_ONE, COMPLEX_ZERO, COMPLEX_ZERO, COMPLEX_ZERO],
+        vec![COMPLEX_ZERO, COMPLEX_ZERO, COMPLEX_ZERO, -COMPLEX_ONE],
+      ],
+      qubits: vec![3, 0],
+    });
+  let expected = circuit.run(&mut rng).state;
+  let sparse = circuit
+    .run_on(
+      SparseStateVector::new(4, 1e-14),
+      &NoiseModel::new(),
+      &mut rng,
+    )
+    .unwrap()
+    .state;
+  for (a, b) in sparse
+    .to_state_vector()
+    .amplitudes()
+    .iter()
+    .zip(expected.amplitudes())
+  {
+    assert!((a - b).norm() < 1e-12);
+  }
+}
+
+#[test]
+fn wide_ghz_state() {
+  use crate::circuit::Circuit;
+  use crate::noise::NoiseModel;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(32);
+  let n = 100;
+  let mut circuit = Circuit::new(n, n);
+  circuit.h(0);
+  for qubit in 1..n {
+    circuit.cx(0, qubit);
+  }
+  let state = circuit
+    .run_on(
+      SparseStateVector::new(n, 1e-12),
+      &NoiseModel::new(),
+      &mut rng,
+    )
+    .unwrap()
+    .state;
+  assert_eq!(state.num_nonzero(), 2);
+  let ones = (1u128 << n) - 1;
+  assert!((state.amplitude(ones).re - 0.5f64.sqrt()).abs() < 1e-12);
+  let counts = state.sample(100, &mut rng);
+  assert_eq!(counts.len(), 2);
+  assert!((30..70).contains(&counts[&"1".repeat(n)]), "{:?}", counts);
+  circuit.measure_all();
+  let counts = circuit
+    .sample_on(
+      10,
+      &SparseStateVector::new(n, 1e-12),
+      &NoiseModel::new(),
+      &mut rng,
+    )
+    .unwrap();
+  assert!(counts
+    .keys()
+    .all(|bits| bits == &"0".repeat(n) || bits == &"1".repeat(n)));
+}
+
+#[test]
+fn pruning_drops_cancelled_amplitudes() {
+  use crate::gate::*;
+  use crate::ket::ValidQuantumState;
+  let mut state = SparseStateVector::new(3, 1e-12);
+  // Rounding errors leave amplitudes of around 1e-17 where a rotation and its inverse
+  // should give exactly zero
+  for qubit in 0..3 {
+    state.apply(&Gate::rx(0.3), qubit);
+    state.apply(&Gate::rx(-0.3), qubit);
+  }
+  assert_eq!(state.num_nonzero(), 1);
+  state.apply(&HADAMARD, 1);
+  state.apply_controlled(&PAULI_X, &[1], 2);
+  assert_eq!(
+    state
+      .nonzero_amplitudes()
+      .iter()
+      .map(|(i, _)| *i)
+      .collect::<Vec<_>>(),
+    vec![0b000, 0b011]
+  );
+  // With a large epsilon, small amplitudes disappear on purpose, and the rest is
+  // renormalized
+  let mut coarse = SparseStateVector::new(1, 0.1);
+  coarse.apply(&Gate::ry(0.1), 0);
+  assert_eq!(coarse.num_nonzero(), 1);
+  assert!((coarse.amplitude(0).norm() - 1.0).abs() < 1e-12);
+  assert!(coarse.probability_of_one(0).abs() < 1e-12);
+  assert!(coarse.to_state_vector().is_valid());
+}
+
+#[test]
+fn zero_amplitudes_are_not_stored() {
+  use crate::gate::*;
+  let mut flipped = SparseStateVector::new(3, 0.0);
+  flipped.apply(&PAULI_X, 0);
+  assert_eq!(flipped.num_nonzero(), 1);
+  // H twice cancels |1> exactly
+  flipped.apply(&HADAMARD, 2);
+  flipped.apply(&HADAMARD, 2);
+  assert_eq!(flipped.num_nonzero(), 1);
+  // A state converted from a dense one has an epsilon of 0 too
+  let n = 20;
+  let mut ghz = SparseStateVector::from(StateVector::new(n));
+  assert_eq!(ghz.epsilon(), 0.0);
+  ghz.apply(&HADAMARD, 0);
+  for qubit in 1..n {
+    ghz.apply_controlled(&PAULI_X, &[0], qubit);
+  }
+  assert_eq!(ghz.num_nonzero(), 2);
+}
+
+#[test]
+fn pruning_never_empties_the_state() {
+  use crate::gate::*;
+  use crate::ket::ValidQuantumState;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(37);
+  // Every amplitude is 0.5, below epsilon, so none of them are pruned
+  let mut state = SparseStateVector::new(2, 0.6);
+  state.apply(&HADAMARD, 0);
+  state.apply(&HADAMARD, 1);
+  assert_eq!(state.num_nonzero(), 4);
+  assert!(state.to_state_vector().is_valid());
+  let counts = state.sample(100, &mut rng);
+  assert_eq!(counts.values().sum::<usize>(), 100);
+  assert_eq!(counts.len(), 4);
+}
+
+#[test]
+fn measurement_collapses() {
+  use crate::gate::*;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(33);
+  let mut state = SparseStateVector::new(70, 1e-12);
+  state.apply(&HADAMARD, 10);
+  state.apply_controlled(&PAULI_X, &[10], 69);
+  assert!((state.probability_of_one(69) - 0.5).abs() < 1e-12);
+  let outcome = state.measure(69, &mut rng);
+  assert_eq!(state.num_nonzero(), 1);
+  assert_eq!(state.measure(10, &mut rng), outcome);
+  let mut noisy = SparseStateVector::new(2, 1e-12);
+  noisy.apply_channel(&Channel::bit_flip(1.0), 1, &mut rng);
+  assert_eq!(noisy.amplitude(0b01), COMPLEX_ONE);
+}
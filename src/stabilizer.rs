@@ -0,0 +1,585 @@
+use crate::backend::{Backend, SimulationError};
+use crate::channel::Channel;
+use crate::circuit::GateKind;
+use crate::gate::*;
+use num_complex::Complex64;
+use rand::{Rng, RngCore};
+use std::f64::consts::FRAC_PI_2;
+
+/// The state of a register of qubits that has only seen Clifford gates (H, S, CNOT and the
+/// gates made out of them), stored as a stabilizer tableau, following Aaronson and
+/// Gottesman, "Improved simulation of stabilizer circuits" (2004).
+///
+/// Such a state is the only state that a set of n Pauli operators, its stabilizers, all leave
+/// unchanged. Keeping track of the stabilizers takes about 2n² bits, instead of the 2^n
+/// amplitudes of a state vector, so circuits on hundreds or thousands of qubits are no
+/// problem. Gates take O(n) time and measurements O(n²).
+///
+/// Run circuits on it with `Circuit::run_on`. Gates that are not Clifford, like T, return a
+/// `SimulationError`, and so do noise channels that are not Pauli channels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StabilizerState {
+  num_qubits: usize,
+  // The number of u64 words in a row of the tableau
+  words: usize,
+  // Rows 0..n are the destabilizers, n..2n the stabilizers, and 2n is scratch space for
+  // measurements. Each row is a Pauli operator: qubit q is X if only its x bit is set, Z if
+  // only its z bit is set, and Y if both are. The bits of a row are packed into words.
+  x: Vec<u64>,
+  z: Vec<u64>,
+  // Whether the operator of each row has a minus sign
+  signs: Vec<bool>,
+}
+
+impl StabilizerState {
+  /// Creates a register of `num_qubits` qubits, all of them in the state |0>.
+  pub fn new(num_qubits: usize) -> StabilizerState {
+    let words = (num_qubits + 63) / 64;
+    let rows = 2 * num_qubits + 1;
+    let mut state = StabilizerState {
+      num_qubits,
+      words,
+      x: vec![0; rows * words],
+      z: vec![0; rows * words],
+      signs: vec![false; rows],
+    };
+    // |0...0> is stabilized by Z on every qubit, and destabilized by X
+    for qubit in 0..num_qubits {
+      let (word, bit) = state.position(qubit, qubit);
+      state.x[word] |= bit;
+      let (word, bit) = state.position(num_qubits + qubit, qubit);
+      state.z[word] |= bit;
+    }
+    state
+  }
+
+  pub fn num_qubits(&self) -> usize {
+    self.num_qubits
+  }
+
+  /// The stabilizers of the state as strings like "+XZ" or "-YI", one per qubit.
+  /// The first character is the sign, and then comes the Pauli operator on each qubit.
+  pub fn stabilizers(&self) -> Vec<String> {
+    (self.num_qubits..2 * self.num_qubits)
+      .map(|row| {
+        let sign = if self.signs[row] { '-' } else { '+' };
+        let paulis = (0..self.num_qubits).map(|qubit| {
+          match (self.x_bit(row, qubit), self.z_bit(row, qubit)) {
+            (false, false) => 'I',
+            (true, false) => 'X',
+            (true, true) => 'Y',
+            (false, true) => 'Z',
+          }
+        });
+        std::iter::once(sign).chain(paulis).collect()
+      })
+      .collect()
+  }
+
+  /// The Hadamard gate.
+  pub fn h(&mut self, qubit: usize) {
+    self.for_each_row(qubit, |x, z, sign| {
+      *sign ^= *x && *z;
+      std::mem::swap(x, z);
+    });
+  }
+
+  /// The phase gate S.
+  pub fn s(&mut self, qubit: usize) {
+    self.for_each_row(qubit, |x, z, sign| {
+      *sign ^= *x && *z;
+      *z ^= *x;
+    });
+  }
+
+  /// The inverse of the phase gate, S† = S³.
+  pub fn sdg(&mut self, qubit: usize) {
+    self.for_each_row(qubit, |x, z, sign| {
+      *sign ^= *x && !*z;
+      *z ^= *x;
+    });
+  }
+
+  /// The Pauli X gate. It flips the sign of every operator with a Z or Y on the qubit.
+  pub fn x(&mut self, qubit: usize) {
+    self.for_each_row(qubit, |_, z, sign| *sign ^= *z);
+  }
+
+  /// The Pauli Y gate.
+  pub fn y(&mut self, qubit: usize) {
+    self.for_each_row(qubit, |x, z, sign| *sign ^= *x ^ *z);
+  }
+
+  /// The Pauli Z gate.
+  pub fn z(&mut self, qubit: usize) {
+    self.for_each_row(qubit, |x, _, sign| *sign ^= *x);
+  }
+
+  /// The controlled NOT gate.
+  pub fn cx(&mut self, control: usize, target: usize) {
+    self.check_distinct(control, target);
+    for row in 0..2 * self.num_qubits {
+      let (xc, zc) = (self.x_bit(row, control), self.z_bit(row, control));
+      let (xt, zt) = (self.x_bit(row, target), self.z_bit(row, target));
+      self.signs[row] ^= xc && zt && (xt == zc);
+      self.set_x(row, target, xt ^ xc);
+      self.set_z(row, control, zc ^ zt);
+    }
+  }
+
+  /// The controlled Z gate.
+  pub fn cz(&mut self, a: usize, b: usize) {
+    self.h(b);
+    self.cx(a, b);
+    self.h(b);
+  }
+
+  /// The controlled Y gate.
+  pub fn cy(&mut self, control: usize, target: usize) {
+    self.sdg(target);
+    self.cx(control, target);
+    self.s(target);
+  }
+
+  /// Swaps the states of qubits `a` and `b`.
+  pub fn swap(&mut self, a: usize, b: usize) {
+    self.cx(a, b);
+    self.cx(b, a);
+    self.cx(a, b);
+  }
+
+  /// The iSWAP gate, up to a global phase.
+  pub fn iswap(&mut self, a: usize, b: usize) {
+    self.s(a);
+    self.s(b);
+    self.h(a);
+    self.cx(a, b);
+    self.cx(b, a);
+    self.h(b);
+  }
+
+  /// Measures a single qubit in the computational basis. Returns `false` for |0> and `true`
+  /// for |1>, and collapses the state to match.
+  pub fn measure<R: RngCore + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> bool {
+    let n = self.num_qubits;
+    self.check_qubit(qubit);
+    match (n..2 * n).find(|row| self.x_bit(*row, qubit)) {
+      // A stabilizer anticommutes with Z on the qubit, so the outcome is random. It is
+      // replaced with ±Z, and the other rows that anticommute are fixed up with it.
+      Some(p) => {
+        for row in 0..2 * n {
+          if row != p && self.x_bit(row, qubit) {
+            self.multiply_row(row, p);
+          }
+        }
+        self.copy_row(p, p - n);
+        self.clear_row(p);
+        let (word, bit) = self.position(p, qubit);
+        self.z[word] |= bit;
+        self.signs[p] = rng.gen::<bool>();
+        self.signs[p]
+      }
+      // Z on the qubit is already a product of stabilizers, and its sign is the outcome.
+      // The destabilizers tell which stabilizers to multiply together.
+      None => {
+        let scratch = 2 * n;
+        self.clear_row(scratch);
+        for row in 0..n {
+          if self.x_bit(row, qubit) {
+            self.multiply_row(scratch, row + n);
+          }
+        }
+        self.signs[scratch]
+      }
+    }
+  }
+
+  // Replaces row `h` with the product of rows `i` and `h`, keeping track of the sign.
+  // This is the "rowsum" of Aaronson and Gottesman, done a word at a time.
+  fn multiply_row(&mut self, h: usize, i: usize) {
+    // The product picks up a factor of i^k, with k counted modulo 4
+    let mut exponent = 2 * (self.signs[h] as i64 + self.signs[i] as i64);
+    for word in 0..self.words {
+      let (x1, z1) = (self.x[i * self.words + word], self.z[i * self.words + word]);
+      let (x2, z2) = (self.x[h * self.words + word], self.z[h * self.words + word]);
+      // Which qubits contribute a factor of i, and which -i, going by the cases of the
+      // function g in the paper: Y·Z = iX, Y·X = -iZ, X·Z = -iY, X·Y = iZ, Z·X = iY, Z·Y = -iX
+      let plus = (x1 & z1 & z2 & !x2) | (x1 & !z1 & z2 & x2) | (!x1 & z1 & x2 & !z2);
+      let minus = (x1 & z1 & x2 & !z2) | (x1 & !z1 & z2 & !x2) | (!x1 & z1 & x2 & z2);
+      exponent += plus.count_ones() as i64 - minus.count_ones() as i64;
+      self.x[h * self.words + word] ^= x1;
+      self.z[h * self.words + word] ^= z1;
+    }
+    self.signs[h] = exponent.rem_euclid(4) == 2;
+  }
+
+  fn copy_row(&mut self, from: usize, to: usize) {
+    let words = self.words;
+    self
+      .x
+      .copy_within(from * words..(from + 1) * words, to * words);
+    self
+      .z
+      .copy_within(from * words..(from + 1) * words, to * words);
+    self.signs[to] = self.signs[from];
+  }
+
+  fn clear_row(&mut self, row: usize) {
+    let words = self.words;
+    self.x[row * words..(row + 1) * words].fill(0);
+    self.z[row * words..(row + 1) * words].fill(0);
+    self.signs[row] = false;
+  }
+
+  // Runs a single-qubit update on the bits of every row, except the scratch row
+  fn for_each_row<F: FnMut(&mut bool, &mut bool, &mut bool)>(&mut self, qubit: usize, mut f: F) {
+    self.check_qubit(qubit);
+    for row in 0..2 * self.num_qubits {
+      let (mut x, mut z) = (self.x_bit(row, qubit), self.z_bit(row, qubit));
+      f(&mut x, &mut z, &mut self.signs[row]);
+      self.set_x(row, qubit, x);
+      self.set_z(row, qubit, z);
+    }
+  }
+
+  // The word and the bit within it for a qubit of a row
+  fn position(&self, row: usize, qubit: usize) -> (usize, u64) {
+    (row * self.words + qubit / 64, 1 << (qubit % 64))
+  }
+
+  fn x_bit(&self, row: usize, qubit: usize) -> bool {
+    let (word, bit) = self.position(row, qubit);
+    self.x[word] & bit != 0
+  }
+
+  fn z_bit(&self, row: usize, qubit: usize) -> bool {
+    let (word, bit) = self.position(row, qubit);
+    self.z[word] & bit != 0
+  }
+
+  fn set_x(&mut self, row: usize, qubit: usize, value: bool) {
+    let (word, bit) = self.position(row, qubit);
+    self.x[word] = (self.x[word] & !bit) | if value { bit } else { 0 };
+  }
+
+  fn set_z(&mut self, row: usize, qubit: usize, value: bool) {
+    let (word, bit) = self.position(row, qubit);
+    self.z[word] = (self.z[word] & !bit) | if value { bit } else { 0 };
+  }
+
+  fn check_qubit(&self, qubit: usize) {
+    assert!(
+      qubit < self.num_qubits,
+      "qubit {} out of range for {} qubits",
+      qubit,
+      self.num_qubits
+    );
+  }
+
+  fn check_distinct(&self, a: usize, b: usize) {
+    self.check_qubit(a);
+    self.check_qubit(b);
+    assert!(a != b, "qubit {} used twice in the same gate", a);
+  }
+
+  // Applies a rotation by `theta` around Z, if it is a multiple of a quarter turn.
+  // Up to a global phase, a quarter turn is the S gate.
+  fn quarter_turns(&mut self, theta: f64, name: &str, qubit: usize) -> Result<(), SimulationError> {
+    let turns = theta / FRAC_PI_2;
+    if (turns - turns.round()).abs() > 1e-9 {
+      return Err(not_clifford(&format!("{}({})", name, theta)));
+    }
+    for _ in 0..(turns.round() as i64).rem_euclid(4) {
+      self.s(qubit);
+    }
+    Ok(())
+  }
+}
+
+fn not_clifford(name: &str) -> SimulationError {
+  SimulationError::new(format!(
+    "the stabilizer backend only simulates Clifford gates, and '{}' is not one",
+    name
+  ))
+}
+
+// A Kraus operator that is a multiple of a Pauli operator, c P, as the Pauli and |c|²
+fn scaled_pauli(operator: &Gate) -> Option<(GateKind, f64)> {
+  let m = operator.matrix();
+  let paulis = [
+    (GateKind::I, IDENTITY),
+    (GateKind::X, PAULI_X),
+    (GateKind::Y, PAULI_Y),
+    (GateKind::Z, PAULI_Z),
+  ];
+  paulis.iter().find_map(|(kind, pauli)| {
+    let p = pauli.matrix();
+    // Paulis are their own inverses and have trace 2 when squared, so c = Tr(P K) / 2
+    let c: Complex64 = (0..2)
+      .flat_map(|i| (0..2).map(move |j| (i, j)))
+      .map(|(i, j)| p[i][j] * m[j][i])
+      .sum::<Complex64>()
+      / 2.0;
+    let matches = (0..2)
+      .flat_map(|i| (0..2).map(move |j| (i, j)))
+      .all(|(i, j)| (m[i][j] - c * p[i][j]).norm() < 1e-12);
+    if matches {
+      Some((*kind, c.norm_sqr()))
+    } else {
+      None
+    }
+  })
+}
+
+impl Backend for StabilizerState {
+  fn num_qubits(&self) -> usize {
+    self.num_qubits
+  }
+
+  fn apply_gate(
+    &mut self,
+    kind: &GateKind,
+    controls: &[usize],
+    target: usize,
+  ) -> Result<(), SimulationError> {
+    match (controls, kind) {
+      (_, GateKind::I) => {}
+      ([], GateKind::X) => self.x(target),
+      ([], GateKind::Y) => self.y(target),
+      ([], GateKind::Z) => self.z(target),
+      ([], GateKind::H) => self.h(target),
+      ([], GateKind::S) => self.s(target),
+      ([], GateKind::Sdg) => self.sdg(target),
+      ([], GateKind::Rz(theta)) | ([], GateKind::Phase(theta)) => {
+        self.quarter_turns(*theta, kind.name(), target)?
+      }
+      // Rx(θ) = H Rz(θ) H
+      ([], GateKind::Rx(theta)) => {
+        self.h(target);
+        self.quarter_turns(*theta, "rx", target)?;
+        self.h(target);
+      }
+      // Ry(θ) = S Rx(θ) S†
+      ([], GateKind::Ry(theta)) => {
+        self.sdg(target);
+        self.h(target);
+        self.quarter_turns(*theta, "ry", target)?;
+        self.h(target);
+        self.s(target);
+      }
+      ([control], GateKind::X) => self.cx(*control, target),
+      ([control], GateKind::Y) => self.cy(*control, target),
+      ([control], GateKind::Z) => self.cz(*control, target),
+      _ => {
+        return Err(not_clifford(&format!(
+          "{}{}",
+          "c".repeat(controls.len()),
+          kind.name()
+        )))
+      }
+    }
+    Ok(())
+  }
+
+  fn apply_controlled_swap(
+    &mut self,
+    controls: &[usize],
+    a: usize,
+    b: usize,
+  ) -> Result<(), SimulationError> {
+    if !controls.is_empty() {
+      return Err(not_clifford(&format!("{}swap", "c".repeat(controls.len()))));
+    }
+    self.swap(a, b);
+    Ok(())
+  }
+
+  fn iswap(&mut self, a: usize, b: usize) -> Result<(), SimulationError> {
+    StabilizerState::iswap(self, a, b);
+    Ok(())
+  }
+
+  fn apply_unitary(
+    &mut self,
+    _matrix: &[Vec<Complex64>],
+    _qubits: &[usize],
+  ) -> Result<(), SimulationError> {
+    Err(SimulationError::new(
+      "the stabilizer backend can not simulate arbitrary unitaries",
+    ))
+  }
+
+  fn measure<R: RngCore + ?Sized>(
+    &mut self,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<bool, SimulationError> {
+    Ok(StabilizerState::measure(self, qubit, rng))
+  }
+
+  // A Pauli channel applies one of the Paulis at random, with a fixed probability for each,
+  // which keeps the state a stabilizer state
+  fn apply_channel<R: RngCore + ?Sized>(
+    &mut self,
+    channel: &Channel,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<(), SimulationError> {
+    let paulis = channel
+      .kraus_operators()
+      .iter()
+      .map(scaled_pauli)
+      .collect::<Option<Vec<(GateKind, f64)>>>()
+      .ok_or_else(|| {
+        SimulationError::new("the stabilizer backend can only simulate Pauli channels")
+      })?;
+    let probabilities: Vec<f64> = paulis.iter().map(|(_, p)| *p).collect();
+    let (kind, _) = paulis[Channel::choose(&probabilities, rng)];
+    self.apply_gate(&kind, &[], qubit)
+  }
+}
+
+#[test]
+fn bell_state_stabilizers() {
+  let mut state = StabilizerState::new(2);
+  assert_eq!(state.stabilizers(), vec!["+ZI", "+IZ"]);
+  state.h(0);
+  state.cx(0, 1);
+  assert_eq!(state.stabilizers(), vec!["+XX", "+ZZ"]);
+  state.y(0);
+  assert_eq!(state.stabilizers(), vec!["-XX", "-ZZ"]);
+  state.s(1);
+  state.sdg(1);
+  state.h(1);
+  state.h(1);
+  assert_eq!(state.stabilizers(), vec!["-XX", "-ZZ"]);
+}
+
+#[test]
+fn measurements_follow_the_stabilizers() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(23);
+  let mut ones = 0;
+  for _ in 0..200 {
+    // |Φ+> with the second qubit flipped: the two outcomes always differ
+    let mut state = StabilizerState::new(2);
+    state.h(0);
+    state.cx(0, 1);
+    state.x(1);
+    let first = state.measure(0, &mut rng);
+    assert_ne!(state.measure(1, &mut rng), first);
+    // Measuring again gives the same outcome
+    assert_eq!(state.measure(0, &mut rng), first);
+    ones += first as usize;
+  }
+  assert!((70..130).contains(&ones), "{}", ones);
+  // H S S H = X, with no randomness
+  let mut state = StabilizerState::new(1);
+  state.h(0);
+  state.s(0);
+  state.s(0);
+  state.h(0);
+  assert!(state.measure(0, &mut rng));
+}
+
+#[test]
+fn matches_state_vector_on_clifford_circuits() {
+  use crate::circuit::Circuit;
+  use crate::noise::NoiseModel;
+  use rand::{Rng, SeedableRng};
+  let mut rng = rand::rngs::StdRng::seed_from_u64(24);
+  for _ in 0..20 {
+    let mut circuit = Circuit::new(4, 4);
+    for _ in 0..15 {
+      let (a, b) = (rng.gen_range(0..4), rng.gen_range(1..4));
+      let b = (a + b) % 4;
+      match rng.gen_range(0..9) {
+        0 => circuit.h(a),
+        1 => circuit.s(a),
+        2 => circuit.sdg(a),
+        3 => circuit.y(a),
+        4 => circuit.cx(a, b),
+        5 => circuit.cz(a, b),
+        6 => circuit.swap(a, b),
+        7 => circuit.iswap(a, b),
+        _ => circuit.ry(FRAC_PI_2 * rng.gen_range(-3..4) as f64, a),
+      };
+    }
+    let support: Vec<String> = {
+      let state = circuit.run(&mut rng).state;
+      (0..16)
+        .filter(|index| state.probabilities()[*index] > 1e-9)
+        .map(|index| state.bitstring(index))
+        .collect()
+    };
+    circuit.measure_all();
+    let counts = circuit
+      .sample_on(400, &StabilizerState::new(4), &NoiseModel::new(), &mut rng)
+      .unwrap();
+    let seen: Vec<String> = counts.keys().cloned().collect();
+    assert_eq!(seen, support, "{:?}", circuit);
+    // Stabilizer states are uniform over their support
+    let expected = 400.0 / support.len() as f64;
+    for count in counts.values() {
+      assert!((*count as f64 - expected).abs() < expected * 0.5 + 10.0);
+    }
+  }
+}
+
+#[test]
+fn hundreds_of_qubits() {
+  use crate::circuit::Circuit;
+  use crate::noise::NoiseModel;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(25);
+  let n = 300;
+  let mut circuit = Circuit::new(n, n);
+  circuit.h(0);
+  for qubit in 1..n {
+    circuit.cx(qubit - 1, qubit);
+  }
+  circuit.measure_all();
+  let counts = circuit
+    .sample_on(20, &StabilizerState::new(n), &NoiseModel::new(), &mut rng)
+    .unwrap();
+  for bitstring in counts.keys() {
+    assert!(bitstring == &"0".repeat(n) || bitstring == &"1".repeat(n));
+  }
+  assert_eq!(counts.len(), 2);
+}
+
+#[test]
+fn non_clifford_operations_are_errors() {
+  use crate::circuit::Circuit;
+  use crate::noise::NoiseModel;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(26);
+  let run = |circuit: &Circuit, noise: &NoiseModel, rng: &mut rand::rngs::StdRng| {
+    circuit
+      .run_on(StabilizerState::new(3), noise, rng)
+      .map(|result| result.classical_bitstring())
+  };
+  let ideal = NoiseModel::new();
+  let mut circuit = Circuit::new(3, 0);
+  circuit.h(0).t(0);
+  let error = run(&circuit, &ideal, &mut rng).unwrap_err();
+  assert!(error.message.contains("'t' is not one"), "{}", error);
+  let mut circuit = Circuit::new(3, 0);
+  circuit.ccx(0, 1, 2);
+  assert!(run(&circuit, &ideal, &mut rng).is_err());
+  let mut circuit = Circuit::new(3, 0);
+  circuit.rz(0.3, 1);
+  let error = run(&circuit, &ideal, &mut rng).unwrap_err();
+  assert!(error.message.contains("'rz(0.3)'"), "{}", error);
+  // Quarter turns are fine
+  let mut circuit = Circuit::new(3, 1);
+  circuit.rx(std::f64::consts::PI, 2).measure(2, 0);
+  assert_eq!(run(&circuit, &ideal, &mut rng).unwrap(), "1");
+  // Pauli noise can be simulated, but not amplitude damping
+  let mut noise = NoiseModel::new();
+  noise.add_gate_error("rx", Channel::bit_flip(1.0));
+  assert_eq!(run(&circuit, &noise, &mut rng).unwrap(), "0");
+  let mut noise = NoiseModel::new();
+  noise.add_gate_error("rx", Channel::amplitude_damping(0.1));
+  assert!(run(&circuit, &noise, &mut rng).is_err());
+}
@@ -0,0 +1,955 @@
+use crate::approx::ComplexMargin;
+use crate::backend::{Backend, SimulationError};
+use crate::basis::Basis;
+use crate::channel::Channel;
+use crate::circuit::GateKind;
+use crate::gate::Gate;
+use crate::ket::*;
+use float_cmp::{approx_eq, ApproxEq};
+use num_complex::Complex64;
+use rand::{Rng, RngCore};
+use std::collections::BTreeMap;
+
+/// A state vector describes a register of `n` qubits.
+/// It has 2^n components, one complex amplitude for each basis state
+/// |00..0>, |00..1>, ..., |11..1>.
+/// A single `Ket` is the special case of a one-qubit state vector.
+///
+/// Qubits are ordered big-endian: qubit 0 is the leftmost symbol in |q0 q1 .. qn-1>
+/// and the most significant bit of the basis index. So in |01>, at index 0b01,
+/// qubit 0 is |0> and qubit 1 is |1>.
+#[derive(Debug, Clone)]
+pub struct StateVector {
+  amplitudes: Vec<Complex64>,
+}
+
+impl StateVector {
+  /// Creates a register of `num_qubits` qubits, all of them in the state |0>.
+  pub fn new(num_qubits: usize) -> StateVector {
+    StateVector::from_basis_index(num_qubits, 0)
+  }
+
+  /// Creates a register of `num_qubits` qubits in the computational basis state
+  /// with the given index. For example, index 0b01 on two qubits is |01>.
+  ///
+  /// Panics if the index does not fit in the register.
+  pub fn from_basis_index(num_qubits: usize, index: usize) -> StateVector {
+    let dimension = 1 << num_qubits;
+    assert!(
+      index < dimension,
+      "basis index {} out of range for {} qubits",
+      index,
+      num_qubits
+    );
+    let mut amplitudes = vec![COMPLEX_ZERO; dimension];
+    amplitudes[index] = COMPLEX_ONE;
+    StateVector { amplitudes }
+  }
+
+  /// Creates a state vector from raw amplitudes.
+  ///
+  /// Panics if the number of amplitudes is not a power of two.
+  pub fn from_amplitudes(amplitudes: Vec<Complex64>) -> StateVector {
+    assert!(
+      amplitudes.len().is_power_of_two(),
+      "a state vector needs a power of two amplitudes, got {}",
+      amplitudes.len()
+    );
+    StateVector { amplitudes }
+  }
+
+  /// The number of qubits in the register.
+  pub fn num_qubits(&self) -> usize {
+    self.amplitudes.len().trailing_zeros() as usize
+  }
+
+  /// Creates a register from a list of single-qubit kets, by taking their tensor product.
+  /// The first ket becomes qubit 0.
+  ///
+  /// Panics if the list is empty.
+  pub fn from_kets(kets: &[Ket]) -> StateVector {
+    let (first, rest) = kets
+      .split_first()
+      .expect("cannot create a register from zero kets");
+    rest
+      .iter()
+      .fold(StateVector::from(*first), |state, ket| state.tensor(*ket))
+  }
+
+  /// The tensor (Kronecker) product of this register with another, |self> ⊗ |other>.
+  /// The qubits of `self` come first, so the qubits of `other` are renumbered to start
+  /// from `self.num_qubits()`.
+  pub fn tensor<T: Into<StateVector>>(&self, other: T) -> StateVector {
+    let other = other.into();
+    let mut amplitudes = Vec::with_capacity(self.amplitudes.len() * other.amplitudes.len());
+    for a in &self.amplitudes {
+      for b in &other.amplitudes {
+        amplitudes.push(a * b);
+      }
+    }
+    StateVector { amplitudes }
+  }
+
+  /// Applies a single-qubit gate to the `target` qubit of the register.
+  ///
+  /// Panics if the target is not in the register.
+  pub fn apply(&mut self, gate: &Gate, target: usize) {
+    self.apply_controlled(gate, &[], target);
+  }
+
+  /// Applies a single-qubit gate to the `target` qubit, but only on the part of the state
+  /// where every one of the `controls` qubits is |1>. With one control and `PAULI_X` this is
+  /// the CNOT gate, with two controls the Toffoli gate, and so on.
+  ///
+  /// The gate is applied in place, without ever building the full 2^n × 2^n matrix.
+  ///
+  /// Panics if the qubits are not in the register, or if the same qubit is used twice.
+  pub fn apply_controlled(&mut self, gate: &Gate, controls: &[usize], target: usize) {
+    let control_mask = self.controls_mask(controls, &[target]);
+    let mask = self.qubit_mask(target);
+    let m = &gate.matrix;
+    // Pair up each basis state where the target is |0> with the one where it is |1>,
+    // and treat the pair of amplitudes as a ket
+    for zero in 0..self.amplitudes.len() {
+      if zero & mask != 0 || zero & control_mask != control_mask {
+        continue;
+      }
+      let one = zero | mask;
+      let (a, b) = (self.amplitudes[zero], self.amplitudes[one]);
+      self.amplitudes[zero] = m[0][0] * a + m[0][1] * b;
+      self.amplitudes[one] = m[1][0] * a + m[1][1] * b;
+    }
+  }
+
+  /// Swaps the states of qubits `a` and `b`, on the part of the state where every one of the
+  /// `controls` qubits is |1>. With no controls this is the SWAP gate, with one the Fredkin gate.
+  pub fn apply_controlled_swap(&mut self, controls: &[usize], a: usize, b: usize) {
+    let control_mask = self.controls_mask(controls, &[a, b]);
+    let (mask_a, mask_b) = (self.qubit_mask(a), self.qubit_mask(b));
+    // Only |..0..1..> and |..1..0..> change, by trading places
+    for index in 0..self.amplitudes.len() {
+      if index & mask_a != 0 && index & mask_b == 0 && index & control_mask == control_mask {
+        self.amplitudes.swap(index, index ^ mask_a ^ mask_b);
+      }
+    }
+  }
+
+  /// Swaps the states of qubits `a` and `b`.
+  pub fn swap(&mut self, a: usize, b: usize) {
+    self.apply_controlled_swap(&[], a, b);
+  }
+
+  /// The iSWAP gate: swaps qubits `a` and `b`, multiplying |01> and |10> by i as they trade places.
+  pub fn iswap(&mut self, a: usize, b: usize) {
+    let control_mask = self.controls_mask(&[], &[a, b]);
+    let (mask_a, mask_b) = (self.qubit_mask(a), self.qubit_mask(b));
+    let i = Complex64::new(0.0, 1.0);
+    for index in 0..self.amplitudes.len() {
+      if index & mask_a != 0 && index & mask_b == 0 && index & control_mask == control_mask {
+        let other = index ^ mask_a ^ mask_b;
+        let (x, y) = (self.amplitudes[index], self.amplitudes[other]);
+        self.amplitudes[index] = i * y;
+        self.amplitudes[other] = i * x;
+      }
+    }
+  }
+
+  /// Applies a unitary matrix on several qubits. The matrix has a row and a column for each
+  /// basis state of `qubits`, with `qubits[0]` as the most significant bit, just like the
+  /// state vector itself.
+  pub fn apply_unitary(&mut self, matrix: &[Vec<Complex64>], qubits: &[usize]) {
+    assert_eq!(
+      matrix.len(),
+      1 << qubits.len(),
+      "matrix size does not match the number of qubits"
+    );
+    self.controls_mask(&[], qubits);
+    // The offset of each basis state of `qubits` within the whole register
+    let offsets: Vec<usize> = (0..matrix.len())
+      .map(|row| {
+        qubits
+          .iter()
+          .enumerate()
+          .filter(|(i, _)| row & (1 << (qubits.len() - 1 - i)) != 0)
+          .map(|(_, qubit)| self.qubit_mask(*qubit))
+          .sum()
+      })
+      .collect();
+    let mask: usize = offsets.last().copied().unwrap_or(0);
+    let mut amplitudes = vec![COMPLEX_ZERO; matrix.len()];
+    for base in 0..self.amplitudes.len() {
+      if base & mask != 0 {
+        continue;
+      }
+      for (amplitude, offset) in amplitudes.iter_mut().zip(&offsets) {
+        *amplitude = self.amplitudes[base + offset];
+      }
+      for (row, offset) in matrix.iter().zip(&offsets) {
+        self.amplitudes[base + offset] = row.iter().zip(&amplitudes).map(|(m, a)| m * a).sum();
+      }
+    }
+  }
+
+  // The bits of the basis index that must all be set for a controlled gate to act.
+  // Also checks that no qubit is used twice.
+  fn controls_mask(&self, controls: &[usize], targets: &[usize]) -> usize {
+    let mut mask = 0;
+    for qubit in controls.iter().chain(targets) {
+      let bit = self.qubit_mask(*qubit);
+      assert!(
+        mask & bit == 0,
+        "qubit {} used twice in the same gate",
+        qubit
+      );
+      mask |= bit;
+    }
+    for target in targets {
+      mask &= !self.qubit_mask(*target);
+    }
+    mask
+  }
+
+  /// Measures a single qubit of the register in the computational basis.
+  /// Returns `false` for |0> and `true` for |1>, with Born rule probabilities.
+  /// The rest of the register collapses to the part consistent with the outcome.
+  pub fn measure<R: RngCore + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> bool {
+    let mask = self.qubit_mask(qubit);
+    let mut zero = 0.0;
+    let mut one = 0.0;
+    for (index, amplitude) in self.amplitudes.iter().enumerate() {
+      if index & mask == 0 {
+        zero += amplitude.norm_sqr();
+      } else {
+        one += amplitude.norm_sqr();
+      }
+    }
+    let outcome = rng.gen::<f64>() * (zero + one) < one;
+    let scale = 1.0 / if outcome { one } else { zero }.sqrt();
+    for (index, amplitude) in self.amplitudes.iter_mut().enumerate() {
+      if (index & mask != 0) == outcome {
+        *amplitude *= scale;
+      } else {
+        *amplitude = COMPLEX_ZERO;
+      }
+    }
+    outcome
+  }
+
+  /// The probability of each outcome of measuring one qubit in the basis, and the state the
+  /// register collapses to. When an outcome is impossible, its state is all zeros, like in
+  /// `Ket::measurement_outcomes`.
+  pub fn measurement_outcomes(&self, qubit: usize, basis: &Basis) -> [(f64, StateVector); 2] {
+    let mask = self.qubit_mask(qubit);
+    // Rotate the basis onto |0> and |1>, project, and rotate back
+    let mut rotated = self.clone();
+    rotated.apply(&basis.from_computational().dagger(), qubit);
+    let total: f64 = rotated.amplitudes.iter().map(|a| a.norm_sqr()).sum();
+    let outcome = |one: bool| {
+      let mut state = rotated.clone();
+      for (index, amplitude) in state.amplitudes.iter_mut().enumerate() {
+        if (index & mask != 0) != one {
+          *amplitude = COMPLEX_ZERO;
+        }
+      }
+      let probability: f64 = state.amplitudes.iter().map(|a| a.norm_sqr()).sum();
+      if probability > 0.0 {
+        state = state * Complex64::from(1.0 / probability.sqrt());
+      }
+      state.apply(&basis.from_computational(), qubit);
+      (probability / total, state)
+    };
+    [outcome(false), outcome(true)]
+  }
+
+  /// Measures a single qubit in any orthonormal basis, like `Basis::X`. Returns `false` for
+  /// the first basis vector and `true` for the second, and collapses the register to match.
+  /// In `Basis::Z` this is the same as `measure`.
+  pub fn measure_in<R: RngCore + ?Sized>(
+    &mut self,
+    qubit: usize,
+    basis: &Basis,
+    rng: &mut R,
+  ) -> bool {
+    let rotation = basis.from_computational();
+    self.apply(&rotation.dagger(), qubit);
+    let outcome = self.measure(qubit, rng);
+    self.apply(&rotation, qubit);
+    outcome
+  }
+
+  /// Applies a noise channel to the target qubit along a single trajectory: one of the Kraus
+  /// operators K_i is picked with probability ‖K_i ψ‖², applied, and the state renormalized.
+  /// Returns the index of the Kraus operator that was applied.
+  pub fn apply_channel<R: RngCore + ?Sized>(
+    &mut self,
+    channel: &Channel,
+    target: usize,
+    rng: &mut R,
+  ) -> usize {
+    let branches: Vec<StateVector> = channel
+      .kraus_operators()
+      .iter()
+      .map(|operator| {
+        let mut branch = self.clone();
+        branch.apply(operator, target);
+        branch
+      })
+      .collect();
+    let norms: Vec<f64> = branches
+      .iter()
+      .map(|branch| branch.probabilities().iter().sum())
+      .collect();
+    let chosen = Channel::choose(&norms, rng);
+    let scale = 1.0 / norms[chosen].sqrt();
+    self.amplitudes = branches[chosen]
+      .amplitudes
+      .iter()
+      .map(|amplitude| amplitude * scale)
+      .collect();
+    chosen
+  }
+
+  /// Measures every qubit of the register, returning the index of the observed basis state.
+  /// The register collapses to that basis state, keeping the phase of its amplitude.
+  pub fn measure_all<R: RngCore + ?Sized>(&mut self, rng: &mut R) -> usize {
+    let total: f64 = self.amplitudes.iter().map(|a| a.norm_sqr()).sum();
+    let mut remaining = rng.gen::<f64>() * total;
+    // Walk through the basis states until the random number runs out. Rounding can leave a
+    // little bit over at the end, so fall back to the last state that could have been observed.
+    let mut observed = 0;
+    for (index, amplitude) in self.amplitudes.iter().enumerate() {
+      let probability = amplitude.norm_sqr();
+      if probability > 0.0 {
+        observed = index;
+        if remaining < probability {
+          break;
+        }
+        remaining -= probability;
+      }
+    }
+    let amplitude = self.amplitudes[observed];
+    for a in self.amplitudes.iter_mut() {
+      *a = COMPLEX_ZERO;
+    }
+    self.amplitudes[observed] = amplitude / amplitude.norm();
+    observed
+  }
+
+  /// The probability of observing each basis state when measuring every qubit.
+  pub fn probabilities(&self) -> Vec<f64> {
+    self.amplitudes.iter().map(|a| a.norm_sqr()).collect()
+  }
+
+  /// The label of a basis state as a string of bits, qubit 0 first. For example "01".
+  pub fn bitstring(&self, index: usize) -> String {
+    format!("{:0width$b}", index, width = self.num_qubits())
+  }
+
+  /// Measures every qubit `shots` times, without collapsing the state, and counts how many
+  /// times each basis state was observed. The keys of the histogram are bitstrings, qubit 0 first.
+  ///
+  /// The probabilities are summed up once, and then every shot is a binary search
+  /// into the cumulative distribution.
+  pub fn sample<R: RngCore + ?Sized>(&self, shots: usize, rng: &mut R) -> BTreeMap<String, usize> {
+    let cumulative: Vec<f64> = self
+      .amplitudes
+      .iter()
+      .scan(0.0, |sum, a| {
+        *sum += a.norm_sqr();
+        Some(*sum)
+      })
+      .collect();
+    let total = cumulative.last().copied().unwrap_or(0.0);
+    let mut counts = BTreeMap::new();
+    for _ in 0..shots {
+      let point = rng.gen::<f64>() * total;
+      let index = cumulative
+        .partition_point(|sum| *sum <= point)
+        .min(cumulative.len() - 1);
+      *counts.entry(self.bitstring(index)).or_insert(0) += 1;
+    }
+    counts
+  }
+
+  /// The bit of the basis index that corresponds to the given qubit.
+  pub(crate) fn qubit_mask(&self, qubit: usize) -> usize {
+    let num_qubits = self.num_qubits();
+    assert!(
+      qubit < num_qubits,
+      "qubit {} out of range for {} qubits",
+      qubit,
+      num_qubits
+    );
+    1 << (num_qubits - 1 - qubit)
+  }
+
+  /// All of the amplitudes, indexed by basis state.
+  pub fn amplitudes(&self) -> &[Complex64] {
+    &self.amplitudes
+  }
+
+  /// The amplitude of a single basis state.
+  pub fn amplitude(&self, index: usize) -> Complex64 {
+    self.amplitudes[index]
+  }
+
+  /// The inner product <self|other>.
+  ///
+  /// Panics if the registers have different numbers of qubits.
+  pub fn inner(&self, other: &StateVector) -> Complex64 {
+    assert_eq!(
+      self.amplitudes.len(),
+      other.amplitudes.len(),
+      "cannot take the inner product of state vectors of different sizes"
+    );
+    self
+      .amplitudes
+      .iter()
+      .zip(&other.amplitudes)
+      .map(|(a, b)| a.conj() * b)
+      .sum()
+  }
+
+  /// The length of the state vector, sqrt(<ψ|ψ>). It is 1 for every valid state.
+  pub fn norm(&self) -> f64 {
+    self.inner(self).re.sqrt()
+  }
+
+  /// The fidelity |<self|other>|² between two pure states, like `Ket::fidelity`.
+  pub fn fidelity(&self, other: &StateVector) -> f64 {
+    self.inner(other).norm_sqr() / (self.inner(self).re * other.inner(other).re)
+  }
+
+  /// The trace distance sqrt(1 - F) between two pure states, like `Ket::trace_distance`.
+  pub fn trace_distance(&self, other: &StateVector) -> f64 {
+    (1.0 - self.fidelity(other)).max(0.0).sqrt()
+  }
+}
+
+// A state vector runs circuits directly, and noise channels along a single trajectory
+impl Backend for StateVector {
+  fn num_qubits(&self) -> usize {
+    StateVector::num_qubits(self)
+  }
+
+  fn apply_gate(
+    &mut self,
+    kind: &GateKind,
+    controls: &[usize],
+    target: usize,
+  ) -> Result<(), SimulationError> {
+    self.apply_controlled(&kind.gate(), controls, target);
+    Ok(())
+  }
+
+  fn apply_controlled_swap(
+    &mut self,
+    controls: &[usize],
+    a: usize,
+    b: usize,
+  ) -> Result<(), SimulationError> {
+    StateVector::apply_controlled_swap(self, controls, a, b);
+    Ok(())
+  }
+
+  fn iswap(&mut self, a: usize, b: usize) -> Result<(), SimulationError> {
+    StateVector::iswap(self, a, b);
+    Ok(())
+  }
+
+  fn apply_unitary(
+    &mut self,
+    matrix: &[Vec<Complex64>],
+    qubits: &[usize],
+  ) -> Result<(), SimulationError> {
+    StateVector::apply_unitary(self, matrix, qubits);
+    Ok(())
+  }
+
+  fn measure<R: RngCore + ?Sized>(
+    &mut self,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<bool, SimulationError> {
+    Ok(StateVector::measure(self, qubit, rng))
+  }
+
+  fn apply_channel<R: RngCore + ?Sized>(
+    &mut self,
+    channel: &Channel,
+    qubit: usize,
+    rng: &mut R,
+  ) -> Result<(), SimulationError> {
+    StateVector::apply_channel(self, channel, qubit, rng);
+    Ok(())
+  }
+}
+
+// A single Ket is a one-qubit register
+impl From<Ket> for StateVector {
+  fn from(ket: Ket) -> StateVector {
+    StateVector {
+      amplitudes: vec![ket.first, ket.second],
+    }
+  }
+}
+
+#[test]
+fn new_register_is_all_zeros() {
+  let state = StateVector::new(3);
+  assert_eq!(state.num_qubits(), 3);
+  assert!(state.amplitude(0) == COMPLEX_ONE);
+  assert!(state.amplitudes()[1..].iter().all(|a| *a == COMPLEX_ZERO));
+}
+
+#[test]
+fn ket_converts_to_single_qubit_register() {
+  assert!(StateVector::from(KET_ZERO) == StateVector::new(1));
+  assert!(StateVector::from(KET_ONE) == StateVector::from_basis_index(1, 1));
+}
+
+#[test]
+fn tensor_orders_qubits_big_endian() {
+  // |0> ⊗ |1> = |01>
+  let state = KET_ZERO.tensor(KET_ONE);
+  assert!(state == StateVector::from_basis_index(2, 0b01));
+  // |1> ⊗ |0> = |10>
+  let state = KET_ONE.tensor(KET_ZERO);
+  assert!(state == StateVector::from_basis_index(2, 0b10));
+}
+
+#[test]
+fn tensor_of_registers() {
+  let left = StateVector::from_basis_index(2, 0b10);
+  let right = StateVector::from_kets(&[KET_ONE, KET_ONE, KET_ZERO]);
+  let state = left.tensor(right);
+  assert_eq!(state.num_qubits(), 5);
+  assert!(state == StateVector::from_basis_index(5, 0b10110));
+}
+
+#[test]
+fn tensor_of_superpositions() {
+  let plus = (KET_ZERO + KET_ONE) * Complex64::from(std::f64::consts::FRAC_1_SQRT_2);
+  let state = plus.tensor(KET_ONE);
+  assert!(state.amplitude(0b01) == Complex64::from(std::f64::consts::FRAC_1_SQRT_2));
+  assert!(state.amplitude(0b11) == Complex64::from(std::f64::consts::FRAC_1_SQRT_2));
+  assert!(state.amplitude(0b00) == COMPLEX_ZERO);
+  assert!(state.amplitude(0b10) == COMPLEX_ZERO);
+  assert!(state.is_valid());
+}
+
+#[test]
+fn apply_gate_to_target() {
+  use crate::gate::*;
+  let mut state = StateVector::new(3);
+  state.apply(&PAULI_X, 1);
+  assert!(state == StateVector::from_kets(&[KET_ZERO, KET_ONE, KET_ZERO]));
+  state.apply(&PAULI_X, 0);
+  state.apply(&PAULI_X, 1);
+  assert!(state == StateVector::from_basis_index(3, 0b100));
+}
+
+#[test]
+fn apply_matches_single_ket() {
+  use crate::gate::*;
+  let gate = Gate::u3(0.4, 1.3, -0.2);
+  let mut state = StateVector::from_kets(&[KET_ONE, KET_ZERO]);
+  state.apply(&gate, 1);
+  assert!(state == KET_ONE.tensor(gate * KET_ZERO));
+  assert!(state.is_valid());
+}
+
+#[test]
+fn measure_entangled_pair() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(4);
+  let half = Complex64::from(std::f64::consts::FRAC_1_SQRT_2);
+  let bell =
+    half * StateVector::from_basis_index(2, 0b00) + half * StateVector::from_basis_index(2, 0b11);
+  let mut seen = [false; 2];
+  for _ in 0..20 {
+    let mut state = bell.clone();
+    let first = state.measure(0, &mut rng);
+    // Measuring one half of a Bell pair fixes the other half
+    assert_eq!(state.measure(1, &mut rng), first);
+    assert!(state.is_valid());
+    seen[first as usize] = true;
+  }
+  assert!(seen[0] && seen[1]);
+}
+
+#[test]
+fn measure_all_collapses_to_basis_state() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+  let mut state = StateVector::from_kets(&[KET_ONE, KET_ZERO, KET_ONE]);
+  assert_eq!(state.measure_all(&mut rng), 0b101);
+  let third = Complex64::from(1.0 / 3.0_f64.sqrt());
+  let mut state = third
+    * (StateVector::from_basis_index(2, 0)
+      + StateVector::from_basis_index(2, 1)
+      + StateVector::from_basis_index(2, 2));
+  let index = state.measure_all(&mut rng);
+  assert!(index < 3);
+  assert!(state == StateVector::from_basis_index(2, index));
+}
+
+#[test]
+fn measure_all_follows_born_rule() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(6);
+  let state = Complex64::from(0.6) * StateVector::from_basis_index(2, 1)
+    + Complex64::from(0.8) * StateVector::from_basis_index(2, 2);
+  let mut counts = [0; 4];
+  for _ in 0..10_000 {
+    counts[state.clone().measure_all(&mut rng)] += 1;
+  }
+  assert_eq!(counts[0] + counts[3], 0);
+  assert!((3400..3800).contains(&counts[1]), "{:?}", counts);
+}
+
+#[test]
+fn sample_basis_state() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+  let state = StateVector::from_kets(&[KET_ZERO, KET_ONE, KET_ONE]);
+  let counts = state.sample(100, &mut rng);
+  assert_eq!(counts.len(), 1);
+  assert_eq!(counts["011"], 100);
+}
+
+#[test]
+fn sample_follows_born_rule() {
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(8);
+  let state = Complex64::from(0.6) * StateVector::from_basis_index(2, 0b01)
+    + Complex64::from(0.8) * StateVector::from_basis_index(2, 0b10);
+  let counts = state.sample(10_000, &mut rng);
+  assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["01", "10"]);
+  assert_eq!(counts["01"] + counts["10"], 10_000);
+  assert!((3400..3800).contains(&counts["01"]), "{:?}", counts);
+  // Sampling does not disturb the state
+  assert!(state.is_valid());
+}
+
+#[test]
+fn cnot_truth_table() {
+  use crate::gate::*;
+  // CNOT with qubit 0 as control flips qubit 1 when qubit 0 is |1>
+  for (input, output) in &[(0b00, 0b00), (0b01, 0b01), (0b10, 0b11), (0b11, 0b10)] {
+    let mut state = StateVector::from_basis_index(2, *input);
+    state.apply_controlled(&PAULI_X, &[0], 1);
+    assert!(state == StateVector::from_basis_index(2, *output));
+  }
+}
+
+#[test]
+fn toffoli_truth_table() {
+  use crate::gate::*;
+  for input in 0..8 {
+    let mut state = StateVector::from_basis_index(3, input);
+    state.apply_controlled(&PAULI_X, &[0, 2], 1);
+    let output = if input & 0b101 == 0b101 {
+      input ^ 0b010
+    } else {
+      input
+    };
+    assert!(state == StateVector::from_basis_index(3, output));
+  }
+}
+
+#[test]
+fn controlled_z_is_symmetric() {
+  use crate::gate::*;
+  let plus = (KET_ZERO + KET_ONE) * Complex64::from(std::f64::consts::FRAC_1_SQRT_2);
+  let mut a = StateVector::from_kets(&[plus, plus]);
+  let mut b = a.clone();
+  a.apply_controlled(&PAULI_Z, &[0], 1);
+  b.apply_controlled(&PAULI_Z, &[1], 0);
+  assert!(a == b);
+  assert!(a.amplitude(0b11) == -a.amplitude(0b00));
+}
+
+#[test]
+fn controlled_y_phases() {
+  use crate::gate::*;
+  let mut state = StateVector::from_basis_index(2, 0b10);
+  state.apply_controlled(&PAULI_Y, &[0], 1);
+  assert!(state == StateVector::from_basis_index(2, 0b11) * Complex64::new(0.0, 1.0));
+}
+
+#[test]
+fn multi_controlled_rotation() {
+  use crate::gate::*;
+  let gate = Gate::ry(0.7);
+  for controls in 0..8 {
+    let mut state = StateVector::from_basis_index(4, controls << 1);
+    state.apply_controlled(&gate, &[0, 1, 2], 3);
+    let expected = if controls == 0b111 {
+      StateVector::from_basis_index(3, controls).tensor(gate * KET_ZERO)
+    } else {
+      StateVector::from_basis_index(4, controls << 1)
+    };
+    assert!(state == expected);
+  }
+}
+
+#[test]
+fn swap_and_fredkin() {
+  let mut state = StateVector::from_kets(&[KET_ONE, KET_ZERO, KET_ONE]);
+  state.swap(0, 1);
+  assert!(state == StateVector::from_kets(&[KET_ZERO, KET_ONE, KET_ONE]));
+  // Control qubit 0 is now |0>, so nothing happens
+  state.apply_controlled_swap(&[0], 1, 2);
+  assert!(state == StateVector::from_kets(&[KET_ZERO, KET_ONE, KET_ONE]));
+  // Control qubit 2 is |1>, so qubits 0 and 1 trade places
+  state.apply_controlled_swap(&[2], 0, 1);
+  assert!(state == StateVector::from_kets(&[KET_ONE, KET_ZERO, KET_ONE]));
+}
+
+#[test]
+fn iswap_phases() {
+  let i = Complex64::new(0.0, 1.0);
+  let mut state = StateVector::from_basis_index(2, 0b01);
+  state.iswap(0, 1);
+  assert!(state == StateVector::from_basis_index(2, 0b10) * i);
+  let mut state = StateVector::from_basis_index(2, 0b11);
+  state.iswap(1, 0);
+  assert!(state == StateVector::from_basis_index(2, 0b11));
+}
+
+#[test]
+fn apply_unitary_matches_gates() {
+  // CNOT with the control on qubit 2 and the target on qubit 0
+  let (o, l) = (COMPLEX_ZERO, COMPLEX_ONE);
+  let cnot = vec![
+    vec![l, o, o, o],
+    vec![o, l, o, o],
+    vec![o, o, o, l],
+    vec![o, o, l, o],
+  ];
+  for index in 0..8 {
+    let mut expected = StateVector::from_basis_index(3, index);
+    expected.apply_controlled(&crate::gate::PAULI_X, &[2], 0);
+    let mut state = StateVector::from_basis_index(3, index);
+    state.apply_unitary(&cnot, &[2, 0]);
+    assert!(state == expected, "{}", index);
+  }
+}
+
+#[test]
+#[should_panic]
+fn control_same_as_target() {
+  StateVector::new(2).apply_controlled(&crate::gate::PAULI_X, &[1], 1);
+}
+
+#[test]
+fn fidelity_of_registers() {
+  use crate::gate::*;
+  let mut bell = StateVector::new(2);
+  bell.apply(&HADAMARD, 0);
+  bell.apply_controlled(&PAULI_X, &[0], 1);
+  assert!((bell.norm() - 1.0).abs() < 1e-12);
+  assert!((bell.inner(&StateVector::from_basis_index(2, 3)).re - 0.5f64.sqrt()).abs() < 1e-12);
+  assert!((bell.fidelity(&StateVector::new(2)) - 0.5).abs() < 1e-12);
+  let mut other = bell.clone();
+  other.apply(&PAULI_Z, 1);
+  assert!(bell.fidelity(&other).abs() < 1e-12);
+  assert!((bell.trace_distance(&other) - 1.0).abs() < 1e-12);
+  // A small rotation moves the state a little bit
+  let mut rotated = bell.clone();
+  rotated.apply(&Gate::ry(0.2), 1);
+  assert!((bell.fidelity(&rotated) - 0.1f64.cos().powi(2)).abs() < 1e-12);
+  assert!((bell.trace_distance(&rotated) - 0.1f64.sin()).abs() < 1e-12);
+}
+
+// Equality checking works just like it does for Ket
+impl PartialEq for StateVector {
+  fn eq(&self, other: &Self) -> bool {
+    self.amplitudes == other.amplitudes
+  }
+}
+impl Eq for StateVector {}
+
+// Approximate equality works on references, so comparing does not need a clone:
+// `approx_eq!(&StateVector, &a, &b, epsilon = 1e-12)`
+impl<'a> ApproxEq for &'a StateVector {
+  type Margin = ComplexMargin;
+
+  fn approx_eq<M: Into<ComplexMargin>>(self, other: &'a StateVector, margin: M) -> bool {
+    margin
+      .into()
+      .all_approx_eq(&self.amplitudes, &other.amplitudes)
+  }
+}
+
+#[test]
+fn basis_states_not_equal() {
+  assert!(StateVector::from_basis_index(2, 1) != StateVector::from_basis_index(2, 2));
+}
+
+#[test]
+fn approximately_equal_state_vectors() {
+  use crate::gate::*;
+  let mut state = StateVector::new(3);
+  state.apply(&HADAMARD, 0);
+  state.apply_controlled(&PAULI_X, &[0], 2);
+  let mut rotated = StateVector::new(3);
+  rotated.apply(&Gate::ry(std::f64::consts::FRAC_PI_2), 0);
+  rotated.apply_controlled(&PAULI_X, &[0], 2);
+  assert!(state != rotated);
+  assert!(approx_eq!(&StateVector, &state, &rotated, epsilon = 1e-12));
+  // Rz(π) is Z, up to a global phase of -i
+  let mut z = state.clone();
+  z.apply(&PAULI_Z, 1);
+  let mut rz = state.clone();
+  rz.apply(&Gate::rz(std::f64::consts::PI), 1);
+  assert!(!approx_eq!(&StateVector, &z, &rz, epsilon = 1e-12));
+  assert!(approx_eq!(
+    &StateVector,
+    &z,
+    &rz,
+    epsilon = 1e-12,
+    up_to_global_phase = true
+  ));
+  assert!(!approx_eq!(
+    &StateVector,
+    &state,
+    &StateVector::new(2),
+    epsilon = 1.0
+  ));
+}
+
+// Adding two registers together, amplitude by amplitude
+use std::ops::Add;
+impl Add for StateVector {
+  type Output = Self;
+
+  fn add(self, other: Self) -> Self {
+    assert_eq!(
+      self.amplitudes.len(),
+      other.amplitudes.len(),
+      "cannot add state vectors of different sizes"
+    );
+    StateVector {
+      amplitudes: self
+        .amplitudes
+        .iter()
+        .zip(other.amplitudes.iter())
+        .map(|(a, b)| a + b)
+        .collect(),
+    }
+  }
+}
+
+#[test]
+fn state_vector_add() {
+  let sum = StateVector::from_basis_index(2, 0) + StateVector::from_basis_index(2, 3);
+  assert!(
+    sum == StateVector::from_amplitudes(vec![COMPLEX_ONE, COMPLEX_ZERO, COMPLEX_ZERO, COMPLEX_ONE])
+  );
+}
+
+// Scalar multiplication, from both sides
+use std::ops::Mul;
+impl Mul<Complex64> for StateVector {
+  type Output = StateVector;
+
+  fn mul(self, rhs: Complex64) -> StateVector {
+    StateVector {
+      amplitudes: self.amplitudes.iter().map(|a| a * rhs).collect(),
+    }
+  }
+}
+
+impl Mul<StateVector> for Complex64 {
+  type Output = StateVector;
+
+  fn mul(self, rhs: StateVector) -> StateVector {
+    rhs * self
+  }
+}
+
+#[test]
+fn state_vector_arithmetic() {
+  let a = Complex64::from(0.6) * StateVector::from_basis_index(2, 0);
+  let b = StateVector::from_basis_index(2, 3) * Complex64::from(0.8);
+  let c = a + b;
+  assert!(c.amplitude(0) == Complex64::from(0.6));
+  assert!(c.amplitude(3) == Complex64::from(0.8));
+  assert!(c.is_valid());
+}
+
+// The same validity constraint as for a single Ket:
+// the squares of the amplitudes must sum up to 1, within the same tolerance of 1e-12
+impl ValidQuantumState for StateVector {
+  fn is_valid(&self) -> bool {
+    let result: f64 = self.amplitudes.iter().map(|a| a.norm_sqr()).sum();
+    approx_eq!(f64, result, 1.0, epsilon = 1e-12, ulps = 2)
+  }
+}
+
+#[test]
+fn basis_states_valid() {
+  for index in 0..8 {
+    assert!(StateVector::from_basis_index(3, index).is_valid());
+  }
+}
+
+#[test]
+fn state_vector_invalid() {
+  assert!(!(StateVector::new(2) + StateVector::from_basis_index(2, 1)).is_valid());
+}
+
+// Unitary gates keep states valid, but their rounding errors add up to more than a few ulps
+#[test]
+fn gates_keep_states_valid() {
+  use crate::gate::*;
+  let mut state = StateVector::new(4);
+  for step in 0..50 {
+    let angle = 0.37 * step as f64;
+    state.apply(&Gate::u3(angle, 1.3 * angle, -0.7), step % 4);
+    state.apply_controlled(&Gate::ry(angle), &[step % 4], (step + 1) % 4);
+    assert!(state.is_valid(), "invalid after step {}", step);
+  }
+}
+
+#[test]
+fn measure_register_in_other_bases() {
+  use crate::gate::*;
+  use rand::SeedableRng;
+  let mut rng = rand::rngs::StdRng::seed_from_u64(35);
+  // In a Bell state, measuring qubit 0 in the X basis leaves qubit 1 in the same X state
+  let mut bell = StateVector::new(2);
+  bell.apply(&HADAMARD, 0);
+  bell.apply_controlled(&PAULI_X, &[0], 1);
+  let outcomes = bell.measurement_outcomes(0, &Basis::X);
+  for (one, (probability, state)) in outcomes.iter().enumerate() {
+    assert!((probability - 0.5).abs() < 1e-12);
+    let ket = if one == 1 { KET_MINUS } else { KET_PLUS };
+    let expected = ket.tensor(ket);
+    assert!(approx_eq!(&StateVector, state, &expected, epsilon = 1e-12));
+  }
+  for _ in 0..10 {
+    let mut state = bell.clone();
+    let first = state.measure_in(0, &Basis::X, &mut rng);
+    assert_eq!(state.measure_in(1, &Basis::X, &mut rng), first);
+    // In the Y basis the outcomes of a Bell state are opposite
+    let mut state = bell.clone();
+    let first = state.measure_in(0, &Basis::Y, &mut rng);
+    assert_eq!(state.measure_in(1, &Basis::Y, &mut rng), !first);
+  }
+  // The Z basis is the computational basis
+  let [(zero, _), (one, state)] = bell.measurement_outcomes(1, &Basis::Z);
+  assert!((zero - 0.5).abs() < 1e-12 && (one - 0.5).abs() < 1e-12);
+  assert!(approx_eq!(
+    &StateVector,
+    &state,
+    &StateVector::from_basis_index(2, 3),
+    epsilon = 1e-12
+  ));
+  let [(_, impossible), (certain, _)] =
+    StateVector::from_basis_index(1, 1).measurement_outcomes(0, &Basis::Z);
+  assert!((certain - 1.0).abs() < 1e-12);
+  assert!(impossible.amplitudes().iter().all(|a| *a == COMPLEX_ZERO));
+}
@@ -0,0 +1,145 @@
+use crate::circuit::Circuit;
+use crate::density_matrix::DensityMatrix;
+use crate::noise::NoiseModel;
+use crate::state_vector::StateVector;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::BTreeMap;
+
+/// Simulates noisy circuits with Monte Carlo quantum trajectories.
+///
+/// A density matrix of n qubits has 4^n elements, which runs out of memory long before a
+/// state vector does. Instead, every trajectory runs the circuit on a state vector, and each
+/// noise channel picks one of its Kraus operators at random along the way. Averaged over
+/// many trajectories, the results converge to those of the density matrix.
+///
+/// The random numbers all come from a generator seeded with `seed`, so the same simulator
+/// gives the same results for the same circuit every time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Trajectories {
+  count: usize,
+  seed: u64,
+}
+
+impl Trajectories {
+  /// A simulator that averages over `count` trajectories.
+  pub fn new(count: usize, seed: u64) -> Trajectories {
+    assert!(count > 0, "there must be at least one trajectory");
+    Trajectories { count, seed }
+  }
+
+  pub fn count(&self) -> usize {
+    self.count
+  }
+
+  pub fn seed(&self) -> u64 {
+    self.seed
+  }
+
+  /// Runs every trajectory, and counts how many times each final classical register was
+  /// seen, like `Circuit::sample_noisy` with one shot per trajectory.
+  pub fn sample(&self, circuit: &Circuit, noise: &NoiseModel) -> BTreeMap<String, usize> {
+    circuit.sample_noisy(self.count, noise, &mut self.rng())
+  }
+
+  /// The probability of each basis state at the end of the circuit, averaged over the
+  /// trajectories. Converges to the diagonal of the density matrix.
+  pub fn probabilities(&self, circuit: &Circuit, noise: &NoiseModel) -> Vec<f64> {
+    let mut sum = vec![0.0; 1 << circuit.num_qubits()];
+    for state in self.final_states(circuit, noise) {
+      for (total, probability) in sum.iter_mut().zip(state.probabilities()) {
+        *total += probability;
+      }
+    }
+    sum.iter().map(|total| total / self.count as f64).collect()
+  }
+
+  /// The average of |ψ><ψ| over the final states of the trajectories. This needs as much
+  /// memory as the density matrix itself, so it is mostly useful for checking results.
+  pub fn density_matrix(&self, circuit: &Circuit, noise: &NoiseModel) -> DensityMatrix {
+    let weight = 1.0 / self.count as f64;
+    self
+      .final_states(circuit, noise)
+      .map(|state| DensityMatrix::from_state(state) * weight)
+      .reduce(|sum, state| sum + state)
+      .unwrap()
+  }
+
+  fn rng(&self) -> StdRng {
+    StdRng::seed_from_u64(self.seed)
+  }
+
+  // The state vectors at the end of every trajectory, one at a time
+  fn final_states<'a>(
+    &self,
+    circuit: &'a Circuit,
+    noise: &'a NoiseModel,
+  ) -> impl Iterator<Item = StateVector> + 'a {
+    let mut rng = self.rng();
+    (0..self.count).map(move |_| circuit.run_noisy(noise, &mut rng).state)
+  }
+}
+
+#[cfg(test)]
+fn noisy_test_circuit() -> (Circuit, NoiseModel) {
+  use crate::channel::Channel;
+  let mut circuit = Circuit::new(3, 0);
+  circuit.h(0).cx(0, 1).ry(0.8, 2).cx(1, 2).rz(0.3, 0).h(1);
+  let mut noise = NoiseModel::new();
+  noise
+    .add_gate_error("cx", Channel::depolarizing(0.1))
+    .add_gate_error("h", Channel::amplitude_damping(0.2))
+    .add_gate_error_on("ry", 2, Channel::phase_damping(0.3));
+  (circuit, noise)
+}
+
+#[test]
+fn trajectories_converge_to_density_matrix() {
+  let (circuit, noise) = noisy_test_circuit();
+  let mut rng = StdRng::seed_from_u64(0);
+  let exact = circuit
+    .run_on(DensityMatrix::new(3), &noise, &mut rng)
+    .unwrap()
+    .state;
+  let trajectories = Trajectories::new(4000, 19);
+  for (estimate, expected) in trajectories
+    .probabilities(&circuit, &noise)
+    .iter()
+    .zip(exact.probabilities())
+  {
+    assert!(
+      (estimate - expected).abs() < 0.02,
+      "{} {}",
+      estimate,
+      expected
+    );
+  }
+  let estimate = trajectories.density_matrix(&circuit, &noise);
+  for (row, expected_row) in estimate.rows().iter().zip(exact.rows()) {
+    for (x, y) in row.iter().zip(expected_row) {
+      assert!((x - y).norm() < 0.03, "{} {}", x, y);
+    }
+  }
+  assert!((estimate.purity() - exact.purity()).abs() < 0.03);
+}
+
+#[test]
+fn trajectories_are_reproducible() {
+  let (mut circuit, noise) = noisy_test_circuit();
+  circuit.measure_all();
+  let first = Trajectories::new(200, 20).sample(&circuit, &noise);
+  assert_eq!(Trajectories::new(200, 20).sample(&circuit, &noise), first);
+  assert_eq!(first.values().sum::<usize>(), 200);
+  assert_ne!(Trajectories::new(200, 21).sample(&circuit, &noise), first);
+}
+
+#[test]
+fn without_noise_every_trajectory_is_the_same() {
+  let (circuit, _) = noisy_test_circuit();
+  let mut rng = StdRng::seed_from_u64(0);
+  let ideal = circuit.run(&mut rng).state.probabilities();
+  let probabilities = Trajectories::new(10, 22).probabilities(&circuit, &NoiseModel::new());
+  for (a, b) in probabilities.iter().zip(ideal) {
+    assert!((a - b).abs() < 1e-12);
+  }
+}